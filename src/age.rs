@@ -0,0 +1,482 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::credentials::Credentials;
+use crate::crypto_error::CryptoError;
+use crate::file_cipher::write_new_file;
+use crate::header::{FLAG_IDENTITY, TAG_SIZE};
+use crate::progress;
+use crate::recipients::{ephemeral_wrapping_key, static_wrapping_key};
+use crate::secret::Secret;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use x25519_dalek::PublicKey;
+use zeroize::Zeroizing;
+
+pub const FILENAME_EXTENSION : &str = "age";
+
+const VERSION_LINE : &[u8] = b"age-encryption.org/v1\n";
+const STANZA_PREFIX : &[u8] = b"-> ";
+const MAC_PREFIX : &[u8] = b"---";
+const X25519_STANZA : &str = "X25519";
+const X25519_INFO : &[u8] = b"age-encryption.org/v1/X25519";
+const SCRYPT_STANZA : &str = "scrypt";
+const SCRYPT_LABEL : &[u8] = b"age-encryption.org/v1/scrypt";
+const SCRYPT_SALT_SIZE : usize = 16;
+/// age's default, which takes about a second.
+const SCRYPT_WORK_FACTOR : u8 = 18;
+/// Anything above needs more than a gigabyte of memory.
+const MAXIMUM_SCRYPT_WORK_FACTOR : u8 = 20;
+
+const FILE_KEY_SIZE : usize = 16;
+const WRAPPED_FILE_KEY_SIZE : usize = FILE_KEY_SIZE + TAG_SIZE;
+const MAC_SIZE : usize = 32;
+const PAYLOAD_NONCE_SIZE : usize = 16;
+const CHUNK_SIZE : usize = 64 * 1024;
+/// Stanza bodies are wrapped after this many base64 characters.
+const COLUMNS : usize = 64;
+
+enum MessageError {
+    Malformed,
+    Unsupported,
+    NoMatchingStanza,
+    AuthenticationFailed,
+}
+
+struct Stanza {
+    kind : String,
+    arguments : Vec<String>,
+    body : Vec<u8>,
+}
+
+struct AgeHeader<'a> {
+    stanzas : Vec<Stanza>,
+    /// Everything up to and including the `---` the MAC is computed over.
+    authenticated_data : &'a [u8],
+    mac : Vec<u8>,
+    payload : &'a [u8],
+}
+
+/// Encrypts `path` into `path.age`, which age can decrypt. Recipients get an
+/// X25519 stanza each, otherwise the password is used with scrypt.
+pub fn encrypt_age_file(path : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    encrypt_age_file_to(path, &(path.to_string() + "." + FILENAME_EXTENSION), credentials)
+}
+
+/// Encrypts `path` into a new age file at `destination_path`.
+pub fn encrypt_age_file_to(path : &str, destination_path : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    let plaintext : Zeroizing<Vec<u8>> = Zeroizing::new(fs::read(path)
+        .map_err(|_| CryptoError::FileLoading(path.to_string()))?);
+    let message : Vec<u8> = encrypt_message(&plaintext, credentials, SCRYPT_WORK_FACTOR)
+        .ok_or_else(|| CryptoError::CredentialsMismatch(path.to_string(), credentials.flags()))?;
+    write_new_file(destination_path, &message, path)
+}
+
+/// Decrypts an age file ending in `.age` with the identity or the password in `credentials`.
+pub fn decrypt_age_file(path : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    if !is_age_path(path) {
+        return Err(CryptoError::FileLoading(path.to_string()));
+    }
+    decrypt_age_file_to(path, &path[0..path.len() - FILENAME_EXTENSION.len() - 1], credentials)
+}
+
+/// Decrypts the age file at `path` into a new file at `destination_path`.
+pub fn decrypt_age_file_to(path : &str, destination_path : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    let message : Vec<u8> = fs::read(path).map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    let plaintext : Zeroizing<Vec<u8>> = decrypt_message(&message, credentials).map_err(|error| match error {
+        MessageError::Malformed => CryptoError::InvalidHeader(path.to_string()),
+        MessageError::Unsupported => CryptoError::UnsupportedParameters(path.to_string()),
+        MessageError::NoMatchingStanza if credentials.identity().is_some() =>
+            CryptoError::NoMatchingRecipient(path.to_string()),
+        MessageError::NoMatchingStanza => CryptoError::CredentialsMismatch(path.to_string(), FLAG_IDENTITY),
+        MessageError::AuthenticationFailed => CryptoError::AuthenticationFailed(path.to_string()),
+    })?;
+    progress::advance(message.len().saturating_sub(plaintext.len()));
+    write_new_file(destination_path, &plaintext, path)
+}
+
+pub fn is_age_path(path : &str) -> bool {
+    Path::new(path).extension().and_then(|extension| extension.to_str()) == Some(FILENAME_EXTENSION)
+}
+
+/// Recognizes an age file by its version line.
+pub fn is_age_message(data : &[u8]) -> bool {
+    data.starts_with(VERSION_LINE)
+}
+
+fn encrypt_message(plaintext : &[u8], credentials : &Credentials, work_factor : u8) -> Option<Vec<u8>> {
+    let file_key : Secret = random_secret(FILE_KEY_SIZE);
+    let stanzas : Vec<Stanza> = if !credentials.recipients().is_empty() {
+        credentials.recipients().iter()
+            .map(|recipient| wrap_for_recipient(&file_key, recipient))
+            .collect::<Option<_>>()?
+    } else {
+        vec![wrap_with_password(&file_key, credentials.password()?, work_factor)?]
+    };
+    let mut message : Vec<u8> = write_header(&stanzas, &file_key);
+    message.extend_from_slice(&encrypt_payload(plaintext, &file_key)?);
+    Some(message)
+}
+
+fn decrypt_message(message : &[u8], credentials : &Credentials) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    let header : AgeHeader = parse_header(message)?;
+    let is_scrypt = |stanza : &Stanza| stanza.kind == SCRYPT_STANZA;
+    // A password must not be mixed with other recipients, or the holder of
+    // an identity could pass off a file as password protected.
+    if header.stanzas.iter().any(is_scrypt) && header.stanzas.len() != 1 {
+        return Err(MessageError::Malformed);
+    }
+    let mut file_key : Option<Secret> = None;
+    for stanza in &header.stanzas {
+        file_key = match (stanza.kind.as_str(), credentials.identity(), credentials.password()) {
+            (X25519_STANZA, Some(identity), _) => unwrap_for_identity(stanza, identity)?,
+            (SCRYPT_STANZA, None, Some(password)) => unwrap_with_password(stanza, password)?,
+            _ => None,
+        };
+        if file_key.is_some() {
+            break;
+        }
+    }
+    let file_key : Secret = file_key.ok_or(MessageError::NoMatchingStanza)?;
+    header_mac(&file_key, header.authenticated_data).verify_slice(&header.mac)
+        .map_err(|_| MessageError::AuthenticationFailed)?;
+    decrypt_payload(header.payload, &file_key)
+}
+
+fn wrap_for_recipient(file_key : &Secret, recipient : &PublicKey) -> Option<Stanza> {
+    let (ephemeral_public_key, wrapping_key) = ephemeral_wrapping_key(recipient, X25519_INFO)?;
+    Some(Stanza{
+        kind : X25519_STANZA.to_string(),
+        arguments : vec![STANDARD_NO_PAD.encode(ephemeral_public_key.as_bytes())],
+        body : seal_file_key(file_key, &wrapping_key)?,
+    })
+}
+
+/// Returns `None` if the stanza was made for another identity.
+fn unwrap_for_identity(stanza : &Stanza, identity : &Secret) -> Result<Option<Secret>, MessageError> {
+    let [ephemeral_public_key] = stanza.arguments.as_slice() else {
+        return Err(MessageError::Malformed);
+    };
+    let ephemeral_public_key : [u8; 32] = decode_base64(ephemeral_public_key)?.try_into()
+        .map_err(|_| MessageError::Malformed)?;
+    if stanza.body.len() != WRAPPED_FILE_KEY_SIZE {
+        return Err(MessageError::Malformed);
+    }
+    let wrapping_key : Secret = static_wrapping_key(identity, &PublicKey::from(ephemeral_public_key), X25519_INFO)
+        .ok_or(MessageError::Malformed)?;
+    Ok(open_file_key(&stanza.body, &wrapping_key))
+}
+
+fn wrap_with_password(file_key : &Secret, password : &Secret, work_factor : u8) -> Option<Stanza> {
+    let salt : Secret = random_secret(SCRYPT_SALT_SIZE);
+    Some(Stanza{
+        kind : SCRYPT_STANZA.to_string(),
+        arguments : vec![STANDARD_NO_PAD.encode(salt.expose()), work_factor.to_string()],
+        body : seal_file_key(file_key, &scrypt_key(password, salt.expose(), work_factor)?)?,
+    })
+}
+
+/// A file with a password has no other stanzas, so a wrong password is an error.
+fn unwrap_with_password(stanza : &Stanza, password : &Secret) -> Result<Option<Secret>, MessageError> {
+    let [salt, work_factor] = stanza.arguments.as_slice() else {
+        return Err(MessageError::Malformed);
+    };
+    let salt : Vec<u8> = decode_base64(salt)?;
+    if salt.len() != SCRYPT_SALT_SIZE || stanza.body.len() != WRAPPED_FILE_KEY_SIZE
+        || work_factor.starts_with('0') || !work_factor.bytes().all(|digit| digit.is_ascii_digit()) {
+        return Err(MessageError::Malformed);
+    }
+    let work_factor : u8 = work_factor.parse().map_err(|_| MessageError::Unsupported)?;
+    if work_factor > MAXIMUM_SCRYPT_WORK_FACTOR {
+        return Err(MessageError::Unsupported);
+    }
+    let key : Secret = scrypt_key(password, &salt, work_factor).ok_or(MessageError::Malformed)?;
+    open_file_key(&stanza.body, &key).map(Some).ok_or(MessageError::AuthenticationFailed)
+}
+
+fn scrypt_key(password : &Secret, salt : &[u8], work_factor : u8) -> Option<Secret> {
+    let mut labeled_salt : Vec<u8> = SCRYPT_LABEL.to_vec();
+    labeled_salt.extend_from_slice(salt);
+    let parameters = scrypt::Params::new(work_factor, 8, 1, 32).ok()?;
+    let mut key : Vec<u8> = vec![0x00; 32];
+    scrypt::scrypt(password.expose(), &labeled_salt, &parameters, &mut key).ok()?;
+    Some(Secret::new(key))
+}
+
+/// Every wrapping key is used exactly once, so age uses a zero nonce.
+fn seal_file_key(file_key : &Secret, wrapping_key : &Secret) -> Option<Vec<u8>> {
+    let cipher : ChaCha20Poly1305 = ChaCha20Poly1305::new_from_slice(wrapping_key.expose()).ok()?;
+    let mut body : Vec<u8> = file_key.expose().to_vec();
+    let tag : Tag = cipher.encrypt_in_place_detached(&Nonce::default(), &[], &mut body).ok()?;
+    body.extend_from_slice(&tag);
+    Some(body)
+}
+
+fn open_file_key(body : &[u8], wrapping_key : &Secret) -> Option<Secret> {
+    let cipher : ChaCha20Poly1305 = ChaCha20Poly1305::new_from_slice(wrapping_key.expose()).ok()?;
+    let (wrapped_file_key, tag) = body.split_at(FILE_KEY_SIZE);
+    let mut file_key : Zeroizing<Vec<u8>> = Zeroizing::new(wrapped_file_key.to_vec());
+    cipher.decrypt_in_place_detached(&Nonce::default(), &[], &mut file_key, Tag::from_slice(tag)).ok()?;
+    Some(Secret::new(file_key.to_vec()))
+}
+
+fn write_header(stanzas : &[Stanza], file_key : &Secret) -> Vec<u8> {
+    let mut header : Vec<u8> = VERSION_LINE.to_vec();
+    for stanza in stanzas {
+        header.extend_from_slice(STANZA_PREFIX);
+        header.extend_from_slice(stanza.kind.as_bytes());
+        for argument in &stanza.arguments {
+            header.push(b' ');
+            header.extend_from_slice(argument.as_bytes());
+        }
+        header.push(b'\n');
+        let body : String = STANDARD_NO_PAD.encode(&stanza.body);
+        for line in body.as_bytes().chunks(COLUMNS) {
+            header.extend_from_slice(line);
+            header.push(b'\n');
+        }
+        // A full last line has to be followed by an empty one.
+        if body.len().is_multiple_of(COLUMNS) {
+            header.push(b'\n');
+        }
+    }
+    header.extend_from_slice(MAC_PREFIX);
+    let mac = header_mac(file_key, &header).finalize().into_bytes();
+    header.push(b' ');
+    header.extend_from_slice(STANDARD_NO_PAD.encode(mac).as_bytes());
+    header.push(b'\n');
+    header
+}
+
+fn header_mac(file_key : &Secret, authenticated_data : &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(hkdf(file_key, &[], b"header").expose())
+        .expect("HMAC accepts keys of any length");
+    mac.update(authenticated_data);
+    mac
+}
+
+fn parse_header(message : &[u8]) -> Result<AgeHeader<'_>, MessageError> {
+    if !message.starts_with(VERSION_LINE) {
+        return Err(MessageError::Unsupported);
+    }
+    let mut position : usize = VERSION_LINE.len();
+    let mut stanzas : Vec<Stanza> = Vec::new();
+    loop {
+        let line : &[u8] = next_line(message, &mut position)?;
+        if let Some(arguments) = line.strip_prefix(STANZA_PREFIX) {
+            let arguments : &str = std::str::from_utf8(arguments).map_err(|_| MessageError::Malformed)?;
+            let mut arguments : Vec<String> = arguments.split(' ').map(str::to_string).collect();
+            if arguments.iter().any(|argument| argument.is_empty() || !argument.bytes().all(|byte| byte.is_ascii_graphic())) {
+                return Err(MessageError::Malformed);
+            }
+            let kind : String = arguments.remove(0);
+            let mut body : String = String::new();
+            loop {
+                let line : &[u8] = next_line(message, &mut position)?;
+                if line.len() > COLUMNS {
+                    return Err(MessageError::Malformed);
+                }
+                body.push_str(std::str::from_utf8(line).map_err(|_| MessageError::Malformed)?);
+                if line.len() < COLUMNS {
+                    break;
+                }
+            }
+            stanzas.push(Stanza{
+                kind,
+                arguments,
+                body : decode_base64(&body)?,
+            });
+        } else if line.starts_with(MAC_PREFIX) {
+            let line_start : usize = position - line.len() - 1;
+            let mac : &[u8] = line.strip_prefix(b"--- ").ok_or(MessageError::Malformed)?;
+            let mac : Vec<u8> = decode_base64(std::str::from_utf8(mac).map_err(|_| MessageError::Malformed)?)?;
+            if stanzas.is_empty() || mac.len() != MAC_SIZE {
+                return Err(MessageError::Malformed);
+            }
+            return Ok(AgeHeader{
+                stanzas,
+                authenticated_data : &message[0..line_start + MAC_PREFIX.len()],
+                mac,
+                payload : &message[position..],
+            });
+        } else {
+            return Err(MessageError::Malformed);
+        }
+    }
+}
+
+fn next_line<'a>(message : &'a [u8], position : &mut usize) -> Result<&'a [u8], MessageError> {
+    let rest : &[u8] = &message[*position..];
+    let length : usize = rest.iter().position(|byte| *byte == b'\n').ok_or(MessageError::Malformed)?;
+    *position += length + 1;
+    Ok(&rest[0..length])
+}
+
+fn decode_base64(text : &str) -> Result<Vec<u8>, MessageError> {
+    STANDARD_NO_PAD.decode(text).map_err(|_| MessageError::Malformed)
+}
+
+/// Encrypts the payload with the STREAM construction: chunks of 64 KiB, each
+/// sealed under a nonce made of its index and a flag marking the last one.
+fn encrypt_payload(plaintext : &[u8], file_key : &Secret) -> Option<Vec<u8>> {
+    let nonce : Secret = random_secret(PAYLOAD_NONCE_SIZE);
+    let cipher : ChaCha20Poly1305 = ChaCha20Poly1305::new_from_slice(hkdf(file_key, nonce.expose(), b"payload").expose()).ok()?;
+    let chunk_count : usize = plaintext.len().div_ceil(CHUNK_SIZE).max(1);
+    let mut payload : Vec<u8> = Vec::with_capacity(PAYLOAD_NONCE_SIZE + plaintext.len() + chunk_count * TAG_SIZE);
+    payload.extend_from_slice(nonce.expose());
+    for index in 0..chunk_count {
+        let start : usize = payload.len();
+        payload.extend_from_slice(&plaintext[index * CHUNK_SIZE..plaintext.len().min((index + 1) * CHUNK_SIZE)]);
+        let tag : Tag = cipher.encrypt_in_place_detached(&stream_nonce(index, index + 1 == chunk_count), &[],
+            &mut payload[start..]).ok()?;
+        payload.extend_from_slice(&tag);
+        progress::advance(payload.len() - start - TAG_SIZE);
+    }
+    Some(payload)
+}
+
+fn decrypt_payload(payload : &[u8], file_key : &Secret) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    if payload.len() < PAYLOAD_NONCE_SIZE + TAG_SIZE {
+        return Err(MessageError::Malformed);
+    }
+    let (nonce, chunks) = payload.split_at(PAYLOAD_NONCE_SIZE);
+    let cipher : ChaCha20Poly1305 = ChaCha20Poly1305::new_from_slice(hkdf(file_key, nonce, b"payload").expose())
+        .map_err(|_| MessageError::Malformed)?;
+    let chunk_count : usize = chunks.len().div_ceil(CHUNK_SIZE + TAG_SIZE);
+    let mut plaintext : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(chunks.len()));
+    for (index, chunk) in chunks.chunks(CHUNK_SIZE + TAG_SIZE).enumerate() {
+        // Only the sole chunk of an empty file may be empty.
+        if chunk.len() < TAG_SIZE || (chunk.len() == TAG_SIZE && index > 0) {
+            return Err(MessageError::Malformed);
+        }
+        let (ciphertext, tag) = chunk.split_at(chunk.len() - TAG_SIZE);
+        let start : usize = plaintext.len();
+        plaintext.extend_from_slice(ciphertext);
+        cipher.decrypt_in_place_detached(&stream_nonce(index, index + 1 == chunk_count), &[],
+            &mut plaintext[start..], Tag::from_slice(tag))
+            .map_err(|_| MessageError::AuthenticationFailed)?;
+        progress::advance(ciphertext.len());
+    }
+    Ok(plaintext)
+}
+
+fn stream_nonce(index : usize, last : bool) -> Nonce {
+    let mut nonce : Nonce = Nonce::default();
+    nonce[3..11].copy_from_slice(&(index as u64).to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+fn hkdf(input_key : &Secret, salt : &[u8], info : &[u8]) -> Secret {
+    let mut key : Vec<u8> = vec![0x00; 32];
+    Hkdf::<Sha256>::new(Some(salt), input_key.expose())
+        .expand(info, &mut key)
+        .expect("32 bytes are a valid HKDF-SHA256 output length");
+    Secret::new(key)
+}
+
+fn random_secret(length : usize) -> Secret {
+    let mut bytes : Vec<u8> = vec![0x00; length];
+    OsRng.fill_bytes(&mut bytes);
+    Secret::new(bytes)
+}
+
+#[cfg(test)]
+mod age_tests {
+    use super::*;
+    use crate::recipients::{generate_identity, load_identity, parse_recipient};
+
+    fn password(password : &str) -> Credentials {
+        Credentials::from(Secret::from(password.to_string()))
+    }
+
+    #[test]
+    fn test_password_round_trip() {
+        let plaintext : Vec<u8> = (0..2 * CHUNK_SIZE).map(|i| i as u8).collect();
+        let message : Vec<u8> = encrypt_message(&plaintext, &password("GNAMPF"), 10).unwrap();
+        assert!(message.starts_with(b"age-encryption.org/v1\n-> scrypt "));
+        assert_eq!(*decrypt_message(&message, &password("GNAMPF")).ok().unwrap(), plaintext);
+        assert!(matches!(decrypt_message(&message, &password("wrong")), Err(MessageError::AuthenticationFailed)));
+
+        let mut tampered : Vec<u8> = message.clone();
+        let last : usize = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert!(matches!(decrypt_message(&tampered, &password("GNAMPF")), Err(MessageError::AuthenticationFailed)));
+        // Cutting off the last chunk leaves a chunk that was not sealed as the last one.
+        let truncated : &[u8] = &message[0..message.len() - CHUNK_SIZE - TAG_SIZE];
+        assert!(matches!(decrypt_message(truncated, &password("GNAMPF")), Err(MessageError::AuthenticationFailed)));
+    }
+
+    #[test]
+    fn test_recipients_round_trip() {
+        let (identity, public_key) = generate_identity();
+        let (other_identity, other_public_key) = generate_identity();
+        let (third_identity, _) = generate_identity();
+        let message : Vec<u8> = encrypt_message(b"", &Credentials::for_recipients(vec![public_key, other_public_key]), 10)
+            .unwrap();
+        for identity in [identity, other_identity] {
+            assert_eq!(*decrypt_message(&message, &Credentials::from_identity(identity)).ok().unwrap(), b"");
+        }
+        assert!(matches!(decrypt_message(&message, &Credentials::from_identity(third_identity)),
+            Err(MessageError::NoMatchingStanza)));
+    }
+
+    #[test]
+    fn test_decrypt_age_files() {
+        // Written by age 0.11, the passphrase one with a work factor of 10.
+        let plaintext : &[u8] = b"BLARZBLURZBLIRZ\nGNAMPF!\n";
+        decrypt_age_file("testfiles/age_passphrase.txt.age", &password("GNAMPF")).unwrap();
+        assert_eq!(fs::read("testfiles/age_passphrase.txt").unwrap(), plaintext);
+        fs::remove_file("testfiles/age_passphrase.txt").unwrap();
+
+        let identity_path : &str = "testfiles/age_x25519.key";
+        fs::write(identity_path, "AGE-SECRET-KEY-1H6W65DLM3N5K2LS3X9GSZ02T6FD42GXZM50NVSFWXSZJVEQXHHKQYFEL0Y\n").unwrap();
+        let identity : Secret = load_identity(identity_path).unwrap();
+        fs::remove_file(identity_path).unwrap();
+        decrypt_age_file("testfiles/age_x25519.txt.age", &Credentials::from_identity(identity)).unwrap();
+        assert_eq!(fs::read("testfiles/age_x25519.txt").unwrap(), plaintext);
+        fs::remove_file("testfiles/age_x25519.txt").unwrap();
+        assert!(parse_recipient("age12uve52z6jhg0edywksdtlmdayv66l9jqzm9mql4asff52mxkm46s3qaux5").is_some());
+    }
+
+    #[test]
+    fn test_stanza_body_wrapping() {
+        let file_key : Secret = random_secret(FILE_KEY_SIZE);
+        // 48 bytes fill exactly one line of base64 and need an empty line after it.
+        let stanza : Stanza = Stanza{
+            kind : "test".to_string(),
+            arguments : vec!["argument".to_string()],
+            body : vec![0x42; 48],
+        };
+        let header : Vec<u8> = write_header(&[stanza], &file_key);
+        let parsed : AgeHeader = parse_header(&header).ok().unwrap();
+        assert_eq!(parsed.stanzas[0].kind, "test");
+        assert_eq!(parsed.stanzas[0].arguments, ["argument"]);
+        assert_eq!(parsed.stanzas[0].body, [0x42; 48]);
+        assert!(parsed.payload.is_empty());
+    }
+}
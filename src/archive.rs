@@ -0,0 +1,108 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::credentials::Credentials;
+use crate::crypto_error::CryptoError;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::file_cipher::{decrypt_to_memory, encrypt_to_memory, write_new_file};
+use std::path::Path;
+use tar::{Archive, Builder};
+use zeroize::Zeroizing;
+
+pub const FILENAME_EXTENSION : &str = "sfe";
+
+/// The archive `pack` writes for `directory` unless told otherwise.
+pub fn default_archive_path(directory : &str) -> String {
+    directory.trim_end_matches('/').to_string() + "." + FILENAME_EXTENSION
+}
+
+/// Encrypts the tree below `directory` into a single file at `archive_path`,
+/// so neither the number nor the sizes of the files inside show. The tree is
+/// stored as a tar archive with paths, permissions and timestamps below the
+/// name of the directory. Symbolic links are stored as links. The archive is
+/// built in memory.
+pub fn pack_directory(directory : &str, archive_path : &str, credentials : &Credentials,
+    parameters : CryptoParameters) -> Result<(), CryptoError> {
+    let name : &Path = Path::new(directory).file_name().map(Path::new).unwrap_or(Path::new("."));
+    let mut builder : Builder<Vec<u8>> = Builder::new(Vec::new());
+    builder.follow_symlinks(false);
+    let plaintext : Zeroizing<Vec<u8>> = builder.append_dir_all(name, directory)
+        .and_then(|_| builder.into_inner())
+        .map(Zeroizing::new)
+        .map_err(|_| CryptoError::FileLoading(directory.to_string()))?;
+    let ciphertext : Vec<u8> = encrypt_to_memory(&plaintext, credentials, parameters, archive_path)?;
+    write_new_file(archive_path, &ciphertext, archive_path)
+}
+
+/// Decrypts an archive written by `pack_directory` and restores its tree
+/// below `output_directory`. Existing files are never overwritten and paths
+/// leaving `output_directory` are rejected.
+pub fn unpack_archive(archive_path : &str, output_directory : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    let plaintext : Zeroizing<Vec<u8>> = decrypt_to_memory(archive_path, credentials)?;
+    let mut archive : Archive<&[u8]> = Archive::new(plaintext.as_slice());
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_overwrite(false);
+    archive.unpack(output_directory).map_err(|_| CryptoError::InvalidArchive(archive_path.to_string()))
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use super::*;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+    use crate::crypto_parameters::key_size::KeySize;
+    use crate::secret::Secret;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn raw_key_parameters() -> CryptoParameters {
+        let default : CryptoParameters = CryptoParameters::default();
+        CryptoParameters::new(default.block_cipher(), Kdf::None, KeySize::Size256,
+            default.operation_mode(), KeyEncapsulation::None)
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let root : &str = "testfiles/pack_round_trip";
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.to_string() + "/tree/sub").unwrap();
+        fs::write(root.to_string() + "/tree/a.txt", "BLARZ").unwrap();
+        fs::write(root.to_string() + "/tree/sub/script.sh", "GNAMPF").unwrap();
+        fs::set_permissions(root.to_string() + "/tree/sub/script.sh", fs::Permissions::from_mode(0o750)).unwrap();
+        let credentials : Credentials = Credentials::from_raw_key(Secret::new(vec![0x42; 32]));
+        let archive_path : String = default_archive_path(&(root.to_string() + "/tree/"));
+        assert_eq!(archive_path, "testfiles/pack_round_trip/tree.sfe");
+
+        pack_directory(&(root.to_string() + "/tree"), &archive_path, &credentials, raw_key_parameters()).unwrap();
+        let wrong_key : Credentials = Credentials::from_raw_key(Secret::new(vec![0x43; 32]));
+        let wrong_key_result = unpack_archive(&archive_path, &(root.to_string() + "/wrong"), &wrong_key);
+        unpack_archive(&archive_path, &(root.to_string() + "/out"), &credentials).unwrap();
+        let overwrite_result = unpack_archive(&archive_path, &(root.to_string() + "/out"), &credentials);
+        let a : String = fs::read_to_string(root.to_string() + "/out/tree/a.txt").unwrap();
+        let script : String = fs::read_to_string(root.to_string() + "/out/tree/sub/script.sh").unwrap();
+        let mode : u32 = fs::metadata(root.to_string() + "/out/tree/sub/script.sh").unwrap().permissions().mode();
+        fs::remove_dir_all(root).unwrap();
+
+        assert!(matches!(wrong_key_result, Err(CryptoError::AuthenticationFailed(_))));
+        assert!(matches!(overwrite_result, Err(CryptoError::InvalidArchive(_))));
+        assert_eq!(a, "BLARZ");
+        assert_eq!(script, "GNAMPF");
+        assert_eq!(mode & 0o777, 0o750);
+    }
+}
@@ -0,0 +1,302 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use clap::builder::TypedValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+#[derive(Parser)]
+#[command(name = "sfe", version, about = "Simple file encryption")]
+pub struct Arguments {
+    #[command(subcommand)]
+    pub command : Command,
+    /// Reads defaults from this TOML file instead of ~/.config/sfe/config.toml
+    #[arg(long, global = true)]
+    pub config : Option<String>,
+    /// Where passwords come from: prompt, env:<variable> or file:<path>
+    #[arg(long, global = true)]
+    pub password_source : Option<String>,
+    /// Extension of native ciphertexts instead of enc or the one in the config
+    #[arg(long, global = true)]
+    pub filename_extension : Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Encrypts each file into <file>.enc, or the filename_extension of the config
+    Encrypt(EncryptArguments),
+    /// Decrypts native, OpenPGP and age files, recognizing them by their content
+    Decrypt(DecryptArguments),
+    /// Generates a diceware passphrase, optionally encrypting files with it
+    Genpass {
+        /// Number of words in the passphrase
+        #[arg(short, long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(1..))]
+        words : u32,
+        /// Files to encrypt with the generated passphrase
+        files : Vec<String>,
+        #[command(flatten)]
+        overwrite : OverwriteArguments,
+    },
+    /// Generates an X25519 identity and prints its public key
+    Keygen {
+        /// Writes the identity to this file instead of stdout
+        #[arg(short, long)]
+        output : Option<String>,
+    },
+    /// Decrypts each <file>.enc and encrypts it again with new parameters
+    Rekey(RekeyArguments),
+    /// Changes the password of an encrypted file without re-encrypting it
+    Passwd {
+        file : String,
+    },
+    /// Encrypts a directory tree into a single archive
+    Pack(PackArguments),
+    /// Restores a directory tree from an archive written by pack
+    Unpack(UnpackArguments),
+    /// Manages the password keyslots of an encrypted file
+    #[command(subcommand)]
+    Slot(SlotCommand),
+    /// Lists the profiles --profile accepts
+    Profiles,
+}
+
+#[derive(Subcommand)]
+pub enum SlotCommand {
+    /// Lists the keyslots of a file
+    List {
+        file : String,
+    },
+    /// Adds a keyslot for a new password, unlocking the file with an existing one
+    Add {
+        file : String,
+        /// Key derivation function of the new keyslot with its default costs, that of the file otherwise
+        #[arg(long, value_parser = ["pbkdf2-hmac-sha3-512", "scrypt", "argon2id"])]
+        kdf : Option<String>,
+    },
+    /// Removes a keyslot, unlocking the file with any of its passwords
+    Remove {
+        file : String,
+        /// Index of the keyslot as shown by `sfe slot list`
+        slot : usize,
+    },
+}
+
+#[derive(Args)]
+pub struct EncryptArguments {
+    #[arg(required = true)]
+    pub files : Vec<String>,
+    #[command(flatten)]
+    pub key : EncryptionKeyArguments,
+    /// Format of the ciphertext, openpgp writes <file>.pgp for gpg and age writes <file>.age
+    #[arg(long, value_enum, default_value_t = Format::Native,
+        conflicts_with_all = ["keyfile", "no_password", "raw_key"])]
+    pub format : Format,
+    /// Writes the ciphertext as base64 text between BEGIN and END lines
+    #[arg(long)]
+    pub armor : bool,
+    #[command(flatten)]
+    pub profile : ProfileArguments,
+    /// Whether each file gets its own salt or all files of a run share one,
+    /// which runs the KDF only once, native format only
+    #[arg(long, value_enum, default_value_t = SaltPolicy::PerFile)]
+    pub salt_policy : SaltPolicy,
+    #[command(flatten)]
+    pub tree : TreeArguments,
+    #[command(flatten)]
+    pub overwrite : OverwriteArguments,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum SaltPolicy {
+    PerFile,
+    /// Files encrypted with a password also share the payload key
+    PerRun,
+}
+
+#[derive(Args)]
+pub struct ProfileArguments {
+    /// Named set of cipher, key size, KDF and operation mode, see `sfe profiles`
+    #[arg(long, value_parser = ["fast", "default", "paranoid", "fips"])]
+    pub profile : Option<String>,
+}
+
+/// What the key of a new ciphertext comes from.
+#[derive(Args)]
+pub struct EncryptionKeyArguments {
+    /// Mixes the hash of this file into the key derivation
+    #[arg(long)]
+    pub keyfile : Option<String>,
+    /// Derives the key from the keyfile alone
+    #[arg(long, requires = "keyfile")]
+    pub no_password : bool,
+    /// Uses the 128, 192 or 256 bit key in this file (binary or hex) without a KDF
+    #[arg(long, conflicts_with_all = ["keyfile", "no_password"])]
+    pub raw_key : Option<String>,
+    /// Encrypts to this public key or age recipient instead of a password, may be repeated
+    #[arg(short, long, conflicts_with_all = ["keyfile", "no_password", "raw_key"])]
+    pub recipient : Vec<String>,
+}
+
+/// Processing many files at once. `-r` is taken by `--recipient`.
+#[derive(Args)]
+pub struct TreeArguments {
+    /// Processes every regular file below the given directories
+    #[arg(short = 'R', long)]
+    pub recursive : bool,
+    /// Only processes files whose path below the directory matches this glob, may be repeated
+    #[arg(long, requires = "recursive")]
+    pub include : Vec<String>,
+    /// Skips files whose path below the directory matches this glob, may be repeated
+    #[arg(long, requires = "recursive")]
+    pub exclude : Vec<String>,
+    /// Writes the output into a tree below this directory that mirrors the input, native format only
+    #[arg(long, requires = "recursive")]
+    pub output_dir : Option<String>,
+    /// Number of files processed in parallel
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs : u32,
+    /// How progress is reported on stderr, per segment for chunked files and per file for GCM files
+    #[arg(long, value_enum, default_value_t = Progress::Auto)]
+    pub progress : Progress,
+}
+
+/// What happens to destinations that already exist. Without any of these
+/// flags the overwrite policy of the config applies, which refuses them by default.
+#[derive(Args)]
+pub struct OverwriteArguments {
+    /// Replaces existing destinations once the new file is complete
+    #[arg(short, long, conflicts_with_all = ["no_clobber", "backup", "interactive"])]
+    pub force : bool,
+    /// Skips files whose destination exists
+    #[arg(long, conflicts_with_all = ["backup", "interactive"])]
+    pub no_clobber : bool,
+    /// Keeps existing destinations as <destination>.bak, or .bak.1, .bak.2 and so on, before replacing them
+    #[arg(long, conflicts_with = "interactive")]
+    pub backup : bool,
+    /// Asks before replacing each existing destination
+    #[arg(long)]
+    pub interactive : bool,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Progress {
+    /// A progress bar if stderr is a terminal
+    Auto,
+    Bar,
+    /// One JSON object per line
+    Json,
+    Off,
+}
+
+impl Default for TreeArguments {
+    fn default() -> TreeArguments {
+        TreeArguments{
+            recursive : false,
+            include : Vec::new(),
+            exclude : Vec::new(),
+            output_dir : None,
+            jobs : 1,
+            progress : Progress::Auto,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Native,
+    /// Only with a password, SKESK v4 and SEIPD v1 packets as gpg writes them
+    Openpgp,
+    /// Only with a password, the SKESK v6 and SEIPD v2 packets of RFC 9580, which gpg cannot read
+    OpenpgpV6,
+    /// With a password or recipients
+    Age,
+}
+
+#[derive(Args)]
+pub struct DecryptArguments {
+    #[arg(required = true)]
+    pub files : Vec<String>,
+    #[command(flatten)]
+    pub key : DecryptionKeyArguments,
+    #[command(flatten)]
+    pub tree : TreeArguments,
+    #[command(flatten)]
+    pub overwrite : OverwriteArguments,
+}
+
+/// What unlocks an existing ciphertext besides a password.
+#[derive(Args)]
+pub struct DecryptionKeyArguments {
+    /// Keyfile used during encryption
+    #[arg(long)]
+    pub keyfile : Option<String>,
+    /// Raw key used during encryption
+    #[arg(long, conflicts_with = "keyfile")]
+    pub raw_key : Option<String>,
+    /// Identity file matching one of the recipients, age identities work too
+    #[arg(short, long, conflicts_with_all = ["keyfile", "raw_key"])]
+    pub identity : Option<String>,
+}
+
+#[derive(Args)]
+pub struct RekeyArguments {
+    #[arg(required = true)]
+    pub files : Vec<String>,
+    /// Block cipher of the new ciphertext
+    #[arg(long, value_parser = ["aes", "camellia"])]
+    pub cipher : Option<String>,
+    /// Key size of the new ciphertext in bits
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(["128", "192", "256"])
+        .map(|size| size.parse::<u32>().unwrap()))]
+    pub key_size : Option<u32>,
+    /// Key derivation function of the new ciphertext, with its default costs
+    #[arg(long, value_parser = ["pbkdf2-hmac-sha3-512", "scrypt", "argon2id"])]
+    pub kdf : Option<String>,
+    /// Operation mode of the new ciphertext, chunked-gcm is processed on all cores
+    #[arg(long, value_parser = ["gcm", "chunked-gcm"])]
+    pub mode : Option<String>,
+    /// Keyfile used during encryption
+    #[arg(long)]
+    pub keyfile : Option<String>,
+    /// Raw key used during encryption, which also determines the new key size
+    #[arg(long, conflicts_with_all = ["keyfile", "key_size", "kdf"])]
+    pub raw_key : Option<String>,
+}
+
+#[derive(Args)]
+pub struct PackArguments {
+    pub directory : String,
+    /// Archive to write, <directory>.sfe by default
+    #[arg(short, long)]
+    pub output : Option<String>,
+    #[command(flatten)]
+    pub key : EncryptionKeyArguments,
+    #[command(flatten)]
+    pub profile : ProfileArguments,
+    #[command(flatten)]
+    pub overwrite : OverwriteArguments,
+}
+
+#[derive(Args)]
+pub struct UnpackArguments {
+    pub archive : String,
+    /// Directory to restore the tree into
+    #[arg(short, long, default_value = ".")]
+    pub output : String,
+    #[command(flatten)]
+    pub key : DecryptionKeyArguments,
+}
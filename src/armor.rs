@@ -0,0 +1,85 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+pub const BEGIN_MARKER : &str = "-----BEGIN SFE ENCRYPTED FILE-----";
+pub const END_MARKER : &str = "-----END SFE ENCRYPTED FILE-----";
+const COLUMNS : usize = 64;
+
+/// Wraps binary ciphertext in base64 between BEGIN and END markers, so it
+/// survives email and text formats like YAML.
+pub fn armor(data : &[u8]) -> Vec<u8> {
+    let encoded : String = STANDARD.encode(data);
+    let mut armored : Vec<u8> = Vec::with_capacity(encoded.len() + encoded.len() / COLUMNS + 2 * BEGIN_MARKER.len() + 4);
+    armored.extend_from_slice(BEGIN_MARKER.as_bytes());
+    armored.push(b'\n');
+    for line in encoded.as_bytes().chunks(COLUMNS) {
+        armored.extend_from_slice(line);
+        armored.push(b'\n');
+    }
+    armored.extend_from_slice(END_MARKER.as_bytes());
+    armored.push(b'\n');
+    armored
+}
+
+pub fn is_armored(data : &[u8]) -> bool {
+    data.trim_ascii_start().starts_with(BEGIN_MARKER.as_bytes())
+}
+
+/// Recovers the binary ciphertext. Line endings and whitespace around the
+/// lines may have been changed in transit.
+pub fn dearmor(data : &[u8]) -> Option<Vec<u8>> {
+    let data : &[u8] = data.trim_ascii().strip_prefix(BEGIN_MARKER.as_bytes())?.strip_suffix(END_MARKER.as_bytes())?;
+    let encoded : Vec<u8> = data.iter().copied().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    STANDARD.decode(encoded).ok()
+}
+
+#[cfg(test)]
+mod armor_tests {
+    use super::*;
+
+    #[test]
+    fn test_armor_round_trip() {
+        let data : Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let armored : Vec<u8> = armor(&data);
+        assert!(is_armored(&armored));
+        assert!(armored.split(|byte| *byte == b'\n').all(|line| line.len() <= COLUMNS || line.starts_with(b"-----")));
+        assert_eq!(dearmor(&armored).unwrap(), data);
+        assert_eq!(dearmor(&armor(&[])).unwrap(), []);
+    }
+
+    #[test]
+    fn test_dearmor_changed_whitespace() {
+        let armored : String = String::from_utf8(armor(b"GNAMPF!")).unwrap().replace('\n', "\r\n  ");
+        assert!(is_armored(armored.as_bytes()));
+        assert_eq!(dearmor(armored.as_bytes()).unwrap(), b"GNAMPF!");
+    }
+
+    #[test]
+    fn test_dearmor_invalid() {
+        assert!(!is_armored(b"GNAMPF!"));
+        assert!(dearmor(b"GNAMPF!").is_none());
+        let armored : Vec<u8> = armor(b"GNAMPF!");
+        assert!(dearmor(&armored[0..armored.len() - 5]).is_none());
+        let mut corrupted : Vec<u8> = armored.clone();
+        corrupted[BEGIN_MARKER.len() + 2] = b'!';
+        assert!(dearmor(&corrupted).is_none());
+    }
+}
@@ -0,0 +1,312 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::credentials::Credentials;
+use crate::crypto_error::CryptoError;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::operation_mode::OperationMode;
+use crate::file_cipher::IN_MEMORY;
+use crate::header::{Header, PREFIX_SIZE, TAG_SIZE};
+use crate::secret::Secret;
+use crate::segments::SEGMENT_SIZE;
+use crate::stream::{header_size, new_chunked_header, open_segment, seal_segment, unlock_header};
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::task;
+use zeroize::Zeroizing;
+
+/// The asynchronous counterpart of `Encryptor` for the chunked format.
+/// `shutdown` seals the last segment, so it has to be called at the end.
+pub struct AsyncEncryptor<W : AsyncWrite + Unpin> {
+    writer : W,
+    header : Header,
+    key : Secret,
+    buffer : Zeroizing<Vec<u8>>,
+    /// Ciphertext that has not been written yet, starting with the header.
+    output : Vec<u8>,
+    written : usize,
+    segment : usize,
+    finished : bool,
+}
+
+impl<W : AsyncWrite + Unpin> AsyncEncryptor<W> {
+    /// Runs the KDF right away. Unlike `AsyncDecryptor::new` this needs no
+    /// I/O, so callers with password credentials can simply create it inside
+    /// `spawn_blocking`.
+    pub fn new(writer : W, credentials : &Credentials, parameters : CryptoParameters) -> Result<AsyncEncryptor<W>, CryptoError> {
+        if parameters.operation_mode() != OperationMode::ChunkedGcm {
+            return Err(CryptoError::UnsupportedParameters(IN_MEMORY.to_string()));
+        }
+        let (header, key) = new_chunked_header(credentials, parameters)?;
+        Ok(AsyncEncryptor{
+            writer,
+            output : header.to_byte_buffer(),
+            header,
+            key,
+            buffer : Zeroizing::new(Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE)),
+            written : 0,
+            segment : 0,
+            finished : false,
+        })
+    }
+
+    fn seal_buffer(&mut self, last : bool) -> io::Result<()> {
+        seal_segment(&self.header, &self.key, self.segment, last, &mut self.buffer)?;
+        self.output = mem::replace(&mut *self.buffer, Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE));
+        self.written = 0;
+        self.segment += 1;
+        Ok(())
+    }
+
+    /// Writes `output` to the writer.
+    fn poll_output(&mut self, context : &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let written : usize = ready!(Pin::new(&mut self.writer).poll_write(context, &self.output[self.written..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += written;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W : AsyncWrite + Unpin> AsyncWrite for AsyncEncryptor<W> {
+    fn poll_write(self : Pin<&mut Self>, context : &mut Context<'_>, data : &[u8]) -> Poll<io::Result<usize>> {
+        let encryptor : &mut AsyncEncryptor<W> = self.get_mut();
+        if encryptor.finished {
+            return Poll::Ready(Err(io::Error::other("write after shutdown")));
+        }
+        ready!(encryptor.poll_output(context))?;
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // A full segment is only sealed once more data shows it is not the last one.
+        if encryptor.buffer.len() == SEGMENT_SIZE {
+            encryptor.seal_buffer(false)?;
+        }
+        let length : usize = data.len().min(SEGMENT_SIZE - encryptor.buffer.len());
+        encryptor.buffer.extend_from_slice(&data[0..length]);
+        Poll::Ready(Ok(length))
+    }
+
+    /// Flushes the ciphertext so far, which does not include a partial segment.
+    fn poll_flush(self : Pin<&mut Self>, context : &mut Context<'_>) -> Poll<io::Result<()>> {
+        let encryptor : &mut AsyncEncryptor<W> = self.get_mut();
+        ready!(encryptor.poll_output(context))?;
+        Pin::new(&mut encryptor.writer).poll_flush(context)
+    }
+
+    fn poll_shutdown(self : Pin<&mut Self>, context : &mut Context<'_>) -> Poll<io::Result<()>> {
+        let encryptor : &mut AsyncEncryptor<W> = self.get_mut();
+        ready!(encryptor.poll_output(context))?;
+        if !encryptor.finished {
+            encryptor.seal_buffer(true)?;
+            encryptor.finished = true;
+            ready!(encryptor.poll_output(context))?;
+        }
+        Pin::new(&mut encryptor.writer).poll_shutdown(context)
+    }
+}
+
+/// The asynchronous counterpart of `Decryptor` for the chunked format. A
+/// segment is only returned once it has been authenticated.
+pub struct AsyncDecryptor<R : AsyncRead + Unpin> {
+    reader : R,
+    header : Header,
+    key : Secret,
+    plaintext : Zeroizing<Vec<u8>>,
+    position : usize,
+    /// Ciphertext read so far, up to one byte past the next segment.
+    ciphertext : Vec<u8>,
+    end_of_stream : bool,
+    segment : usize,
+    finished : bool,
+}
+
+impl<R : AsyncRead + Unpin> AsyncDecryptor<R> {
+    /// Reads the header and checks the credentials against it. The KDF runs
+    /// on tokio's blocking thread pool instead of stalling the runtime, which
+    /// is why the credentials are shared.
+    pub async fn new(mut reader : R, credentials : Arc<Credentials>) -> Result<AsyncDecryptor<R>, CryptoError> {
+        let mut byte_buffer : Vec<u8> = vec![0x00; PREFIX_SIZE];
+        reader.read_exact(&mut byte_buffer).await.map_err(|_| CryptoError::InvalidHeader(IN_MEMORY.to_string()))?;
+        byte_buffer.resize(header_size(&byte_buffer)?, 0x00);
+        reader.read_exact(&mut byte_buffer[PREFIX_SIZE..]).await
+            .map_err(|_| CryptoError::InvalidHeader(IN_MEMORY.to_string()))?;
+        let (header, key) = task::spawn_blocking(move || unlock_header(&byte_buffer, &credentials)).await
+            .map_err(|_| CryptoError::InvalidKey(IN_MEMORY.to_string()))??;
+        if header.parameters.operation_mode() != OperationMode::ChunkedGcm {
+            return Err(CryptoError::UnsupportedParameters(IN_MEMORY.to_string()));
+        }
+        Ok(AsyncDecryptor{
+            reader,
+            header,
+            key,
+            plaintext : Zeroizing::new(Vec::new()),
+            position : 0,
+            ciphertext : Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE + 1),
+            end_of_stream : false,
+            segment : 0,
+            finished : false,
+        })
+    }
+
+    /// Reads until the next segment is complete and it is known whether it is
+    /// the last one, then opens it into `plaintext`.
+    fn poll_plaintext(&mut self, context : &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.end_of_stream && self.ciphertext.len() <= SEGMENT_SIZE + TAG_SIZE {
+            let start : usize = self.ciphertext.len();
+            self.ciphertext.resize(SEGMENT_SIZE + TAG_SIZE + 1, 0x00);
+            let mut read_buffer : ReadBuf = ReadBuf::new(&mut self.ciphertext[start..]);
+            let result : Poll<io::Result<()>> = Pin::new(&mut self.reader).poll_read(context, &mut read_buffer);
+            let read : usize = read_buffer.filled().len();
+            self.ciphertext.truncate(start + read);
+            ready!(result)?;
+            self.end_of_stream = read == 0;
+        }
+        let last : bool = self.ciphertext.len() <= SEGMENT_SIZE + TAG_SIZE;
+        let mut segment : Zeroizing<Vec<u8>> = if last {
+            Zeroizing::new(mem::take(&mut self.ciphertext))
+        } else {
+            let next : Vec<u8> = self.ciphertext[SEGMENT_SIZE + TAG_SIZE..].to_vec();
+            self.ciphertext.truncate(SEGMENT_SIZE + TAG_SIZE);
+            Zeroizing::new(mem::replace(&mut self.ciphertext, next))
+        };
+        open_segment(&self.header, &self.key, self.segment, last, &mut segment)?;
+        self.plaintext = segment;
+        self.position = 0;
+        self.segment += 1;
+        self.finished = last;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R : AsyncRead + Unpin> AsyncRead for AsyncDecryptor<R> {
+    fn poll_read(self : Pin<&mut Self>, context : &mut Context<'_>, buffer : &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let decryptor : &mut AsyncDecryptor<R> = self.get_mut();
+        while decryptor.position == decryptor.plaintext.len() {
+            if decryptor.finished {
+                return Poll::Ready(Ok(()));
+            }
+            ready!(decryptor.poll_plaintext(context))?;
+        }
+        let length : usize = buffer.remaining().min(decryptor.plaintext.len() - decryptor.position);
+        buffer.put_slice(&decryptor.plaintext[decryptor.position..decryptor.position + length]);
+        decryptor.position += length;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod async_stream_tests {
+    use super::*;
+    use crate::crypto_parameters::block_cipher::BlockCipher;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::kdf_cost::KdfCost;
+    use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+    use crate::crypto_parameters::key_size::KeySize;
+    use crate::file_cipher::{decrypt_bytes, encrypt_bytes};
+    use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+    fn raw_key() -> Credentials {
+        Credentials::from_raw_key(Secret::new(vec![0x42; 32]))
+    }
+
+    fn parameters() -> CryptoParameters {
+        CryptoParameters::new(BlockCipher::Aes, Kdf::None, KeySize::Size256, OperationMode::ChunkedGcm, KeyEncapsulation::None)
+    }
+
+    fn plaintext(length : usize) -> Vec<u8> {
+        (0..length).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Encrypts through a duplex stream much smaller than a segment, so both
+    /// sides have to wait for each other.
+    async fn encrypt(plaintext : &[u8]) -> Vec<u8> {
+        let (client, mut server) : (DuplexStream, DuplexStream) = duplex(4096);
+        let mut encryptor : AsyncEncryptor<DuplexStream> = AsyncEncryptor::new(client, &raw_key(), parameters()).unwrap();
+        let writing = async {
+            encryptor.write_all(plaintext).await.unwrap();
+            encryptor.shutdown().await.unwrap();
+        };
+        let mut ciphertext : Vec<u8> = Vec::new();
+        let reading = server.read_to_end(&mut ciphertext);
+        let (_, read) = tokio::join!(writing, reading);
+        read.unwrap();
+        ciphertext
+    }
+
+    async fn decrypt(ciphertext : &[u8], credentials : Credentials) -> io::Result<Vec<u8>> {
+        let (mut client, server) : (DuplexStream, DuplexStream) = duplex(4096);
+        let writing = async {
+            client.write_all(ciphertext).await.unwrap();
+            client.shutdown().await.unwrap();
+        };
+        let reading = async {
+            let mut decryptor : AsyncDecryptor<DuplexStream> = AsyncDecryptor::new(server, Arc::new(credentials)).await
+                .map_err(io::Error::other)?;
+            let mut plaintext : Vec<u8> = Vec::new();
+            decryptor.read_to_end(&mut plaintext).await.map(|_| plaintext)
+        };
+        tokio::join!(writing, reading).1
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        for length in [0, 1000, SEGMENT_SIZE, 2 * SEGMENT_SIZE + 1000] {
+            let plaintext : Vec<u8> = plaintext(length);
+            let ciphertext : Vec<u8> = encrypt(&plaintext).await;
+            assert_eq!(*decrypt_bytes(&ciphertext, &raw_key()).unwrap(), plaintext);
+            let ciphertext : Vec<u8> = encrypt_bytes(&plaintext, &raw_key(), &parameters()).unwrap();
+            assert_eq!(decrypt(&ciphertext, raw_key()).await.unwrap(), plaintext);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password() {
+        let password = || Credentials::from(Secret::from("GNAMPF".to_string()));
+        let parameters : CryptoParameters = CryptoParameters::builder()
+            .kdf_cost(KdfCost::Argon2id{memory_kib : 1024, iterations : 2, parallelism : 1}).build().unwrap();
+        let ciphertext : Vec<u8> = encrypt_bytes(&plaintext(1000), &password(), &parameters).unwrap();
+        assert_eq!(decrypt(&ciphertext, password()).await.unwrap(), plaintext(1000));
+        assert!(decrypt(&ciphertext, Credentials::from(Secret::from("wrong".to_string()))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tampering_is_detected() {
+        let ciphertext : Vec<u8> = encrypt(&plaintext(SEGMENT_SIZE + 1000)).await;
+        let truncated : &[u8] = &ciphertext[0..ciphertext.len() - 1000 - TAG_SIZE];
+        assert_eq!(decrypt(truncated, raw_key()).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut flipped : Vec<u8> = ciphertext.clone();
+        flipped[ciphertext.len() - 1] ^= 0x01;
+        assert_eq!(decrypt(&flipped, raw_key()).await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_gcm_is_rejected() {
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Aes, Kdf::None, KeySize::Size256,
+            OperationMode::Gcm, KeyEncapsulation::None);
+        assert!(matches!(AsyncEncryptor::new(Vec::new(), &raw_key(), parameters),
+            Err(CryptoError::UnsupportedParameters(_))));
+    }
+}
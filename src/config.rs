@@ -0,0 +1,298 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::age;
+use crate::crypto_parameters::block_cipher::BlockCipher;
+use crate::crypto_parameters::crypto_parameters::{CryptoParameters, CryptoParametersBuilder};
+use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::kdf_cost::KdfCost;
+use crate::crypto_parameters::key_size::KeySize;
+use crate::crypto_parameters::operation_mode::OperationMode;
+use crate::file_cipher::FILENAME_EXTENSION;
+use crate::openpgp;
+use crate::overwrite::OverwritePolicy;
+use crate::profile::Profile;
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// Defaults for new ciphertexts from a TOML file, which command line flags
+/// override. Every key is optional:
+///
+/// ```toml
+/// profile = "paranoid"
+/// cipher = "camellia"
+/// key_size = 256
+/// kdf = "scrypt"
+/// scrypt_log_n = 21
+/// mode = "chunked-gcm"
+/// filename_extension = "sfe"
+/// password_source = "env:SFE_PASSWORD"
+/// overwrite = "backup"
+/// ```
+///
+/// The other keys refine the profile. The cost keys `pbkdf2_iterations`,
+/// `scrypt_log_n`, `scrypt_r`, `scrypt_p`, `argon2id_memory_kib`,
+/// `argon2id_iterations` and `argon2id_parallelism` change the costs of the
+/// KDF that ends up being used, and only keys of that KDF are accepted. The
+/// costs are recorded in the header, so decryption never reads them from here.
+#[derive(Deserialize, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub profile : Option<String>,
+    pub cipher : Option<String>,
+    pub key_size : Option<u32>,
+    pub kdf : Option<String>,
+    pub pbkdf2_iterations : Option<u32>,
+    pub scrypt_log_n : Option<u8>,
+    pub scrypt_r : Option<u32>,
+    pub scrypt_p : Option<u32>,
+    pub argon2id_memory_kib : Option<u32>,
+    pub argon2id_iterations : Option<u32>,
+    pub argon2id_parallelism : Option<u32>,
+    pub mode : Option<String>,
+    pub filename_extension : Option<String>,
+    pub password_source : Option<String>,
+    pub overwrite : Option<String>,
+}
+
+/// Where passwords come from when they are not typed in.
+#[derive(PartialEq, Debug, Clone)]
+pub enum PasswordSource {
+    Prompt,
+    /// The value of an environment variable.
+    Environment(String),
+    /// The first line of a file.
+    File(String),
+}
+
+impl PasswordSource {
+    /// Parses `prompt`, `env:<variable>` or `file:<path>`.
+    pub fn from_name(name : &str) -> Option<PasswordSource> {
+        if name == "prompt" {
+            return Some(PasswordSource::Prompt);
+        }
+        if let Some(variable) = name.strip_prefix("env:").filter(|variable| !variable.is_empty()) {
+            return Some(PasswordSource::Environment(variable.to_string()));
+        }
+        name.strip_prefix("file:").filter(|path| !path.is_empty()).map(|path| PasswordSource::File(path.to_string()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    path : String,
+    message : String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Error for ConfigError{}
+
+/// `$XDG_CONFIG_HOME/sfe/config.toml`, which defaults to `~/.config/sfe/config.toml`.
+pub fn default_config_path() -> Option<PathBuf> {
+    let directory : PathBuf = env::var_os("XDG_CONFIG_HOME").filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(directory.join("sfe").join("config.toml"))
+}
+
+impl Config {
+    /// Loads the config at `path`, or the default config if there is one.
+    pub fn load(path : Option<&str>) -> Result<Config, ConfigError> {
+        let path : PathBuf = match path {
+            Some(path) => PathBuf::from(path),
+            None => match default_config_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|content| Config::parse(&content))
+            .map_err(|message| ConfigError{path : path.display().to_string(), message})
+    }
+
+    /// Parses a config and checks every value, so a mistake shows up even
+    /// if the command at hand does not need the value.
+    pub fn parse(content : &str) -> Result<Config, String> {
+        let config : Config = toml::from_str(content).map_err(|error| error.message().to_string())?;
+        config.parameters(None)?;
+        config.password_source()?;
+        config.overwrite_policy()?;
+        if let Some(extension) = &config.filename_extension {
+            check_filename_extension(extension)?;
+        }
+        Ok(config)
+    }
+
+    /// Replaces the extension of native ciphertexts, e.g. with one from the command line.
+    pub fn set_filename_extension(&mut self, extension : &str) -> Result<(), String> {
+        check_filename_extension(extension)?;
+        self.filename_extension = Some(extension.to_string());
+        Ok(())
+    }
+
+    /// The parameters of new ciphertexts. A profile from the command line
+    /// replaces everything the config says about them.
+    pub fn parameters(&self, profile : Option<Profile>) -> Result<CryptoParameters, String> {
+        if let Some(profile) = profile {
+            return Ok(profile.parameters());
+        }
+        let profile : Profile = match &self.profile {
+            Some(name) => Profile::from_name(name).ok_or_else(|| format!("unknown profile {}", name))?,
+            None => Profile::Default,
+        };
+        let mut builder : CryptoParametersBuilder = CryptoParametersBuilder::from(profile.parameters());
+        if let Some(cipher) = &self.cipher {
+            builder = builder.block_cipher(BlockCipher::from_name(cipher).ok_or_else(|| format!("unknown cipher {}", cipher))?);
+        }
+        if let Some(key_size) = self.key_size {
+            builder = builder.key_size(KeySize::from_integer(key_size).ok_or_else(|| format!("unknown key_size {}", key_size))?);
+        }
+        if let Some(kdf) = &self.kdf {
+            builder = builder.kdf(Kdf::from_name(kdf).ok_or_else(|| format!("unknown kdf {}", kdf))?);
+        }
+        if let Some(mode) = &self.mode {
+            builder = builder.operation_mode(OperationMode::from_name(mode).ok_or_else(|| format!("unknown mode {}", mode))?);
+        }
+        let parameters : CryptoParameters = builder.build().map_err(|error| error.to_string())?;
+        CryptoParametersBuilder::from(parameters)
+            .kdf_cost(self.kdf_cost(parameters.kdf_cost())?)
+            .build()
+            .map_err(|error| error.to_string())
+    }
+
+    /// Applies the cost keys to `kdf_cost`. Keys of another KDF are an error
+    /// instead of being ignored, since they would not do what they promise.
+    fn kdf_cost(&self, kdf_cost : KdfCost) -> Result<KdfCost, String> {
+        let pbkdf2 : bool = self.pbkdf2_iterations.is_some();
+        let scrypt : bool = self.scrypt_log_n.is_some() || self.scrypt_r.is_some() || self.scrypt_p.is_some();
+        let argon2id : bool = self.argon2id_memory_kib.is_some() || self.argon2id_iterations.is_some()
+            || self.argon2id_parallelism.is_some();
+        match kdf_cost {
+            KdfCost::Pbkdf2HmacSha3512{iterations} if !scrypt && !argon2id => Ok(KdfCost::Pbkdf2HmacSha3512{
+                iterations : self.pbkdf2_iterations.unwrap_or(iterations),
+            }),
+            KdfCost::Scrypt{log_n, r, p} if !pbkdf2 && !argon2id => Ok(KdfCost::Scrypt{
+                log_n : self.scrypt_log_n.unwrap_or(log_n),
+                r : self.scrypt_r.unwrap_or(r),
+                p : self.scrypt_p.unwrap_or(p),
+            }),
+            KdfCost::Argon2id{memory_kib, iterations, parallelism} if !pbkdf2 && !scrypt => Ok(KdfCost::Argon2id{
+                memory_kib : self.argon2id_memory_kib.unwrap_or(memory_kib),
+                iterations : self.argon2id_iterations.unwrap_or(iterations),
+                parallelism : self.argon2id_parallelism.unwrap_or(parallelism),
+            }),
+            KdfCost::None if !pbkdf2 && !scrypt && !argon2id => Ok(KdfCost::None),
+            _ => Err(format!("the cost keys do not belong to the kdf {}", kdf_cost.kdf())),
+        }
+    }
+
+    pub fn filename_extension(&self) -> &str {
+        self.filename_extension.as_deref().unwrap_or(FILENAME_EXTENSION)
+    }
+
+    pub fn password_source(&self) -> Result<PasswordSource, String> {
+        match &self.password_source {
+            Some(name) => PasswordSource::from_name(name).ok_or_else(|| format!("unknown password_source {}", name)),
+            None => Ok(PasswordSource::Prompt),
+        }
+    }
+
+    pub fn overwrite_policy(&self) -> Result<OverwritePolicy, String> {
+        match &self.overwrite {
+            Some(name) => OverwritePolicy::from_name(name).ok_or_else(|| format!("unknown overwrite policy {}", name)),
+            None => Ok(OverwritePolicy::default()),
+        }
+    }
+}
+
+/// The extensions of the other containers are taken, and anything but a
+/// single extension would change the directory or the name of the file.
+fn check_filename_extension(extension : &str) -> Result<(), String> {
+    let reserved : [&str; 3] = [age::FILENAME_EXTENSION, openpgp::FILENAME_EXTENSION, openpgp::GPG_FILENAME_EXTENSION];
+    if extension.is_empty() || reserved.contains(&extension)
+        || !extension.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_') {
+        return Err(format!("{} cannot be used as filename extension", extension));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config : Config = Config::parse("profile = \"fast\"\ncipher = \"camellia\"\nkdf = \"scrypt\"\n\
+            filename_extension = \"sfe\"\npassword_source = \"env:SFE_PASSWORD\"\noverwrite = \"no-clobber\"\n").unwrap();
+        let parameters : CryptoParameters = config.parameters(None).unwrap();
+        assert_eq!(parameters, CryptoParametersBuilder::from(Profile::Fast.parameters())
+            .block_cipher(BlockCipher::Camellia).kdf(Kdf::Scrypt).build().unwrap());
+        assert_eq!(config.parameters(Some(Profile::Fips)).unwrap(), Profile::Fips.parameters());
+        assert_eq!(config.filename_extension(), "sfe");
+        assert_eq!(config.password_source().unwrap(), PasswordSource::Environment("SFE_PASSWORD".to_string()));
+        assert_eq!(config.overwrite_policy().unwrap(), OverwritePolicy::NoClobber);
+
+        let empty : Config = Config::parse("").unwrap();
+        assert_eq!(empty.parameters(None).unwrap(), CryptoParameters::default());
+        assert_eq!(empty.filename_extension(), FILENAME_EXTENSION);
+        assert_eq!(empty.password_source().unwrap(), PasswordSource::Prompt);
+        assert_eq!(empty.overwrite_policy().unwrap(), OverwritePolicy::Refuse);
+
+        let costly : Config = Config::parse("kdf = \"argon2id\"\nargon2id_memory_kib = 262144\nargon2id_parallelism = 2").unwrap();
+        assert_eq!(costly.parameters(None).unwrap().kdf_cost(),
+            KdfCost::Argon2id{memory_kib : 262_144, iterations : 3, parallelism : 2});
+        let pbkdf2 : Config = Config::parse("kdf = \"pbkdf2-hmac-sha3-512\"\npbkdf2_iterations = 600000").unwrap();
+        assert_eq!(pbkdf2.parameters(None).unwrap().kdf_cost(), KdfCost::Pbkdf2HmacSha3512{iterations : 600_000});
+        let scrypt : Config = Config::parse("kdf = \"scrypt\"\nscrypt_log_n = 18\nscrypt_r = 16").unwrap();
+        assert_eq!(scrypt.parameters(None).unwrap().kdf_cost(), KdfCost::Scrypt{log_n : 18, r : 16, p : 1});
+
+        let mut overridden : Config = Config::parse("filename_extension = \"sfe\"").unwrap();
+        overridden.set_filename_extension("crypt").unwrap();
+        assert_eq!(overridden.filename_extension(), "crypt");
+        assert!(overridden.set_filename_extension("pgp").is_err());
+        assert_eq!(overridden.filename_extension(), "crypt");
+    }
+
+    #[test]
+    fn test_reject_invalid_config() {
+        for content in ["cipher = \"des\"", "key_size = 512", "kdf = \"none\"", "mode = \"ecb\"", "profile = \"weak\"",
+            "filename_extension = \"age\"", "filename_extension = \"../enc\"", "password_source = \"stdin\"", "overwrite = \"yes\"",
+            "compression = true", "key_size = \"256\"", "pbkdf2_iterations = 1000", "scrypt_log_n = 18",
+            "kdf = \"scrypt\"\nscrypt_log_n = 300", "kdf = \"argon2id\"\nargon2id_parallelism = 0",
+            "kdf = \"argon2id\"\npbkdf2_iterations = 600000", "argon2id_memory_kib = -1"] {
+            assert!(Config::parse(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn test_password_source_from_name() {
+        assert_eq!(PasswordSource::from_name("prompt").unwrap(), PasswordSource::Prompt);
+        assert_eq!(PasswordSource::from_name("file:/run/secret").unwrap(), PasswordSource::File("/run/secret".to_string()));
+        assert_eq!(PasswordSource::from_name("env:"), None);
+    }
+}
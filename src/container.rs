@@ -0,0 +1,130 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::age;
+use crate::archive;
+use crate::armor::is_armored;
+use crate::crypto_error::CryptoError;
+use crate::file_cipher::FILENAME_EXTENSION;
+use crate::header::Header;
+use crate::openpgp;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Appended to the name of a decrypted file if the ciphertext has none of
+/// the known extensions.
+pub const DECRYPTED_EXTENSION : &str = "decrypted";
+/// Enough of a file to recognize its container, including some whitespace
+/// in front of an armored one.
+const SIGNATURE_SIZE : usize = 256;
+
+/// The kinds of ciphertext sfe decrypts.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Container {
+    /// The native format, armored or not.
+    Native,
+    OpenPgp,
+    Age,
+}
+
+/// Recognizes a ciphertext by its first bytes. Native ciphertexts start
+/// with the header magic. Those of an unknown version are still reported as
+/// native, so reading them fails with a clear error instead of being skipped.
+pub fn detect_container(data : &[u8]) -> Option<Container> {
+    if Header::has_magic(data) || is_armored(data) {
+        Some(Container::Native)
+    } else if openpgp::is_openpgp_message(data) {
+        Some(Container::OpenPgp)
+    } else if age::is_age_message(data) {
+        Some(Container::Age)
+    } else {
+        None
+    }
+}
+
+/// Reads the beginning of `path` to recognize its container, no matter
+/// which extension it has.
+pub fn detect_file_container(path : &str) -> Result<Option<Container>, CryptoError> {
+    let mut signature : Vec<u8> = Vec::with_capacity(SIGNATURE_SIZE);
+    File::open(path)
+        .and_then(|file| file.take(SIGNATURE_SIZE as u64).read_to_end(&mut signature))
+        .map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    Ok(detect_container(&signature))
+}
+
+/// Where a ciphertext at `path` is decrypted to by default: `path` without
+/// `.extension` or one of the extensions sfe, gpg and age write, otherwise
+/// `path.decrypted`.
+pub fn decrypted_path(path : &str, extension : &str) -> String {
+    let known : [&str; 6] = [extension, FILENAME_EXTENSION, archive::FILENAME_EXTENSION, openpgp::FILENAME_EXTENSION,
+        openpgp::GPG_FILENAME_EXTENSION, age::FILENAME_EXTENSION];
+    match Path::new(path).extension().and_then(|suffix| suffix.to_str()) {
+        Some(suffix) if known.contains(&suffix) && path.len() > suffix.len() + 1 =>
+            path[0..path.len() - suffix.len() - 1].to_string(),
+        _ => path.to_string() + "." + DECRYPTED_EXTENSION,
+    }
+}
+
+#[cfg(test)]
+mod container_tests {
+    use super::*;
+    use crate::credentials::Credentials;
+    use crate::crypto_parameters::block_cipher::BlockCipher;
+    use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+    use crate::crypto_parameters::key_size::KeySize;
+    use crate::crypto_parameters::operation_mode::OperationMode;
+    use crate::file_cipher::{encrypt_armored_file_to, encrypt_bytes};
+    use crate::header::MAGIC_SIZE;
+    use crate::secret::Secret;
+    use std::fs;
+
+    #[test]
+    fn test_detect_container() {
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Aes, Kdf::None,
+            KeySize::Size128, OperationMode::ChunkedGcm, KeyEncapsulation::None);
+        let credentials : Credentials = Credentials::from_raw_key(Secret::new(vec![0x42; 16]));
+        let native : Vec<u8> = encrypt_bytes(b"GNAMPF!", &credentials, &parameters).unwrap();
+        assert_eq!(detect_container(&native), Some(Container::Native));
+        assert_eq!(detect_container(&native[MAGIC_SIZE..]), None);
+        let armored_path : &str = "testfiles/container_armored";
+        let _ = fs::remove_file(armored_path);
+        encrypt_armored_file_to("testfiles/test1.txt", armored_path, &credentials, parameters).unwrap();
+        assert_eq!(detect_file_container(armored_path).unwrap(), Some(Container::Native));
+        fs::remove_file(armored_path).unwrap();
+
+        assert_eq!(detect_file_container("testfiles/gpg_symmetric.txt.gpg").unwrap(), Some(Container::OpenPgp));
+        assert_eq!(detect_file_container("testfiles/age_passphrase.txt.age").unwrap(), Some(Container::Age));
+        assert_eq!(detect_file_container("testfiles/test1.txt.enc").unwrap(), None);
+        assert_eq!(detect_container(b""), None);
+        assert!(detect_file_container("testfiles/nonexistent").is_err());
+    }
+
+    #[test]
+    fn test_decrypted_path() {
+        assert_eq!(decrypted_path("dir/file.txt.enc", FILENAME_EXTENSION), "dir/file.txt");
+        assert_eq!(decrypted_path("file.txt.sfe", FILENAME_EXTENSION), "file.txt");
+        assert_eq!(decrypted_path("file.txt.crypt", "crypt"), "file.txt");
+        assert_eq!(decrypted_path("file.txt.gpg", FILENAME_EXTENSION), "file.txt");
+        assert_eq!(decrypted_path("file", FILENAME_EXTENSION), "file.decrypted");
+        assert_eq!(decrypted_path("file.pdf", FILENAME_EXTENSION), "file.pdf.decrypted");
+        assert_eq!(decrypted_path(".enc", FILENAME_EXTENSION), ".enc.decrypted");
+    }
+}
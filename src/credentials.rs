@@ -0,0 +1,337 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::kdf_cost::KdfCost;
+use crate::crypto_parameters::key_size::KeySize;
+use crate::header::{Keyslot, FLAG_IDENTITY, FLAG_KEYFILE, FLAG_PASSWORD, FLAG_RAW_KEY, SALT_SIZE};
+use crate::key_derivation;
+use crate::secret::Secret;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha3::digest::generic_array::GenericArray;
+use sha3::{Digest, Sha3_512};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::sync::Mutex;
+use x25519_dalek::PublicKey;
+use zeroize::Zeroize;
+
+/// Salts are shared by a whole run or by the files of one archive, so a few
+/// keys cover every repetition.
+const DERIVED_KEY_CACHE_SIZE : usize = 16;
+
+/// What the key of a file is derived from: a password, a keyfile or both.
+/// Alternatively, a raw key that is used without any derivation, or X25519
+/// recipients for encryption and an identity for decryption.
+///
+/// Keys derived from the credentials are cached by salt, so files sharing a
+/// salt only run the KDF once, even when several threads use them. Only the
+/// most recently used keys are kept, since files with salts of their own
+/// never hit the cache.
+pub struct Credentials {
+    password : Option<Secret>,
+    keyfile_hash : Option<Secret>,
+    raw_key : Option<Secret>,
+    recipients : Vec<PublicKey>,
+    identity : Option<Secret>,
+    shared_salt : Option<[u8; SALT_SIZE]>,
+    derived_keys : Mutex<Vec<DerivedKey>>,
+    shared_keyslot : Mutex<Option<SharedKeyslot>>,
+}
+
+struct DerivedKey {
+    flags : u32,
+    salt : Vec<u8>,
+    kdf_cost : KdfCost,
+    key_size : KeySize,
+    key : Secret,
+}
+
+/// A payload key and its keyslot, shared by all files of a run.
+struct SharedKeyslot {
+    parameters : CryptoParameters,
+    key : Secret,
+    keyslot : Keyslot,
+}
+
+impl Credentials {
+    pub fn new(password : Option<Secret>, keyfile_hash : Option<Secret>) -> Credentials {
+        Credentials{
+            password,
+            keyfile_hash,
+            raw_key : None,
+            recipients : Vec::new(),
+            identity : None,
+            shared_salt : None,
+            derived_keys : Mutex::new(Vec::new()),
+            shared_keyslot : Mutex::new(None),
+        }
+    }
+
+    pub fn from_raw_key(raw_key : Secret) -> Credentials {
+        Credentials{
+            raw_key : Some(raw_key),
+            ..Credentials::new(None, None)
+        }
+    }
+
+    pub fn for_recipients(recipients : Vec<PublicKey>) -> Credentials {
+        Credentials{
+            recipients,
+            ..Credentials::new(None, None)
+        }
+    }
+
+    pub fn from_identity(identity : Secret) -> Credentials {
+        Credentials{
+            identity : Some(identity),
+            ..Credentials::new(None, None)
+        }
+    }
+
+    pub fn recipients(&self) -> &[PublicKey] {
+        &self.recipients
+    }
+
+    pub fn identity(&self) -> Option<&Secret> {
+        self.identity.as_ref()
+    }
+
+    pub fn password(&self) -> Option<&Secret> {
+        self.password.as_ref()
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    pub fn set_password(&mut self, password : Secret) {
+        self.password = Some(password);
+    }
+
+    /// Makes every file encrypted with these credentials use the same salt
+    /// and, with keyslots, the same payload key, so the KDF runs only once.
+    pub fn share_salt(&mut self) {
+        let mut salt : [u8; SALT_SIZE] = [0x00; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        self.shared_salt = Some(salt);
+    }
+
+    pub fn shared_salt(&self) -> Option<[u8; SALT_SIZE]> {
+        self.shared_salt
+    }
+
+    /// Derives a key from the key material for `flags`, or takes it from the
+    /// cache if it was derived with the same salt recently.
+    pub fn derive_key(&self, flags : u32, salt : &[u8], kdf_cost : KdfCost, key_size : KeySize) -> Option<Secret> {
+        let matches = |derived : &DerivedKey| derived.flags == flags && derived.salt == salt
+            && derived.kdf_cost == kdf_cost && derived.key_size == key_size;
+        {
+            let mut derived_keys = self.derived_keys.lock().unwrap();
+            if let Some(index) = derived_keys.iter().position(matches) {
+                let derived : DerivedKey = derived_keys.remove(index);
+                let key : Secret = derived.key.clone();
+                derived_keys.push(derived);
+                return Some(key);
+            }
+        }
+        // The lock is not held while deriving, so threads with different
+        // salts do not wait for each other.
+        let key : Secret = key_derivation::derive_key(&self.key_material(flags)?, salt, kdf_cost, key_size)?;
+        let mut derived_keys = self.derived_keys.lock().unwrap();
+        if derived_keys.len() == DERIVED_KEY_CACHE_SIZE {
+            derived_keys.remove(0);
+        }
+        derived_keys.push(DerivedKey{
+            flags,
+            salt : salt.to_vec(),
+            kdf_cost,
+            key_size,
+            key : key.clone(),
+        });
+        Some(key)
+    }
+
+    /// The payload key and keyslot shared by all files encrypted with
+    /// `parameters`, created by `create` for the first one.
+    pub fn shared_keyslot<F>(&self, parameters : CryptoParameters, create : F) -> Option<(Secret, Keyslot)>
+        where F : FnOnce() -> Option<(Secret, Keyslot)> {
+        let mut shared = self.shared_keyslot.lock().unwrap();
+        match shared.as_ref() {
+            Some(shared) if shared.parameters == parameters => Some((shared.key.clone(), shared.keyslot.clone())),
+            Some(_) => create(),
+            None => {
+                let (key, keyslot) = create()?;
+                *shared = Some(SharedKeyslot{parameters, key : key.clone(), keyslot : keyslot.clone()});
+                Some((key, keyslot))
+            }
+        }
+    }
+
+    /// The header flags describing these credentials.
+    pub fn flags(&self) -> u32 {
+        let mut flags : u32 = 0;
+        if self.password.is_some() {
+            flags |= FLAG_PASSWORD;
+        }
+        if self.keyfile_hash.is_some() {
+            flags |= FLAG_KEYFILE;
+        }
+        if self.raw_key.is_some() {
+            flags |= FLAG_RAW_KEY;
+        }
+        if !self.recipients.is_empty() || self.identity.is_some() {
+            flags |= FLAG_IDENTITY;
+        }
+        flags
+    }
+
+    /// The input to the key derivation for a file with the given header flags,
+    /// or `None` if a required credential is missing. The keyfile hash has a
+    /// fixed length, so appending it to the password is unambiguous.
+    pub fn key_material(&self, flags : u32) -> Option<Secret> {
+        if flags & FLAG_RAW_KEY != 0 {
+            return self.raw_key.as_ref().map(|raw_key| Secret::new(raw_key.expose().to_vec()));
+        }
+        let mut key_material : Vec<u8> = Vec::new();
+        let password : Option<&Secret> = self.password.as_ref().filter(|_| flags & FLAG_PASSWORD != 0);
+        let keyfile_hash : Option<&Secret> = self.keyfile_hash.as_ref().filter(|_| flags & FLAG_KEYFILE != 0);
+        if (flags & FLAG_PASSWORD != 0 && password.is_none()) || (flags & FLAG_KEYFILE != 0 && keyfile_hash.is_none()) {
+            return None;
+        }
+        let length : usize = password.map_or(0, |secret| secret.expose().len())
+            + keyfile_hash.map_or(0, |secret| secret.expose().len());
+        key_material.reserve_exact(length);
+        if let Some(password) = password {
+            key_material.extend_from_slice(password.expose());
+        }
+        if let Some(keyfile_hash) = keyfile_hash {
+            key_material.extend_from_slice(keyfile_hash.expose());
+        }
+        Some(Secret::new(key_material))
+    }
+}
+
+impl From<Secret> for Credentials {
+    fn from(password : Secret) -> Credentials {
+        Credentials::new(Some(password), None)
+    }
+}
+
+/// Hashes the content of a keyfile with SHA3-512.
+pub fn hash_keyfile(path : &str) -> io::Result<Secret> {
+    let mut file : File = File::open(path)?;
+    let mut hasher = Sha3_512::new();
+    let mut buffer : [u8; 4096] = [0x00; 4096];
+    loop {
+        let read : usize = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[0..read]);
+    }
+    buffer.zeroize();
+    // Finalizing into the vector the secret takes over leaves no copy of the
+    // hash behind on the stack.
+    let mut hash : Vec<u8> = vec![0x00; Sha3_512::output_size()];
+    hasher.finalize_into(GenericArray::from_mut_slice(&mut hash));
+    Ok(Secret::new(hash))
+}
+
+/// Describes the credentials required by the given header flags.
+pub fn describe_flags(flags : u32) -> &'static str {
+    if flags & FLAG_RAW_KEY != 0 {
+        return "a raw key";
+    }
+    if flags & FLAG_IDENTITY != 0 {
+        return "an identity";
+    }
+    match (flags & FLAG_PASSWORD != 0, flags & FLAG_KEYFILE != 0) {
+        (true, true) => "a password and a keyfile",
+        (false, true) => "a keyfile",
+        _ => "a password",
+    }
+}
+
+#[cfg(test)]
+mod credentials_tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_flags() {
+        let password = || Some(Secret::from("password".to_string()));
+        let keyfile_hash = || Some(Secret::new(vec![0x42; 64]));
+        assert_eq!(Credentials::new(password(), None).flags(), FLAG_PASSWORD);
+        assert_eq!(Credentials::new(None, keyfile_hash()).flags(), FLAG_KEYFILE);
+        assert_eq!(Credentials::new(password(), keyfile_hash()).flags(), FLAG_PASSWORD | FLAG_KEYFILE);
+        assert_eq!(Credentials::from_raw_key(Secret::new(vec![0x42; 32])).flags(), FLAG_RAW_KEY);
+        assert_eq!(Credentials::from_identity(Secret::new(vec![0x42; 32])).flags(), FLAG_IDENTITY);
+        assert_eq!(Credentials::for_recipients(Vec::new()).flags(), 0);
+    }
+
+    #[test]
+    fn test_key_material() {
+        let credentials : Credentials = Credentials::new(
+            Some(Secret::from("password".to_string())), Some(Secret::new(vec![0x42; 64])));
+        let both : Secret = credentials.key_material(FLAG_PASSWORD | FLAG_KEYFILE).unwrap();
+        assert_eq!(both.expose()[0..8], *b"password");
+        assert_eq!(both.expose()[8..], [0x42; 64]);
+        assert_eq!(credentials.key_material(FLAG_PASSWORD).unwrap().expose(), b"password");
+        assert_eq!(credentials.key_material(FLAG_KEYFILE).unwrap().expose(), [0x42; 64]);
+    }
+
+    #[test]
+    fn test_key_material_missing_credential() {
+        let credentials : Credentials = Credentials::from(Secret::from("password".to_string()));
+        assert!(credentials.key_material(FLAG_KEYFILE).is_none());
+        assert!(credentials.key_material(FLAG_PASSWORD | FLAG_KEYFILE).is_none());
+        assert!(credentials.key_material(FLAG_RAW_KEY).is_none());
+    }
+
+    #[test]
+    fn test_derived_key_cache_is_bounded() {
+        let credentials : Credentials = Credentials::from(Secret::from("password".to_string()));
+        let kdf_cost : KdfCost = KdfCost::Pbkdf2HmacSha3512{iterations : 10_000};
+        let derive = |salt : u8| credentials.derive_key(FLAG_PASSWORD, &[salt; SALT_SIZE], kdf_cost, KeySize::Size256)
+            .unwrap();
+        let first : Secret = derive(0);
+        for salt in 1..DERIVED_KEY_CACHE_SIZE as u8 {
+            derive(salt);
+        }
+        // Using the first key again keeps it when the next one is added.
+        assert_eq!(derive(0).expose(), first.expose());
+        derive(DERIVED_KEY_CACHE_SIZE as u8);
+        let derived_keys = credentials.derived_keys.lock().unwrap();
+        assert_eq!(derived_keys.len(), DERIVED_KEY_CACHE_SIZE);
+        assert!(derived_keys.iter().any(|derived| derived.salt == [0; SALT_SIZE]));
+        assert!(derived_keys.iter().all(|derived| derived.salt != [1; SALT_SIZE]));
+    }
+
+    #[test]
+    fn test_hash_keyfile() {
+        let path : &str = "testfiles/hash_keyfile.key";
+        fs::write(path, b"GNAMPF!").unwrap();
+        let hash : Secret = hash_keyfile(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(hash.expose(), Sha3_512::digest(b"GNAMPF!").as_slice());
+        assert!(hash_keyfile("testfiles/nonexistent.key").is_err());
+    }
+}
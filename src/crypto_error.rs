@@ -0,0 +1,67 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::credentials::describe_flags;
+use crate::overwrite::OverwritePolicy;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum CryptoError {
+    FileLoading(String),
+    InvalidHeader(String),
+    AuthenticationFailed(String),
+    CredentialsMismatch(String, u32),
+    InvalidKey(String),
+    NoMatchingRecipient(String),
+    UnsupportedParameters(String),
+    NoKeyslots(String),
+    NoSuchKeyslot(String, usize),
+    LastKeyslot(String),
+    Encryption(String),
+    Io(String),
+    InvalidArchive(String),
+    DestinationExists(String, OverwritePolicy),
+    OverwriteFailed(String, OverwritePolicy),
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoError::FileLoading(path) => write!(f, "{}: could not open source or create destination", path),
+            CryptoError::InvalidHeader(path) => write!(f, "{}: not a valid sfe file", path),
+            CryptoError::AuthenticationFailed(path) => write!(f, "{}: wrong password or corrupted file", path),
+            CryptoError::CredentialsMismatch(path, flags) => write!(f, "{}: requires {}", path, describe_flags(*flags)),
+            CryptoError::InvalidKey(path) => write!(f, "{}: key does not match the key size", path),
+            CryptoError::NoMatchingRecipient(path) => write!(f, "{}: not encrypted for this identity", path),
+            CryptoError::UnsupportedParameters(path) => write!(f, "{}: parameters do not fit the credentials", path),
+            CryptoError::NoKeyslots(path) => write!(f, "{}: file has no keyslots", path),
+            CryptoError::NoSuchKeyslot(path, index) => write!(f, "{}: there is no keyslot {}", path, index),
+            CryptoError::LastKeyslot(path) => write!(f, "{}: the last keyslot cannot be removed", path),
+            CryptoError::Encryption(path) => write!(f, "{}: encryption failed", path),
+            CryptoError::Io(path) => write!(f, "{}: could not write destination", path),
+            CryptoError::InvalidArchive(path) => write!(f, "{}: archive could not be unpacked", path),
+            CryptoError::DestinationExists(path, policy) =>
+                write!(f, "{}: already exists and the overwrite policy is {}", path, policy),
+            CryptoError::OverwriteFailed(path, policy) =>
+                write!(f, "{}: could not be replaced with overwrite policy {}", path, policy),
+        }
+    }
+}
+
+impl Error for CryptoError{}
@@ -17,14 +17,16 @@
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
 
-#[derive(PartialEq, Debug)]
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum BlockCipher {
     Aes,
     Camellia,
 }
 
 impl BlockCipher {
-    pub fn to_integer(&self) -> u32 {
+    pub fn to_integer(self) -> u32 {
         match self {
             BlockCipher::Aes => 1,
             BlockCipher::Camellia => 2,
@@ -38,6 +40,23 @@ impl BlockCipher {
             _ => None,
         }
     }
+
+    pub fn from_name(name : &str) -> Option<BlockCipher> {
+        match name {
+            "aes" => Some(BlockCipher::Aes),
+            "camellia" => Some(BlockCipher::Camellia),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BlockCipher {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockCipher::Aes => write!(f, "AES"),
+            BlockCipher::Camellia => write!(f, "Camellia"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -57,4 +76,11 @@ mod block_cipher_tests {
         assert_eq!(BlockCipher::from_integer(2).unwrap(), BlockCipher::Camellia);
         assert_eq!(BlockCipher::from_integer(3), None);
     }
+
+    #[test]
+    fn test_block_cipher_from_name() {
+        assert_eq!(BlockCipher::from_name("aes").unwrap(), BlockCipher::Aes);
+        assert_eq!(BlockCipher::from_name("camellia").unwrap(), BlockCipher::Camellia);
+        assert_eq!(BlockCipher::from_name("des"), None);
+    }
 }
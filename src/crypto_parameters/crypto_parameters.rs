@@ -18,23 +18,18 @@
  */
 use crate::crypto_parameters::block_cipher::BlockCipher;
 use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::kdf_cost::{KdfCost, KDF_COST_SIZE};
+use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
 use crate::crypto_parameters::key_size::KeySize;
 use crate::crypto_parameters::operation_mode::OperationMode;
+use crate::crypto_parameters::parameter_error::ParameterError;
 
 fn u32_to_byte_array(integer : u32) -> [u8; 4] {
-    let mut byte_array : [u8; 4] = [0xFF ; 4];
-    for i in 0..4 {
-        byte_array[i] = ((integer >> (3 - i) * 8) & 0xFF) as u8;
-    }
-    byte_array
+    integer.to_be_bytes()
 }
 
 fn byte_array_to_u32(byte_array : &[u8; 4]) -> u32 {
-    let mut integer : u32 = 0x00000000;
-    for i in 0..4 {
-        integer |= (byte_array[i] as u32) << ((3 - i) * 8);
-    }
-    integer
+    u32::from_be_bytes(*byte_array)
 }
 
 #[cfg(test)]
@@ -58,30 +53,100 @@ mod utility_function_tests {
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct CryptoParameters {
     block_cipher : BlockCipher,
-    kdf : Kdf,
+    kdf_cost : KdfCost,
     key_size : KeySize,
     operation_mode : OperationMode,
+    key_encapsulation : KeyEncapsulation,
+}
+
+impl Default for CryptoParameters {
+    fn default() -> CryptoParameters {
+        CryptoParameters{
+            block_cipher : BlockCipher::Aes,
+            kdf_cost : KdfCost::default_for(Kdf::Argon2id),
+            key_size : KeySize::Size256,
+            operation_mode : OperationMode::ChunkedGcm,
+            key_encapsulation : KeyEncapsulation::Keyslots,
+        }
+    }
 }
 
 impl CryptoParameters {
-    pub fn to_byte_buffer(&self) -> [u8; 16] {
-        let mut byte_buffer : [u8; 16] = [0xFF; 16];
+    /// Runs `kdf` with its default costs. Nothing is validated, so outside
+    /// the crate parameters are made with `builder`.
+    pub(crate) fn new(block_cipher : BlockCipher, kdf : Kdf, key_size : KeySize,
+        operation_mode : OperationMode, key_encapsulation : KeyEncapsulation) -> CryptoParameters {
+        CryptoParameters{
+            block_cipher,
+            kdf_cost : KdfCost::default_for(kdf),
+            key_size,
+            operation_mode,
+            key_encapsulation,
+        }
+    }
+
+    /// Starts from the defaults: AES-256 in the chunked GCM mode with password
+    /// keyslots protected by Argon2id with its default costs.
+    pub fn builder() -> CryptoParametersBuilder {
+        CryptoParametersBuilder::from(CryptoParameters::default())
+    }
+
+    /// Checks that the KDF costs are in bounds and that the KDF fits the way
+    /// the payload key is encapsulated.
+    pub fn validate(&self) -> Result<(), ParameterError> {
+        if !self.kdf_cost.is_valid() {
+            return Err(ParameterError::InvalidKdfCost(self.kdf_cost));
+        }
+        match (self.key_encapsulation, self.kdf()) {
+            (KeyEncapsulation::Keyslots, Kdf::None) => Err(ParameterError::KeyslotsWithoutKdf),
+            (KeyEncapsulation::X25519, kdf) if kdf != Kdf::None => Err(ParameterError::RecipientsWithKdf(kdf)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn block_cipher(&self) -> BlockCipher {
+        self.block_cipher
+    }
+
+    pub fn kdf(&self) -> Kdf {
+        self.kdf_cost.kdf()
+    }
+
+    pub fn kdf_cost(&self) -> KdfCost {
+        self.kdf_cost
+    }
+
+    pub fn key_size(&self) -> KeySize {
+        self.key_size
+    }
+
+    pub fn operation_mode(&self) -> OperationMode {
+        self.operation_mode
+    }
+
+    pub fn key_encapsulation(&self) -> KeyEncapsulation {
+        self.key_encapsulation
+    }
+
+    /// The five parameters followed by the KDF costs.
+    pub fn to_byte_buffer(self) -> [u8; 32] {
+        let mut byte_buffer : [u8; 32] = [0xFF; 32];
         
-        let buffers : [[u8; 4]; 4] = [
+        let buffers : [[u8; 4]; 5] = [
             self.get_block_cipher_byte_buffer(),
             self.get_kdf_byte_buffer(),
             self.get_key_size_byte_buffer(),
             self.get_operation_mode_byte_buffer(),
+            self.get_key_encapsulation_byte_buffer(),
         ];
 
-        for i in 0..4 {
-            for j in 0..4 {
-                byte_buffer[j + i * 4] = buffers[i][j];
-            }
+        for (i, buffer) in buffers.iter().enumerate() {
+            byte_buffer[i * 4..i * 4 + 4].copy_from_slice(buffer);
         }
+        byte_buffer[20..].copy_from_slice(&self.kdf_cost.to_byte_buffer());
         
         byte_buffer
     }
@@ -92,7 +157,7 @@ impl CryptoParameters {
     }
 
     fn get_kdf_byte_buffer(&self) -> [u8; 4] {
-        let kdf_integer = self.kdf.to_integer();
+        let kdf_integer = self.kdf().to_integer();
         u32_to_byte_array(kdf_integer)
     }
 
@@ -106,52 +171,98 @@ impl CryptoParameters {
         u32_to_byte_array(operation_mode_integer)
     }
 
-    pub fn from_byte_buffer(byte_buffer : &[u8; 16]) -> Option<CryptoParameters> {
-        let block_cipher_integer : u32 = Self::get_sub_array_at_offset_as_u32(&byte_buffer, 0);
-        let block_cipher : Option<BlockCipher> = 
-            BlockCipher::from_integer(block_cipher_integer);
-        if block_cipher == None {
-            return None;
-        }
+    fn get_key_encapsulation_byte_buffer(&self) -> [u8; 4] {
+        let key_encapsulation_integer = self.key_encapsulation.to_integer();
+        u32_to_byte_array(key_encapsulation_integer)
+    }
+
+    pub fn from_byte_buffer(byte_buffer : &[u8; 32]) -> Option<CryptoParameters> {
+        let block_cipher_integer : u32 = Self::get_sub_array_at_offset_as_u32(byte_buffer, 0);
+        let block_cipher : BlockCipher = BlockCipher::from_integer(block_cipher_integer)?;
 
         let kdf_integer : u32 = Self::get_sub_array_at_offset_as_u32(byte_buffer, 4);
-        let kdf : Option<Kdf> = 
-            Kdf::from_integer(kdf_integer);
-        if kdf == None {
-            return None;
-        }
+        let kdf : Kdf = Kdf::from_integer(kdf_integer)?;
+        let mut kdf_cost_buffer : [u8; KDF_COST_SIZE] = [0x00; KDF_COST_SIZE];
+        kdf_cost_buffer.copy_from_slice(&byte_buffer[20..]);
+        let kdf_cost : KdfCost = KdfCost::from_byte_buffer(kdf, &kdf_cost_buffer)?;
 
         let key_size_integer : u32 = Self::get_sub_array_at_offset_as_u32(byte_buffer, 8);
-        let key_size : Option<KeySize> = 
-            KeySize::from_integer(key_size_integer);
-        if key_size == None {
-            return None;
-        }
+        let key_size : KeySize = KeySize::from_integer(key_size_integer)?;
         
         let operation_mode_integer : u32 = Self::get_sub_array_at_offset_as_u32(byte_buffer, 12);
-        let operation_mode : Option<OperationMode> = 
-            OperationMode::from_integer(operation_mode_integer);
-        if operation_mode == None {
-            return None;
-        }
+        let operation_mode : OperationMode = OperationMode::from_integer(operation_mode_integer)?;
+
+        let key_encapsulation_integer : u32 = Self::get_sub_array_at_offset_as_u32(byte_buffer, 16);
+        let key_encapsulation : KeyEncapsulation = KeyEncapsulation::from_integer(key_encapsulation_integer)?;
 
         Some(CryptoParameters{
-            block_cipher : block_cipher.unwrap(),
-            kdf : kdf.unwrap(),
-            key_size : key_size.unwrap(),
-            operation_mode : operation_mode.unwrap(),
+            block_cipher,
+            kdf_cost,
+            key_size,
+            operation_mode,
+            key_encapsulation,
         })
     }
 
-    fn get_sub_array_at_offset_as_u32(array : &[u8; 16], offset : usize) -> u32 {
+    fn get_sub_array_at_offset_as_u32(array : &[u8; 32], offset : usize) -> u32 {
         let mut sub_array : [u8; 4] = [0xFF; 4];
-        for i in 0..4 {
-            sub_array[i] = array[i + offset];
-        }
+        sub_array.copy_from_slice(&array[offset..offset + 4]);
         byte_array_to_u32(&sub_array)
     }
 }
 
+/// Changes single parameters and validates the result in `build`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct CryptoParametersBuilder {
+    parameters : CryptoParameters,
+}
+
+impl From<CryptoParameters> for CryptoParametersBuilder {
+    fn from(parameters : CryptoParameters) -> CryptoParametersBuilder {
+        CryptoParametersBuilder{
+            parameters,
+        }
+    }
+}
+
+impl CryptoParametersBuilder {
+    pub fn block_cipher(mut self, block_cipher : BlockCipher) -> CryptoParametersBuilder {
+        self.parameters.block_cipher = block_cipher;
+        self
+    }
+
+    /// Switches to `kdf` with its default costs.
+    pub fn kdf(mut self, kdf : Kdf) -> CryptoParametersBuilder {
+        self.parameters.kdf_cost = KdfCost::default_for(kdf);
+        self
+    }
+
+    pub fn kdf_cost(mut self, kdf_cost : KdfCost) -> CryptoParametersBuilder {
+        self.parameters.kdf_cost = kdf_cost;
+        self
+    }
+
+    pub fn key_size(mut self, key_size : KeySize) -> CryptoParametersBuilder {
+        self.parameters.key_size = key_size;
+        self
+    }
+
+    pub fn operation_mode(mut self, operation_mode : OperationMode) -> CryptoParametersBuilder {
+        self.parameters.operation_mode = operation_mode;
+        self
+    }
+
+    pub fn key_encapsulation(mut self, key_encapsulation : KeyEncapsulation) -> CryptoParametersBuilder {
+        self.parameters.key_encapsulation = key_encapsulation;
+        self
+    }
+
+    pub fn build(self) -> Result<CryptoParameters, ParameterError> {
+        self.parameters.validate()?;
+        Ok(self.parameters)
+    }
+}
+
 #[cfg(test)]
 mod crypto_parameters_tests {
     use super::*;
@@ -160,71 +271,134 @@ mod crypto_parameters_tests {
     fn test_crypto_parameters_to_byte_buffer() {
         let parameters : CryptoParameters = CryptoParameters{
             block_cipher : BlockCipher::Camellia,
-            kdf : Kdf::Pbkdf2HmacSha3512,
+            kdf_cost : KdfCost::default_for(Kdf::Pbkdf2HmacSha3512),
             key_size : KeySize::Size256,
             operation_mode : OperationMode::Gcm,
+            key_encapsulation : KeyEncapsulation::X25519,
         };
-        let expected_array : [u8; 16] = 
+        let expected_array : [u8; 32] = 
             [0x00, 0x00, 0x00, 0x02
             ,0x00, 0x00, 0x00, 0x01
             ,0x00, 0x00, 0x01, 0x00
-            ,0x00, 0x00, 0x00, 0x01];
-        let actual_array : [u8; 16] = parameters.to_byte_buffer();
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x00, 0x02
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x00];
+        let actual_array : [u8; 32] = parameters.to_byte_buffer();
         assert_eq!(expected_array, actual_array);
     }
 
     #[test]
     fn test_crypto_parameters_from_byte_buffer_wrong_block_cipher() {
-        let byte_buffer : [u8; 16] = 
+        let byte_buffer : [u8; 32] = 
             [0x00, 0x00, 0x00, 0x00
             ,0x00, 0x00, 0x00, 0x01
             ,0x00, 0x00, 0x01, 0x00
-            ,0x00, 0x00, 0x00, 0x01];
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x00];
         assert_eq!(CryptoParameters::from_byte_buffer(&byte_buffer), None);
     }
     
     #[test]
     fn test_crypto_parameters_from_byte_buffer_wrong_kdf() {
-        let byte_buffer : [u8; 16] = 
+        let byte_buffer : [u8; 32] = 
             [0x00, 0x00, 0x00, 0x02
             ,0x00, 0x00, 0x00, 0x00
             ,0x00, 0x00, 0x01, 0x00
-            ,0x00, 0x00, 0x00, 0x01];
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x00];
         assert_eq!(CryptoParameters::from_byte_buffer(&byte_buffer), None);
     }
     
     #[test]
     fn test_crypto_parameters_from_byte_buffer_wrong_key_size() {
-        let byte_buffer : [u8; 16] = 
+        let byte_buffer : [u8; 32] = 
             [0x00, 0x00, 0x00, 0x02
             ,0x00, 0x00, 0x00, 0x01
             ,0x00, 0x00, 0x00, 0xFF
-            ,0x00, 0x00, 0x00, 0x01];
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x00];
         assert_eq!(CryptoParameters::from_byte_buffer(&byte_buffer), None);
     }
     
     #[test]
     fn test_crypto_parameters_from_byte_buffer_wrong_operation_mode() {
-        let byte_buffer : [u8; 16] = 
+        let byte_buffer : [u8; 32] = 
             [0x00, 0x00, 0x00, 0x02
             ,0x00, 0x00, 0x00, 0x01
             ,0x00, 0x00, 0x01, 0x00
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
             ,0x00, 0x00, 0x00, 0x00];
         assert_eq!(CryptoParameters::from_byte_buffer(&byte_buffer), None);
     }
     
     #[test]
     fn test_crypto_parameters_from_byte_buffer() {
-        let byte_buffer : [u8; 16] = 
+        let byte_buffer : [u8; 32] = 
             [0x00, 0x00, 0x00, 0x02
             ,0x00, 0x00, 0x00, 0x01
             ,0x00, 0x00, 0x01, 0x00
-            ,0x00, 0x00, 0x00, 0x01];
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x00];
         let parameters : CryptoParameters = 
             CryptoParameters::from_byte_buffer(&byte_buffer).unwrap();
         assert_eq!(parameters.block_cipher, BlockCipher::Camellia);
-        assert_eq!(parameters.kdf, Kdf::Pbkdf2HmacSha3512);
+        assert_eq!(parameters.kdf_cost, KdfCost::default_for(Kdf::Pbkdf2HmacSha3512));
         assert_eq!(parameters.key_size, KeySize::Size256);
         assert_eq!(parameters.operation_mode, OperationMode::Gcm);
+        assert_eq!(parameters.key_encapsulation, KeyEncapsulation::None);
+    }
+
+    #[test]
+    fn test_crypto_parameters_from_byte_buffer_wrong_key_encapsulation() {
+        let byte_buffer : [u8; 32] = 
+            [0x00, 0x00, 0x00, 0x02
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x01, 0x00
+            ,0x00, 0x00, 0x00, 0x01
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x03, 0x34, 0x50
+            ,0x00, 0x00, 0x00, 0x00
+            ,0x00, 0x00, 0x00, 0x00];
+        assert_eq!(CryptoParameters::from_byte_buffer(&byte_buffer), None);
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        assert_eq!(CryptoParameters::builder().build().unwrap(), CryptoParameters::default());
+        let parameters : CryptoParameters = CryptoParameters::builder()
+            .block_cipher(BlockCipher::Camellia)
+            .key_size(KeySize::Size128)
+            .operation_mode(OperationMode::Gcm)
+            .build().unwrap();
+        assert_eq!(parameters, CryptoParameters::new(BlockCipher::Camellia, Kdf::Argon2id,
+            KeySize::Size128, OperationMode::Gcm, KeyEncapsulation::Keyslots));
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        assert_eq!(CryptoParameters::builder().kdf(Kdf::None).build(), Err(ParameterError::KeyslotsWithoutKdf));
+        assert_eq!(CryptoParameters::builder().key_encapsulation(KeyEncapsulation::X25519).build(),
+            Err(ParameterError::RecipientsWithKdf(Kdf::Argon2id)));
+        assert!(CryptoParameters::builder().key_encapsulation(KeyEncapsulation::X25519).kdf(Kdf::None).build().is_ok());
+        assert!(CryptoParameters::builder().key_encapsulation(KeyEncapsulation::None).kdf(Kdf::None).build().is_ok());
+        let kdf_cost : KdfCost = KdfCost::Scrypt{log_n : 30, r : 8, p : 1};
+        assert_eq!(CryptoParameters::builder().kdf_cost(kdf_cost).build(), Err(ParameterError::InvalidKdfCost(kdf_cost)));
     }
 }
@@ -16,26 +16,61 @@
  * You should have received a copy of the GNU General Public License
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
-#[derive(PartialEq, Debug)]
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Kdf {
     Pbkdf2HmacSha3512,
+    /// Memory-hard, costs far more than PBKDF2 to attack with dedicated hardware.
+    Scrypt,
+    /// Memory-hard like scrypt, with memory and time costs set independently.
+    Argon2id,
+    /// The key is used as is, without any derivation.
+    None,
 }
 
 impl Kdf {
-    pub fn to_integer(&self) -> u32 {
+    pub fn to_integer(self) -> u32 {
         match self {
             Kdf::Pbkdf2HmacSha3512 => 1,
+            Kdf::None => 2,
+            Kdf::Scrypt => 3,
+            Kdf::Argon2id => 4,
         }
     }
 
     pub fn from_integer(integer : u32) -> Option<Kdf> {
         match integer {
             1 => Some(Kdf::Pbkdf2HmacSha3512),
+            2 => Some(Kdf::None),
+            3 => Some(Kdf::Scrypt),
+            4 => Some(Kdf::Argon2id),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Kdf> {
+        match name {
+            "pbkdf2-hmac-sha3-512" => Some(Kdf::Pbkdf2HmacSha3512),
+            "scrypt" => Some(Kdf::Scrypt),
+            "argon2id" => Some(Kdf::Argon2id),
+            "none" => Some(Kdf::None),
             _ => None,
         }
     }
 }
 
+impl fmt::Display for Kdf {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kdf::Pbkdf2HmacSha3512 => write!(f, "PBKDF2-HMAC-SHA3-512"),
+            Kdf::Scrypt => write!(f, "scrypt"),
+            Kdf::Argon2id => write!(f, "Argon2id"),
+            Kdf::None => write!(f, "none"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod kdf_tests {
     use super::*;
@@ -43,12 +78,27 @@ mod kdf_tests {
     #[test]
     fn test_kdf_to_integer() {
         assert_eq!(Kdf::Pbkdf2HmacSha3512.to_integer(), 1);
+        assert_eq!(Kdf::None.to_integer(), 2);
+        assert_eq!(Kdf::Scrypt.to_integer(), 3);
+        assert_eq!(Kdf::Argon2id.to_integer(), 4);
     }
     
     #[test]
     fn test_kdf_from_integer() {
         assert_eq!(Kdf::from_integer(0), None);
         assert_eq!(Kdf::from_integer(1).unwrap(), Kdf::Pbkdf2HmacSha3512);
-        assert_eq!(Kdf::from_integer(2), None);
+        assert_eq!(Kdf::from_integer(2).unwrap(), Kdf::None);
+        assert_eq!(Kdf::from_integer(3).unwrap(), Kdf::Scrypt);
+        assert_eq!(Kdf::from_integer(4).unwrap(), Kdf::Argon2id);
+        assert_eq!(Kdf::from_integer(5), None);
+    }
+
+    #[test]
+    fn test_kdf_from_name() {
+        assert_eq!(Kdf::from_name("pbkdf2-hmac-sha3-512").unwrap(), Kdf::Pbkdf2HmacSha3512);
+        assert_eq!(Kdf::from_name("none").unwrap(), Kdf::None);
+        assert_eq!(Kdf::from_name("scrypt").unwrap(), Kdf::Scrypt);
+        assert_eq!(Kdf::from_name("argon2id").unwrap(), Kdf::Argon2id);
+        assert_eq!(Kdf::from_name("argon2"), None);
     }
 }
@@ -0,0 +1,191 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::kdf::Kdf;
+use std::fmt;
+
+pub const KDF_COST_SIZE : usize = 12;
+
+pub const PBKDF2_ITERATIONS : u32 = 210_000;
+/// 2^20 blocks of 1 KiB with r = 8, so every guess needs 1 GiB of memory.
+pub const SCRYPT_LOG_N : u8 = 20;
+pub const SCRYPT_R : u32 = 8;
+pub const SCRYPT_P : u32 = 1;
+/// 64 MiB over three passes, the second recommendation of RFC 9106.
+pub const ARGON2ID_MEMORY_KIB : u32 = 65_536;
+pub const ARGON2ID_ITERATIONS : u32 = 3;
+pub const ARGON2ID_PARALLELISM : u32 = 4;
+
+/// Costs are read from untrusted headers, so they are capped to keep a
+/// crafted file from making decryption run for days or exhaust memory.
+const MAXIMUM_MEMORY : u64 = 4 << 30;
+
+/// A KDF together with the cost parameters it runs with. The costs are stored
+/// in the header, so decryption never depends on the defaults of the version
+/// or configuration that encrypted a file.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum KdfCost {
+    Pbkdf2HmacSha3512{
+        iterations : u32,
+    },
+    Scrypt{
+        log_n : u8,
+        r : u32,
+        p : u32,
+    },
+    Argon2id{
+        memory_kib : u32,
+        iterations : u32,
+        parallelism : u32,
+    },
+    None,
+}
+
+impl KdfCost {
+    pub fn default_for(kdf : Kdf) -> KdfCost {
+        match kdf {
+            Kdf::Pbkdf2HmacSha3512 => KdfCost::Pbkdf2HmacSha3512{
+                iterations : PBKDF2_ITERATIONS,
+            },
+            Kdf::Scrypt => KdfCost::Scrypt{
+                log_n : SCRYPT_LOG_N,
+                r : SCRYPT_R,
+                p : SCRYPT_P,
+            },
+            Kdf::Argon2id => KdfCost::Argon2id{
+                memory_kib : ARGON2ID_MEMORY_KIB,
+                iterations : ARGON2ID_ITERATIONS,
+                parallelism : ARGON2ID_PARALLELISM,
+            },
+            Kdf::None => KdfCost::None,
+        }
+    }
+
+    pub fn kdf(self) -> Kdf {
+        match self {
+            KdfCost::Pbkdf2HmacSha3512{..} => Kdf::Pbkdf2HmacSha3512,
+            KdfCost::Scrypt{..} => Kdf::Scrypt,
+            KdfCost::Argon2id{..} => Kdf::Argon2id,
+            KdfCost::None => Kdf::None,
+        }
+    }
+
+    /// Checks the costs against the lower bounds that still make the KDF
+    /// worth running and the upper bounds a header may ask for.
+    pub fn is_valid(self) -> bool {
+        match self {
+            KdfCost::Pbkdf2HmacSha3512{iterations} => (10_000..=100_000_000).contains(&iterations),
+            KdfCost::Scrypt{log_n, r, p} => (10..=24).contains(&log_n) && (1..=32).contains(&r)
+                && (1..=16).contains(&p) && 128 * r as u64 * (1 << log_n) <= MAXIMUM_MEMORY,
+            KdfCost::Argon2id{memory_kib, iterations, parallelism} => (1..=16).contains(&parallelism)
+                && (1..=64).contains(&iterations)
+                && (8 * parallelism..).contains(&memory_kib) && memory_kib as u64 * 1024 <= MAXIMUM_MEMORY,
+            KdfCost::None => true,
+        }
+    }
+
+    pub fn to_byte_buffer(self) -> [u8; KDF_COST_SIZE] {
+        let integers : [u32; 3] = match self {
+            KdfCost::Pbkdf2HmacSha3512{iterations} => [iterations, 0, 0],
+            KdfCost::Scrypt{log_n, r, p} => [log_n as u32, r, p],
+            KdfCost::Argon2id{memory_kib, iterations, parallelism} => [memory_kib, iterations, parallelism],
+            KdfCost::None => [0, 0, 0],
+        };
+        let mut byte_buffer : [u8; KDF_COST_SIZE] = [0x00; KDF_COST_SIZE];
+        for (i, integer) in integers.iter().enumerate() {
+            byte_buffer[i * 4..i * 4 + 4].copy_from_slice(&integer.to_be_bytes());
+        }
+        byte_buffer
+    }
+
+    /// Reads the costs of `kdf`, rejecting costs outside the valid bounds and
+    /// unused fields that are not zero.
+    pub fn from_byte_buffer(kdf : Kdf, byte_buffer : &[u8; KDF_COST_SIZE]) -> Option<KdfCost> {
+        let integer = |i : usize| u32::from_be_bytes([byte_buffer[i * 4], byte_buffer[i * 4 + 1],
+            byte_buffer[i * 4 + 2], byte_buffer[i * 4 + 3]]);
+        let (first, second, third) : (u32, u32, u32) = (integer(0), integer(1), integer(2));
+        let kdf_cost : KdfCost = match kdf {
+            Kdf::Pbkdf2HmacSha3512 if second == 0 && third == 0 => KdfCost::Pbkdf2HmacSha3512{
+                iterations : first,
+            },
+            Kdf::Scrypt => KdfCost::Scrypt{
+                log_n : u8::try_from(first).ok()?,
+                r : second,
+                p : third,
+            },
+            Kdf::Argon2id => KdfCost::Argon2id{
+                memory_kib : first,
+                iterations : second,
+                parallelism : third,
+            },
+            Kdf::None if *byte_buffer == [0x00; KDF_COST_SIZE] => KdfCost::None,
+            _ => return None,
+        };
+        Some(kdf_cost).filter(|kdf_cost| kdf_cost.is_valid())
+    }
+}
+
+impl fmt::Display for KdfCost {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KdfCost::Pbkdf2HmacSha3512{iterations} => write!(f, "{}, {} iterations", self.kdf(), iterations),
+            KdfCost::Scrypt{log_n, r, p} => write!(f, "{}, N = 2^{}, r = {}, p = {}", self.kdf(), log_n, r, p),
+            KdfCost::Argon2id{memory_kib, iterations, parallelism} =>
+                write!(f, "{}, {} KiB, {} iterations, {} lanes", self.kdf(), memory_kib, iterations, parallelism),
+            KdfCost::None => write!(f, "{}", self.kdf()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod kdf_cost_tests {
+    use super::*;
+
+    #[test]
+    fn test_kdf_cost_defaults() {
+        for kdf in [Kdf::Pbkdf2HmacSha3512, Kdf::Scrypt, Kdf::Argon2id, Kdf::None] {
+            assert_eq!(KdfCost::default_for(kdf).kdf(), kdf);
+            assert!(KdfCost::default_for(kdf).is_valid());
+        }
+    }
+
+    #[test]
+    fn test_kdf_cost_round_trip() {
+        let kdf_cost : KdfCost = KdfCost::Argon2id{memory_kib : 1024, iterations : 2, parallelism : 1};
+        let byte_buffer : [u8; KDF_COST_SIZE] = kdf_cost.to_byte_buffer();
+        assert_eq!(byte_buffer, [0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(KdfCost::from_byte_buffer(Kdf::Argon2id, &byte_buffer).unwrap(), kdf_cost);
+        for kdf in [Kdf::Pbkdf2HmacSha3512, Kdf::Scrypt, Kdf::None] {
+            let kdf_cost : KdfCost = KdfCost::default_for(kdf);
+            assert_eq!(KdfCost::from_byte_buffer(kdf, &kdf_cost.to_byte_buffer()).unwrap(), kdf_cost);
+        }
+    }
+
+    #[test]
+    fn test_kdf_cost_bounds() {
+        assert!(!KdfCost::Pbkdf2HmacSha3512{iterations : 1000}.is_valid());
+        assert!(!KdfCost::Scrypt{log_n : 30, r : 8, p : 1}.is_valid());
+        assert!(!KdfCost::Scrypt{log_n : 22, r : 32, p : 1}.is_valid());
+        assert!(!KdfCost::Argon2id{memory_kib : 16, iterations : 1, parallelism : 4}.is_valid());
+        assert!(!KdfCost::Argon2id{memory_kib : 8 << 20, iterations : 1, parallelism : 4}.is_valid());
+        assert!(!KdfCost::Argon2id{memory_kib : 65_536, iterations : 0, parallelism : 4}.is_valid());
+        let unused_field : [u8; KDF_COST_SIZE] = [0x00, 0x03, 0x34, 0x50, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(KdfCost::from_byte_buffer(Kdf::Pbkdf2HmacSha3512, &unused_field), None);
+        assert_eq!(KdfCost::from_byte_buffer(Kdf::None, &unused_field), None);
+    }
+}
@@ -0,0 +1,67 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum KeyEncapsulation {
+    /// The payload key comes straight from the credentials.
+    None,
+    /// A random payload key is wrapped for each X25519 recipient.
+    X25519,
+    /// A random payload key is wrapped once per password, each with its own KDF and salt.
+    Keyslots,
+}
+
+impl KeyEncapsulation {
+    pub fn to_integer(self) -> u32 {
+        match self {
+            KeyEncapsulation::None => 1,
+            KeyEncapsulation::X25519 => 2,
+            KeyEncapsulation::Keyslots => 3,
+        }
+    }
+
+    pub fn from_integer(integer : u32) -> Option<KeyEncapsulation> {
+        match integer {
+            1 => Some(KeyEncapsulation::None),
+            2 => Some(KeyEncapsulation::X25519),
+            3 => Some(KeyEncapsulation::Keyslots),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod key_encapsulation_tests {
+    use super::*;
+
+    #[test]
+    fn test_key_encapsulation_to_integer() {
+        assert_eq!(KeyEncapsulation::None.to_integer(), 1);
+        assert_eq!(KeyEncapsulation::X25519.to_integer(), 2);
+        assert_eq!(KeyEncapsulation::Keyslots.to_integer(), 3);
+    }
+
+    #[test]
+    fn test_key_encapsulation_from_integer() {
+        assert_eq!(KeyEncapsulation::from_integer(0), None);
+        assert_eq!(KeyEncapsulation::from_integer(1).unwrap(), KeyEncapsulation::None);
+        assert_eq!(KeyEncapsulation::from_integer(2).unwrap(), KeyEncapsulation::X25519);
+        assert_eq!(KeyEncapsulation::from_integer(3).unwrap(), KeyEncapsulation::Keyslots);
+        assert_eq!(KeyEncapsulation::from_integer(4), None);
+    }
+}
@@ -16,7 +16,7 @@
  * You should have received a copy of the GNU General Public License
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum KeySize {
     Size128,
     Size192,
@@ -24,7 +24,7 @@ pub enum KeySize {
 }
 
 impl KeySize {
-    pub fn to_integer(&self) -> u32 {
+    pub fn to_integer(self) -> u32 {
         match self {
             KeySize::Size128 => 128, 
             KeySize::Size192 => 192, 
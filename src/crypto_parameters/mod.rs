@@ -1,5 +1,18 @@
-mod crypto_parameters;
-mod block_cipher;
-mod kdf;
-mod key_size;
-mod operation_mode;
+#[allow(clippy::module_inception)]
+pub mod crypto_parameters;
+pub mod block_cipher;
+pub mod kdf;
+pub mod kdf_cost;
+pub mod key_encapsulation;
+pub mod key_size;
+pub mod operation_mode;
+pub mod parameter_error;
+
+pub use self::block_cipher::BlockCipher;
+pub use self::crypto_parameters::{CryptoParameters, CryptoParametersBuilder};
+pub use self::kdf::Kdf;
+pub use self::kdf_cost::KdfCost;
+pub use self::key_encapsulation::KeyEncapsulation;
+pub use self::key_size::KeySize;
+pub use self::operation_mode::OperationMode;
+pub use self::parameter_error::ParameterError;
@@ -16,24 +16,64 @@
  * You should have received a copy of the GNU General Public License
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
-#[derive(PartialEq, Debug)]
+use crate::segments;
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum OperationMode {
     Gcm,
+    /// GCM over independent segments, see `segments`.
+    ChunkedGcm,
 }
 
 impl OperationMode {
-    pub fn to_integer(&self) -> u32 {
+    pub fn to_integer(self) -> u32 {
         match self {
             OperationMode::Gcm => 1,
+            OperationMode::ChunkedGcm => 2,
         }
     }
 
     pub fn from_integer(integer : u32) -> Option<OperationMode> {
         match integer {
             1 => Some(OperationMode::Gcm),
+            2 => Some(OperationMode::ChunkedGcm),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<OperationMode> {
+        match name {
+            "gcm" => Some(OperationMode::Gcm),
+            "chunked-gcm" => Some(OperationMode::ChunkedGcm),
             _ => None,
         }
     }
+
+    /// The size of the payload that holds `plaintext_length` bytes.
+    pub fn ciphertext_length(self, plaintext_length : usize) -> usize {
+        match self {
+            OperationMode::Gcm => plaintext_length,
+            OperationMode::ChunkedGcm => segments::ciphertext_length(plaintext_length),
+        }
+    }
+
+    /// The inverse of `ciphertext_length`, or `None` if no plaintext fits.
+    pub fn plaintext_length(self, ciphertext_length : usize) -> Option<usize> {
+        match self {
+            OperationMode::Gcm => Some(ciphertext_length),
+            OperationMode::ChunkedGcm => segments::plaintext_length(ciphertext_length),
+        }
+    }
+}
+
+impl fmt::Display for OperationMode {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OperationMode::Gcm => write!(f, "GCM"),
+            OperationMode::ChunkedGcm => write!(f, "chunked GCM"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -43,12 +83,21 @@ mod operation_mode_tests {
     #[test]
     fn test_operation_mode_to_integer() {
         assert_eq!(OperationMode::Gcm.to_integer(), 1);
+        assert_eq!(OperationMode::ChunkedGcm.to_integer(), 2);
     }
     
     #[test]
     fn test_operation_mode_from_integer() {
         assert_eq!(OperationMode::from_integer(0), None);
         assert_eq!(OperationMode::from_integer(1).unwrap(), OperationMode::Gcm);
-        assert_eq!(OperationMode::from_integer(2), None);
+        assert_eq!(OperationMode::from_integer(2).unwrap(), OperationMode::ChunkedGcm);
+        assert_eq!(OperationMode::from_integer(3), None);
+    }
+
+    #[test]
+    fn test_operation_mode_from_name() {
+        assert_eq!(OperationMode::from_name("gcm").unwrap(), OperationMode::Gcm);
+        assert_eq!(OperationMode::from_name("chunked-gcm").unwrap(), OperationMode::ChunkedGcm);
+        assert_eq!(OperationMode::from_name("cbc"), None);
     }
 }
@@ -0,0 +1,48 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::kdf_cost::KdfCost;
+use std::error::Error;
+use std::fmt;
+
+/// A combination of parameters no header can describe.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ParameterError {
+    /// Keyslots wrap the payload key with a key derived from a password.
+    KeyslotsWithoutKdf,
+    /// Recipients wrap a random payload key, so there is nothing to derive.
+    RecipientsWithKdf(Kdf),
+    /// The costs are too low to be worth running the KDF, or higher than a
+    /// header may ask for.
+    InvalidKdfCost(KdfCost),
+}
+
+impl fmt::Display for ParameterError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParameterError::KeyslotsWithoutKdf =>
+                write!(f, "keyslots need a key derivation function, only raw keys can do without one"),
+            ParameterError::RecipientsWithKdf(kdf) =>
+                write!(f, "X25519 recipients wrap a random key and cannot be combined with {}", kdf),
+            ParameterError::InvalidKdfCost(kdf_cost) => write!(f, "the KDF costs are out of bounds: {}", kdf_cost),
+        }
+    }
+}
+
+impl Error for ParameterError{}
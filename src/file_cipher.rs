@@ -0,0 +1,783 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::armor::{armor, dearmor, is_armored, BEGIN_MARKER};
+use crate::credentials::Credentials;
+use crate::crypto_error::CryptoError;
+use crate::crypto_parameters::block_cipher::BlockCipher;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+use crate::crypto_parameters::key_size::KeySize;
+use crate::crypto_parameters::operation_mode::OperationMode;
+use crate::file_loader::{Direction, FileLoader, LoadedFiles};
+use crate::header::{Header, Keyslot, Stanza, FLAG_IDENTITY, PREFIX_SIZE, TAG_SIZE};
+use crate::keyslots::{create_keyslot, unlock_keyslots_with};
+use crate::overwrite::temporary_path;
+use crate::progress;
+use crate::recipients::{unwrap_key, wrap_key};
+use crate::secret::Secret;
+use crate::segments::{open_segments, seal_segments};
+use aes::cipher::consts::{U12, U16};
+use aes::cipher::{BlockCipher as CipherAlgorithm, BlockEncrypt, BlockSizeUser, KeyInit};
+use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{AesGcm, Nonce, Tag};
+use camellia::{Camellia128, Camellia192, Camellia256};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use zeroize::Zeroizing;
+
+pub const FILENAME_EXTENSION : &str = "enc";
+/// Names ciphertexts that only exist in memory in errors.
+pub const IN_MEMORY : &str = "<memory>";
+
+/// Encrypts `path` into `path.enc` using a key derived from `credentials`,
+/// or a random key wrapped for each of their recipients.
+pub fn encrypt_file(path : &str, credentials : &Credentials, parameters : CryptoParameters) -> Result<(), CryptoError> {
+    encrypt_mapped_file(path, None, credentials, parameters)
+}
+
+/// Like `encrypt_file`, but writes the ciphertext to `destination_path`.
+pub fn encrypt_file_to(path : &str, destination_path : &str, credentials : &Credentials,
+    parameters : CryptoParameters) -> Result<(), CryptoError> {
+    encrypt_mapped_file(path, Some(destination_path), credentials, parameters)
+}
+
+/// Like `encrypt_file`, but the ciphertext is ASCII-armored.
+pub fn encrypt_armored_file(path : &str, credentials : &Credentials, parameters : CryptoParameters) -> Result<(), CryptoError> {
+    encrypt_armored_file_to(path, &(path.to_string() + "." + FILENAME_EXTENSION), credentials, parameters)
+}
+
+/// Like `encrypt_armored_file`, but writes the ciphertext to `destination_path`.
+pub fn encrypt_armored_file_to(path : &str, destination_path : &str, credentials : &Credentials,
+    parameters : CryptoParameters) -> Result<(), CryptoError> {
+    let plaintext : Zeroizing<Vec<u8>> = Zeroizing::new(fs::read(path)
+        .map_err(|_| CryptoError::FileLoading(path.to_string()))?);
+    let ciphertext : Vec<u8> = encrypt_to_memory(&plaintext, credentials, parameters, path)?;
+    write_new_file(destination_path, &armor(&ciphertext), path)
+}
+
+/// Decrypts `path`, which has to end in `.enc`, using the parameters stored in its header.
+/// ASCII-armored files are recognized automatically.
+pub fn decrypt_file(path : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    decrypt_mapped_file(path, None, credentials)
+}
+
+/// Like `decrypt_file`, but writes the plaintext to `destination_path` and
+/// accepts any name for `path`.
+pub fn decrypt_file_to(path : &str, destination_path : &str, credentials : &Credentials) -> Result<(), CryptoError> {
+    decrypt_mapped_file(path, Some(destination_path), credentials)
+}
+
+/// Decrypts `path` and encrypts it again with `parameters` in one pass. The
+/// plaintext is only kept in memory. The new ciphertext is written to a
+/// private temporary directory next to the original, decrypted again for
+/// verification and only then moved over it.
+pub fn rekey_file(path : &str, credentials : &Credentials, parameters : CryptoParameters) -> Result<(), CryptoError> {
+    let plaintext : Zeroizing<Vec<u8>> = decrypt_to_memory(path, credentials)?;
+    let mut ciphertext : Vec<u8> = encrypt_to_memory(&plaintext, credentials, parameters, path)?;
+    if file_is_armored(path)? {
+        ciphertext = armor(&ciphertext);
+    }
+
+    let (_directory, temporary_path) = temporary_path(path).map_err(|_| CryptoError::Io(path.to_string()))?;
+    write_replacement(path, &temporary_path, &ciphertext).map_err(|_| CryptoError::Io(path.to_string()))?;
+    match decrypt_to_memory(&temporary_path, credentials) {
+        Ok(decrypted) if decrypted == plaintext => (),
+        _ => return Err(CryptoError::Encryption(path.to_string())),
+    }
+    fs::rename(&temporary_path, path).map_err(|_| CryptoError::Io(path.to_string()))
+}
+
+/// Encrypts `path` through memory maps, into `destination_path` or next to it.
+fn encrypt_mapped_file(path : &str, destination_path : Option<&str>, credentials : &Credentials,
+    parameters : CryptoParameters) -> Result<(), CryptoError> {
+    let (mut header, key) = new_header(credentials, parameters, path)?;
+    let loader : FileLoader = FileLoader::new(Direction::Encrypt, FILENAME_EXTENSION, header.size(),
+        parameters.operation_mode());
+    let mut loaded_files : LoadedFiles = load_files(&loader, path, destination_path)?;
+    let result = encrypt_loaded_files(&mut loaded_files, &mut header, &key, path);
+    finish(loaded_files, result, path)
+}
+
+fn decrypt_mapped_file(path : &str, destination_path : Option<&str>, credentials : &Credentials) -> Result<(), CryptoError> {
+    if file_is_armored(path)? {
+        let plaintext : Zeroizing<Vec<u8>> = decrypt_to_memory(path, credentials)?;
+        let file_length : u64 = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        progress::advance((file_length as usize).saturating_sub(plaintext.len()));
+        let plaintext_path : String = match destination_path {
+            Some(destination_path) => destination_path.to_string(),
+            None => FileLoader::new(Direction::Decrypt, FILENAME_EXTENSION, 0, OperationMode::Gcm)
+                .destination_path(path)
+                .ok_or_else(|| CryptoError::FileLoading(path.to_string()))?,
+        };
+        return write_new_file(&plaintext_path, &plaintext, path);
+    }
+    let header : Header = read_header(path)?;
+    let loader : FileLoader = FileLoader::new(Direction::Decrypt, FILENAME_EXTENSION, header.size(),
+        header.parameters.operation_mode());
+    let mut loaded_files : LoadedFiles = load_files(&loader, path, destination_path)?;
+    let result = decrypt_loaded_files(&mut loaded_files, &header, credentials, path);
+    finish(loaded_files, result, path)
+}
+
+fn load_files(loader : &FileLoader, path : &str, destination_path : Option<&str>) -> Result<LoadedFiles, CryptoError> {
+    match destination_path {
+        Some(destination_path) => loader.load_files_to(path, destination_path),
+        None => loader.load_files(path),
+    }.ok_or_else(|| CryptoError::FileLoading(path.to_string()))
+}
+
+/// Reads the header of an encrypted file, e.g. to find out which credentials it requires.
+pub fn read_header(path : &str) -> Result<Header, CryptoError> {
+    if file_is_armored(path)? {
+        return Header::from_byte_buffer(&read_ciphertext(path)?)
+            .ok_or_else(|| CryptoError::InvalidHeader(path.to_string()));
+    }
+    let mut file : File = File::open(path).map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    let mut byte_buffer : Vec<u8> = vec![0x00; PREFIX_SIZE];
+    file.read_exact(&mut byte_buffer).map_err(|_| CryptoError::InvalidHeader(path.to_string()))?;
+    let header_size : usize = Header::size_from_prefix(&byte_buffer)
+        .ok_or_else(|| CryptoError::InvalidHeader(path.to_string()))?;
+    byte_buffer.resize(header_size, 0x00);
+    file.read_exact(&mut byte_buffer[PREFIX_SIZE..]).map_err(|_| CryptoError::InvalidHeader(path.to_string()))?;
+    Header::from_byte_buffer(&byte_buffer).ok_or_else(|| CryptoError::InvalidHeader(path.to_string()))
+}
+
+/// Creates a header with a fresh salt and nonce and returns it with the
+/// payload key, which may have been wrapped into the header's stanzas.
+pub(crate) fn new_header(credentials : &Credentials, parameters : CryptoParameters, path : &str) -> Result<(Header, Secret), CryptoError> {
+    let mut header : Header = Header::new(parameters, credentials.flags());
+    match credentials.shared_salt() {
+        Some(salt) => header.salt = salt,
+        None => OsRng.fill_bytes(&mut header.salt),
+    }
+    OsRng.fill_bytes(&mut header.nonce);
+    let key : Secret = encryption_key(&mut header, credentials, path)?;
+    if Header::from_byte_buffer(&header.to_byte_buffer()).is_none() {
+        return Err(CryptoError::UnsupportedParameters(path.to_string()));
+    }
+    Ok((header, key))
+}
+
+fn file_is_armored(path : &str) -> Result<bool, CryptoError> {
+    let mut beginning : Vec<u8> = Vec::with_capacity(2 * BEGIN_MARKER.len());
+    File::open(path).and_then(|file| file.take(2 * BEGIN_MARKER.len() as u64).read_to_end(&mut beginning))
+        .map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    Ok(is_armored(&beginning))
+}
+
+/// Reads a whole encrypted file, removing the ASCII armor if there is one.
+fn read_ciphertext(path : &str) -> Result<Vec<u8>, CryptoError> {
+    let ciphertext : Vec<u8> = fs::read(path).map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    if is_armored(&ciphertext) {
+        dearmor(&ciphertext).ok_or_else(|| CryptoError::InvalidHeader(path.to_string()))
+    } else {
+        Ok(ciphertext)
+    }
+}
+
+/// Encrypts `plaintext` into a complete ciphertext including the header.
+/// `path` only names the data in errors.
+pub fn encrypt_to_memory(plaintext : &[u8], credentials : &Credentials, parameters : CryptoParameters,
+    path : &str) -> Result<Vec<u8>, CryptoError> {
+    let (mut header, key) = new_header(credentials, parameters, path)?;
+    let header_size : usize = header.size();
+    let mut ciphertext : Vec<u8> = vec![0x00; header_size + parameters.operation_mode().ciphertext_length(plaintext.len())];
+    header.tag = seal_payload(&header, &key, plaintext, &mut ciphertext[header_size..])
+        .ok_or_else(|| CryptoError::Encryption(path.to_string()))?;
+    ciphertext[0..header_size].copy_from_slice(&header.to_byte_buffer());
+    Ok(ciphertext)
+}
+
+/// Decrypts the file at `path` without writing the plaintext anywhere.
+pub fn decrypt_to_memory(path : &str, credentials : &Credentials) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    decrypt_ciphertext(&read_ciphertext(path)?, credentials, path)
+}
+
+/// Encrypts `plaintext` into the same container `encrypt_file` writes, for
+/// data that never touches the disk.
+pub fn encrypt_bytes(plaintext : &[u8], credentials : &Credentials,
+    parameters : &CryptoParameters) -> Result<Vec<u8>, CryptoError> {
+    encrypt_to_memory(plaintext, credentials, *parameters, IN_MEMORY)
+}
+
+/// Decrypts a container written by `encrypt_bytes` or `encrypt_file`,
+/// armored or not.
+pub fn decrypt_bytes(ciphertext : &[u8], credentials : &Credentials) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    if is_armored(ciphertext) {
+        let ciphertext : Vec<u8> = dearmor(ciphertext).ok_or_else(|| CryptoError::InvalidHeader(IN_MEMORY.to_string()))?;
+        return decrypt_ciphertext(&ciphertext, credentials, IN_MEMORY);
+    }
+    decrypt_ciphertext(ciphertext, credentials, IN_MEMORY)
+}
+
+fn decrypt_ciphertext(ciphertext : &[u8], credentials : &Credentials, path : &str) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let header : Header = Header::from_byte_buffer(ciphertext)
+        .ok_or_else(|| CryptoError::InvalidHeader(path.to_string()))?;
+    let key : Secret = decryption_key(&header, credentials, path)?;
+    let payload : &[u8] = &ciphertext[header.size()..];
+    let plaintext_length : usize = header.parameters.operation_mode().plaintext_length(payload.len())
+        .ok_or_else(|| CryptoError::InvalidHeader(path.to_string()))?;
+    let mut plaintext : Zeroizing<Vec<u8>> = Zeroizing::new(vec![0x00; plaintext_length]);
+    if open_payload(&header, &key, payload, &mut plaintext) {
+        Ok(plaintext)
+    } else {
+        Err(CryptoError::AuthenticationFailed(path.to_string()))
+    }
+}
+
+fn encrypt_loaded_files(loaded_files : &mut LoadedFiles, header : &mut Header, key : &Secret,
+    path : &str) -> Result<(), CryptoError> {
+    let header_size : usize = header.size();
+    let (source, destination) = loaded_files.source_and_destination();
+    header.tag = seal_payload(header, key, source, &mut destination[header_size..])
+        .ok_or_else(|| CryptoError::Encryption(path.to_string()))?;
+    destination[0..header_size].copy_from_slice(&header.to_byte_buffer());
+    Ok(())
+}
+
+fn decrypt_loaded_files(loaded_files : &mut LoadedFiles, header : &Header, credentials : &Credentials,
+    path : &str) -> Result<(), CryptoError> {
+    let (source, destination) = loaded_files.source_and_destination();
+    // The file could have changed since its header was read.
+    if Header::from_byte_buffer(source).as_ref() != Some(header) {
+        return Err(CryptoError::InvalidHeader(path.to_string()));
+    }
+    let key : Secret = decryption_key(header, credentials, path)?;
+
+    if open_payload(header, &key, &source[header.size()..], destination) {
+        progress::advance(source.len() - destination.len());
+        Ok(())
+    } else {
+        Err(CryptoError::AuthenticationFailed(path.to_string()))
+    }
+}
+
+fn encryption_key(header : &mut Header, credentials : &Credentials, path : &str) -> Result<Secret, CryptoError> {
+    match header.parameters.key_encapsulation() {
+        KeyEncapsulation::None => derived_key(header, credentials, path),
+        KeyEncapsulation::X25519 => {
+            if credentials.recipients().is_empty() {
+                return Err(CryptoError::CredentialsMismatch(path.to_string(), FLAG_IDENTITY));
+            }
+            let key : Secret = random_key(header.parameters.key_size());
+            for recipient in credentials.recipients() {
+                let stanza : Stanza = wrap_key(&key, recipient).map(Stanza::Recipient)
+                    .ok_or_else(|| CryptoError::Encryption(path.to_string()))?;
+                header.stanzas.push(stanza);
+            }
+            Ok(key)
+        }
+        KeyEncapsulation::Keyslots => {
+            let password : Secret = credentials.key_material(header.flags)
+                .ok_or_else(|| CryptoError::CredentialsMismatch(path.to_string(), header.flags))?;
+            let parameters : CryptoParameters = header.parameters;
+            let create = || {
+                let key : Secret = random_key(parameters.key_size());
+                let keyslot : Keyslot = create_keyslot(&key, &password, parameters.kdf_cost(), &parameters)?;
+                Some((key, keyslot))
+            };
+            // Keyslots are sealed with a fixed nonce, so files sharing a salt
+            // also have to share the payload key the keyslot wraps.
+            let (key, keyslot) = if credentials.shared_salt().is_some() {
+                credentials.shared_keyslot(parameters, create)
+            } else {
+                create()
+            }.ok_or_else(|| CryptoError::UnsupportedParameters(path.to_string()))?;
+            header.stanzas.push(Stanza::Keyslot(keyslot));
+            Ok(key)
+        }
+    }
+}
+
+pub(crate) fn decryption_key(header : &Header, credentials : &Credentials, path : &str) -> Result<Secret, CryptoError> {
+    match header.parameters.key_encapsulation() {
+        KeyEncapsulation::None => derived_key(header, credentials, path),
+        KeyEncapsulation::X25519 => {
+            let identity : &Secret = credentials.identity()
+                .ok_or_else(|| CryptoError::CredentialsMismatch(path.to_string(), header.flags))?;
+            header.stanzas.iter()
+                .find_map(|stanza| match stanza {
+                    Stanza::Recipient(recipient) => unwrap_key(recipient, identity),
+                    Stanza::Keyslot(_) => None,
+                })
+                .ok_or_else(|| CryptoError::NoMatchingRecipient(path.to_string()))
+        }
+        KeyEncapsulation::Keyslots => {
+            if credentials.key_material(header.flags).is_none() {
+                return Err(CryptoError::CredentialsMismatch(path.to_string(), header.flags));
+            }
+            unlock_keyslots_with(header, credentials).ok_or_else(|| CryptoError::AuthenticationFailed(path.to_string()))
+        }
+    }
+}
+
+/// Replaces the header of an encrypted file while keeping its payload. The
+/// new file is written to a private temporary directory next to the old one
+/// and then moved over it; the directory is removed either way.
+pub fn rewrite_header(path : &str, header : &Header) -> Result<(), CryptoError> {
+    let old_header : Header = read_header(path)?;
+    let (_directory, temporary_path) = temporary_path(path).map_err(|_| CryptoError::Io(path.to_string()))?;
+    let result = if file_is_armored(path)? {
+        let ciphertext : Vec<u8> = read_ciphertext(path)?;
+        let mut new_ciphertext : Vec<u8> = header.to_byte_buffer();
+        new_ciphertext.extend_from_slice(&ciphertext[old_header.size()..]);
+        write_replacement(path, &temporary_path, &armor(&new_ciphertext))
+    } else {
+        copy_with_header(path, &temporary_path, old_header.size(), header)
+    };
+    result.and_then(|_| fs::rename(&temporary_path, path)).map_err(|_| CryptoError::Io(path.to_string()))
+}
+
+fn copy_with_header(path : &str, destination_path : &str, old_header_size : usize, header : &Header) -> io::Result<()> {
+    let mut source : File = File::open(path)?;
+    source.seek(SeekFrom::Start(old_header_size as u64))?;
+    let mut destination : File = OpenOptions::new().write(true).create_new(true).open(destination_path)?;
+    destination.set_permissions(source.metadata()?.permissions())?;
+    destination.write_all(&header.to_byte_buffer())?;
+    io::copy(&mut source, &mut destination)?;
+    destination.sync_all()
+}
+
+/// Writes `content` to a file that must not exist yet, removing it again if
+/// the write fails. Errors refer to the source `path`.
+pub fn write_new_file(destination_path : &str, content : &[u8], path : &str) -> Result<(), CryptoError> {
+    let mut destination : File = OpenOptions::new().write(true).create_new(true).open(destination_path)
+        .map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    if destination.write_all(content).and_then(|_| destination.sync_all()).is_err() {
+        let _ = fs::remove_file(destination_path);
+        return Err(CryptoError::Io(path.to_string()));
+    }
+    Ok(())
+}
+
+fn write_replacement(path : &str, destination_path : &str, content : &[u8]) -> io::Result<()> {
+    let mut destination : File = OpenOptions::new().write(true).create_new(true).open(destination_path)?;
+    destination.set_permissions(fs::metadata(path)?.permissions())?;
+    destination.write_all(content)?;
+    destination.sync_all()
+}
+
+fn derived_key(header : &Header, credentials : &Credentials, path : &str) -> Result<Secret, CryptoError> {
+    let parameters : CryptoParameters = header.parameters;
+    if credentials.key_material(header.flags).is_none() {
+        return Err(CryptoError::CredentialsMismatch(path.to_string(), header.flags));
+    }
+    credentials.derive_key(header.flags, &header.salt, parameters.kdf_cost(), parameters.key_size())
+        .ok_or_else(|| CryptoError::InvalidKey(path.to_string()))
+}
+
+fn random_key(key_size : KeySize) -> Secret {
+    let mut key : Vec<u8> = vec![0x00; key_size.to_integer() as usize / 8];
+    OsRng.fill_bytes(&mut key);
+    Secret::new(key)
+}
+
+/// Flushes the destination on success and removes it on failure, so no
+/// half-written files are left behind.
+fn finish(loaded_files : LoadedFiles, result : Result<(), CryptoError>, path : &str) -> Result<(), CryptoError> {
+    let destination_path : String = loaded_files.destination_path().to_string();
+    let result = result.and_then(|_| loaded_files.flush().map_err(|_| CryptoError::Io(path.to_string())));
+    drop(loaded_files);
+    if result.is_err() {
+        let _ = fs::remove_file(destination_path);
+    }
+    result
+}
+
+/// Seals `plaintext` into `payload` in the operation mode of `header` and
+/// returns the tag for the header.
+fn seal_payload(header : &Header, key : &Secret, plaintext : &[u8], payload : &mut [u8]) -> Option<[u8; TAG_SIZE]> {
+    match header.parameters.operation_mode() {
+        OperationMode::Gcm => {
+            payload.copy_from_slice(plaintext);
+            let tag : Option<[u8; TAG_SIZE]> = seal(&header.parameters, key, &header.nonce, &header.authenticated_data(), payload);
+            progress::advance(plaintext.len());
+            tag
+        }
+        OperationMode::ChunkedGcm => seal_segments(&header.parameters, key, &header.nonce,
+            &header.authenticated_data(), plaintext, payload),
+    }
+}
+
+fn open_payload(header : &Header, key : &Secret, payload : &[u8], plaintext : &mut [u8]) -> bool {
+    match header.parameters.operation_mode() {
+        OperationMode::Gcm => {
+            plaintext.copy_from_slice(payload);
+            let opened : bool = open(&header.parameters, key, &header.nonce, &header.authenticated_data(), plaintext, &header.tag);
+            progress::advance(plaintext.len());
+            opened
+        }
+        OperationMode::ChunkedGcm => open_segments(&header.parameters, key, &header.nonce,
+            &header.authenticated_data(), payload, plaintext, &header.tag),
+    }
+}
+
+/// Seals `buffer` in place with GCM, whatever the operation mode in `parameters`.
+pub fn seal(parameters : &CryptoParameters, key : &Secret, nonce : &[u8], authenticated_data : &[u8],
+    buffer : &mut [u8]) -> Option<[u8; TAG_SIZE]> {
+    match (parameters.block_cipher(), parameters.key_size()) {
+        (BlockCipher::Aes, KeySize::Size128) => seal_with::<Aes128>(key, nonce, authenticated_data, buffer),
+        (BlockCipher::Aes, KeySize::Size192) => seal_with::<Aes192>(key, nonce, authenticated_data, buffer),
+        (BlockCipher::Aes, KeySize::Size256) => seal_with::<Aes256>(key, nonce, authenticated_data, buffer),
+        (BlockCipher::Camellia, KeySize::Size128) => seal_with::<Camellia128>(key, nonce, authenticated_data, buffer),
+        (BlockCipher::Camellia, KeySize::Size192) => seal_with::<Camellia192>(key, nonce, authenticated_data, buffer),
+        (BlockCipher::Camellia, KeySize::Size256) => seal_with::<Camellia256>(key, nonce, authenticated_data, buffer),
+    }
+}
+
+/// Opens `buffer` in place with GCM, whatever the operation mode in `parameters`.
+pub fn open(parameters : &CryptoParameters, key : &Secret, nonce : &[u8], authenticated_data : &[u8],
+    buffer : &mut [u8], tag : &[u8]) -> bool {
+    match (parameters.block_cipher(), parameters.key_size()) {
+        (BlockCipher::Aes, KeySize::Size128) => open_with::<Aes128>(key, nonce, authenticated_data, buffer, tag),
+        (BlockCipher::Aes, KeySize::Size192) => open_with::<Aes192>(key, nonce, authenticated_data, buffer, tag),
+        (BlockCipher::Aes, KeySize::Size256) => open_with::<Aes256>(key, nonce, authenticated_data, buffer, tag),
+        (BlockCipher::Camellia, KeySize::Size128) => open_with::<Camellia128>(key, nonce, authenticated_data, buffer, tag),
+        (BlockCipher::Camellia, KeySize::Size192) => open_with::<Camellia192>(key, nonce, authenticated_data, buffer, tag),
+        (BlockCipher::Camellia, KeySize::Size256) => open_with::<Camellia256>(key, nonce, authenticated_data, buffer, tag),
+    }
+}
+
+fn seal_with<C>(key : &Secret, nonce : &[u8], authenticated_data : &[u8], buffer : &mut [u8]) -> Option<[u8; TAG_SIZE]>
+where C : CipherAlgorithm + BlockSizeUser<BlockSize = U16> + BlockEncrypt + KeyInit {
+    let cipher = AesGcm::<C, U12>::new_from_slice(key.expose()).ok()?;
+    let tag = cipher.encrypt_in_place_detached(Nonce::from_slice(nonce), authenticated_data, buffer).ok()?;
+    Some(tag.into())
+}
+
+fn open_with<C>(key : &Secret, nonce : &[u8], authenticated_data : &[u8], buffer : &mut [u8], tag : &[u8]) -> bool
+where C : CipherAlgorithm + BlockSizeUser<BlockSize = U16> + BlockEncrypt + KeyInit {
+    match AesGcm::<C, U12>::new_from_slice(key.expose()) {
+        Ok(cipher) => cipher.decrypt_in_place_detached(Nonce::from_slice(nonce), authenticated_data,
+            buffer, Tag::from_slice(tag)).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod file_cipher_tests {
+    use super::*;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::kdf_cost::KdfCost;
+    use crate::segments::SEGMENT_SIZE;
+    use crate::keyslots::change_password;
+    use crate::recipients::generate_identity;
+    use std::path::Path;
+
+    fn write_plaintext(path : &str, content : &[u8]) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(path.to_string() + "." + FILENAME_EXTENSION);
+        fs::write(path, content).unwrap();
+    }
+
+    fn derived_key_parameters() -> CryptoParameters {
+        CryptoParameters::new(BlockCipher::Aes, Kdf::Pbkdf2HmacSha3512, KeySize::Size256,
+            OperationMode::Gcm, KeyEncapsulation::None)
+    }
+
+    fn password() -> Credentials {
+        Credentials::from(Secret::from("password".to_string()))
+    }
+
+    fn round_trip(path : &str, parameters : CryptoParameters, credentials : Credentials) {
+        let content : &[u8] = b"BLARZBLURZBLIRZ\nGNAMPF!\nKNIRFFNURP\n";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, content);
+
+        encrypt_file(path, &credentials, parameters).unwrap();
+        let ciphertext : Vec<u8> = fs::read(&ciphertext_path).unwrap();
+        let header : Header = read_header(&ciphertext_path).unwrap();
+        assert_eq!(ciphertext.len(), header.size() + parameters.operation_mode().ciphertext_length(content.len()));
+        assert_eq!(header.parameters, parameters);
+        assert_eq!(header.flags, credentials.flags());
+
+        fs::remove_file(path).unwrap();
+        decrypt_file(&ciphertext_path, &credentials).unwrap();
+        assert_eq!(fs::read(path).unwrap(), content);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_aes() {
+        round_trip("testfiles/round_trip_aes.txt", CryptoParameters::default(), password());
+    }
+
+    #[test]
+    fn test_rekey() {
+        let path : &str = "testfiles/rekey.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        encrypt_file(path, &password(), CryptoParameters::default()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Camellia,
+            Kdf::Pbkdf2HmacSha3512, KeySize::Size128, OperationMode::Gcm, KeyEncapsulation::Keyslots);
+        let unrelated_path : String = ciphertext_path.clone() + ".tmp";
+        fs::write(&unrelated_path, b"unrelated").unwrap();
+        assert!(matches!(rekey_file(&ciphertext_path, &Credentials::from(Secret::from("wrong".to_string())), parameters),
+            Err(CryptoError::AuthenticationFailed(_))));
+        assert_eq!(fs::read(&unrelated_path).unwrap(), b"unrelated");
+        rekey_file(&ciphertext_path, &password(), parameters).unwrap();
+        assert_eq!(read_header(&ciphertext_path).unwrap().parameters, parameters);
+        assert_eq!(fs::read(&unrelated_path).unwrap(), b"unrelated");
+        fs::remove_file(&unrelated_path).unwrap();
+
+        decrypt_file(&ciphertext_path, &password()).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_rekey_to_argon2id() {
+        let path : &str = "testfiles/rekey_to_argon2id.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        let pbkdf2 : CryptoParameters = CryptoParameters::builder().kdf(Kdf::Pbkdf2HmacSha3512).build().unwrap();
+        encrypt_file(path, &password(), pbkdf2).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(read_header(&ciphertext_path).unwrap().parameters.kdf(), Kdf::Pbkdf2HmacSha3512);
+
+        let parameters : CryptoParameters = CryptoParameters::builder().kdf(Kdf::Argon2id).build().unwrap();
+        rekey_file(&ciphertext_path, &password(), parameters).unwrap();
+        let header : Header = read_header(&ciphertext_path).unwrap();
+        assert_eq!(header.parameters.kdf_cost(), KdfCost::default_for(Kdf::Argon2id));
+        assert!(header.stanzas.iter().all(|stanza|
+            matches!(stanza, Stanza::Keyslot(keyslot) if keyslot.kdf_cost == KdfCost::default_for(Kdf::Argon2id))));
+
+        decrypt_file(&ciphertext_path, &password()).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_rekey_to_chunked() {
+        let path : &str = "testfiles/rekey_to_chunked.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        let content : Vec<u8> = (0..5 * SEGMENT_SIZE / 2).map(|i| (i % 251) as u8).collect();
+        let credentials = || Credentials::from_raw_key(Secret::new(vec![0x42; 32]));
+        let raw_key_parameters = |operation_mode| CryptoParameters::new(BlockCipher::Aes, Kdf::None,
+            KeySize::Size256, operation_mode, KeyEncapsulation::None);
+        write_plaintext(path, &content);
+        encrypt_file(path, &credentials(), raw_key_parameters(OperationMode::Gcm)).unwrap();
+        fs::remove_file(path).unwrap();
+
+        rekey_file(&ciphertext_path, &credentials(), raw_key_parameters(OperationMode::ChunkedGcm)).unwrap();
+        let header : Header = read_header(&ciphertext_path).unwrap();
+        assert_eq!(header.parameters.operation_mode(), OperationMode::ChunkedGcm);
+        assert_eq!(fs::metadata(&ciphertext_path).unwrap().len() as usize, header.size() + content.len() + 3 * TAG_SIZE);
+        decrypt_file(&ciphertext_path, &credentials()).unwrap();
+        assert_eq!(fs::read(path).unwrap(), content);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_rekey_wrong_password() {
+        let path : &str = "testfiles/rekey_wrong_password.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        encrypt_file(path, &password(), CryptoParameters::default()).unwrap();
+        fs::remove_file(path).unwrap();
+        let ciphertext : Vec<u8> = fs::read(&ciphertext_path).unwrap();
+
+        let result = rekey_file(&ciphertext_path, &Credentials::from(Secret::from("wrong".to_string())),
+            CryptoParameters::default());
+        assert!(matches!(result, Err(CryptoError::AuthenticationFailed(_))));
+        assert_eq!(fs::read(&ciphertext_path).unwrap(), ciphertext);
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_armored_round_trip() {
+        let path : &str = "testfiles/armored.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        encrypt_armored_file(path, &password(), CryptoParameters::default()).unwrap();
+        fs::remove_file(path).unwrap();
+        let armored : Vec<u8> = fs::read(&ciphertext_path).unwrap();
+        assert!(armored.starts_with(BEGIN_MARKER.as_bytes()));
+        assert_eq!(read_header(&ciphertext_path).unwrap().parameters, CryptoParameters::default());
+
+        // Changing the password and rekeying keep the armor.
+        change_password(&ciphertext_path, &Secret::from("password".to_string()), &Secret::from("new".to_string())).unwrap();
+        assert!(decrypt_file(&ciphertext_path, &password()).is_err());
+        change_password(&ciphertext_path, &Secret::from("new".to_string()), &Secret::from("password".to_string())).unwrap();
+        rekey_file(&ciphertext_path, &password(), derived_key_parameters()).unwrap();
+        assert!(fs::read(&ciphertext_path).unwrap().starts_with(BEGIN_MARKER.as_bytes()));
+        decrypt_file(&ciphertext_path, &password()).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_derived_key() {
+        round_trip("testfiles/round_trip_derived_key.txt", derived_key_parameters(), password());
+    }
+
+    #[test]
+    fn test_shared_salt() {
+        let paths : [&str; 2] = ["testfiles/shared_salt_1.txt", "testfiles/shared_salt_2.txt"];
+        let mut credentials : Credentials = password();
+        credentials.share_salt();
+        let mut headers : Vec<Header> = Vec::new();
+        for path in paths {
+            write_plaintext(path, path.as_bytes());
+            encrypt_file(path, &credentials, CryptoParameters::default()).unwrap();
+            fs::remove_file(path).unwrap();
+            headers.push(read_header(&(path.to_string() + "." + FILENAME_EXTENSION)).unwrap());
+        }
+        assert_eq!(headers[0].salt, credentials.shared_salt().unwrap());
+        assert_eq!(headers[0].salt, headers[1].salt);
+        assert_eq!(headers[0].stanzas, headers[1].stanzas);
+        assert_ne!(headers[0].nonce, headers[1].nonce);
+
+        let credentials : Credentials = password();
+        for path in paths {
+            let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+            decrypt_file(&ciphertext_path, &credentials).unwrap();
+            assert_eq!(fs::read(path).unwrap(), path.as_bytes());
+            fs::remove_file(path).unwrap();
+            fs::remove_file(&ciphertext_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_encrypt_keyslots_with_keyfile() {
+        let path : &str = "testfiles/keyslots_with_keyfile.txt";
+        write_plaintext(path, b"GNAMPF!");
+        let credentials : Credentials = Credentials::new(None, Some(Secret::new(vec![0x42; 64])));
+        let result = encrypt_file(path, &credentials, CryptoParameters::default());
+        assert!(matches!(result, Err(CryptoError::UnsupportedParameters(_))));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_camellia() {
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Camellia,
+            Kdf::Pbkdf2HmacSha3512, KeySize::Size128, OperationMode::Gcm, KeyEncapsulation::None);
+        round_trip("testfiles/round_trip_camellia.txt", parameters, password());
+    }
+
+    #[test]
+    fn test_decrypt_wrong_password() {
+        let path : &str = "testfiles/wrong_password.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        encrypt_file(path, &password(), CryptoParameters::default()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let result = decrypt_file(&ciphertext_path, &Credentials::from(Secret::from("wrong".to_string())));
+        assert!(matches!(result, Err(CryptoError::AuthenticationFailed(_))));
+        assert!(!Path::new(path).exists());
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_keyfile() {
+        let keyfile_hash = || Some(Secret::new(vec![0x42; 64]));
+        round_trip("testfiles/round_trip_keyfile.txt", derived_key_parameters(),
+            Credentials::new(None, keyfile_hash()));
+        round_trip("testfiles/round_trip_password_keyfile.txt", derived_key_parameters(),
+            Credentials::new(Some(Secret::from("password".to_string())), keyfile_hash()));
+    }
+
+    #[test]
+    fn test_round_trip_raw_key() {
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Aes,
+            Kdf::None, KeySize::Size192, OperationMode::Gcm, KeyEncapsulation::None);
+        round_trip("testfiles/round_trip_raw_key.txt", parameters,
+            Credentials::from_raw_key(Secret::new(vec![0x42; 24])));
+    }
+
+    #[test]
+    fn test_encrypt_raw_key_wrong_size() {
+        let path : &str = "testfiles/raw_key_wrong_size.txt";
+        write_plaintext(path, b"GNAMPF!");
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Aes,
+            Kdf::None, KeySize::Size256, OperationMode::Gcm, KeyEncapsulation::None);
+        let result = encrypt_file(path, &Credentials::from_raw_key(Secret::new(vec![0x42; 16])), parameters);
+        assert!(matches!(result, Err(CryptoError::InvalidKey(_))));
+        assert!(!Path::new(&(path.to_string() + "." + FILENAME_EXTENSION)).exists());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_decrypt_missing_keyfile() {
+        let path : &str = "testfiles/missing_keyfile.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        let credentials : Credentials = Credentials::new(
+            Some(Secret::from("password".to_string())), Some(Secret::new(vec![0x42; 64])));
+        encrypt_file(path, &credentials, derived_key_parameters()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let result = decrypt_file(&ciphertext_path, &password());
+        assert!(matches!(result, Err(CryptoError::CredentialsMismatch(_, _))));
+        assert!(!Path::new(path).exists());
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_recipients() {
+        let path : &str = "testfiles/round_trip_recipients.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        write_plaintext(path, b"GNAMPF!");
+        let (identity1, public_key1) = generate_identity();
+        let (identity2, public_key2) = generate_identity();
+        let (identity3, _) = generate_identity();
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Aes,
+            Kdf::None, KeySize::Size256, OperationMode::Gcm, KeyEncapsulation::X25519);
+        encrypt_file(path, &Credentials::for_recipients(vec![public_key1, public_key2]), parameters).unwrap();
+        assert_eq!(read_header(&ciphertext_path).unwrap().stanzas.len(), 2);
+        fs::remove_file(path).unwrap();
+
+        let result = decrypt_file(&ciphertext_path, &Credentials::from_identity(identity3));
+        assert!(matches!(result, Err(CryptoError::NoMatchingRecipient(_))));
+        let result = decrypt_file(&ciphertext_path, &password());
+        assert!(matches!(result, Err(CryptoError::CredentialsMismatch(_, FLAG_IDENTITY))));
+        for identity in [identity1, identity2] {
+            decrypt_file(&ciphertext_path, &Credentials::from_identity(identity)).unwrap();
+            assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypt_recipients_with_kdf() {
+        let path : &str = "testfiles/recipients_with_kdf.txt";
+        write_plaintext(path, b"GNAMPF!");
+        let (_, public_key) = generate_identity();
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Aes,
+            Kdf::Pbkdf2HmacSha3512, KeySize::Size256, OperationMode::Gcm, KeyEncapsulation::X25519);
+        let result = encrypt_file(path, &Credentials::for_recipients(vec![public_key]), parameters);
+        assert!(matches!(result, Err(CryptoError::UnsupportedParameters(_))));
+        assert!(!Path::new(&(path.to_string() + "." + FILENAME_EXTENSION)).exists());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -16,11 +16,13 @@
  * You should have received a copy of the GNU General Public License
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
+use crate::crypto_parameters::operation_mode::OperationMode;
 use memmap::Mmap;
 use memmap::MmapMut;
 use memmap::MmapOptions;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::io;
 use std::path::Path;
 
 #[derive(Clone, Copy)]
@@ -32,115 +34,89 @@ pub enum Direction {
 pub struct LoadedFiles {
     source : Mmap,
     destination : MmapMut,
-    direction : Direction,
+    destination_path : String,
+}
+
+impl LoadedFiles {
+    pub fn source_and_destination(&mut self) -> (&[u8], &mut [u8]) {
+        (&self.source, &mut self.destination)
+    }
+
+    pub fn destination_path(&self) -> &str {
+        &self.destination_path
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.destination.flush()
+    }
 }
 
 pub struct FileLoader {
     direction : Direction,
     filename_extension : String,
     header_size : usize,
+    operation_mode : OperationMode,
 }
 
 impl FileLoader {
-    pub fn load_files(&self, path : &String) -> Option<LoadedFiles> {
-        match self.direction {
-            Direction::Encrypt => self.load_files_for_encryption(path),
-            Direction::Decrypt => self.load_files_for_decryption(path),
+    /// The destination is sized for a header of `header_size` bytes and a
+    /// payload in `operation_mode`.
+    pub fn new(direction : Direction, filename_extension : &str, header_size : usize,
+        operation_mode : OperationMode) -> FileLoader {
+        FileLoader{
+            direction,
+            filename_extension : filename_extension.to_string(),
+            header_size,
+            operation_mode,
         }
     }
 
-    fn load_files_for_encryption(&self, path : &String) -> Option<LoadedFiles> {
-        if !Path::new(path).exists() {
-            None
-        } else {
-            let ciphertext_path = path.to_owned() + &".".to_string() + &self.filename_extension.to_owned();
-            if Path::new(&ciphertext_path).exists() {
-                None
-            } else {
-                let source = match self.create_source_memmap(path) {
-                    None => {return None;}
-                    Some(source) => source
-                };
-                let destination_length : usize = source.len() + self.header_size;
-                let destination = match self.create_destination_memmap(path, destination_length) {
-                    None =>{return None;}
-                    Some(destination) => destination
-                };
-                Some(LoadedFiles{
-                    source : source,
-                    destination : destination,
-                    direction : self.direction,
-                })
-            }
-        }
+    pub fn load_files(&self, path : &str) -> Option<LoadedFiles> {
+        self.load_files_to(path, &self.destination_path(path)?)
     }
 
-    fn create_source_memmap(&self, path : &String) -> Option<Mmap> {
-        let source_file = match File::open(path) {
-            Ok(file) => Some(file),
-            Err(err) => None
-        };
-        match source_file {
-            None => None,
-            Some(source_file) => {
-                match unsafe{MmapOptions::new().map(&source_file)} {
-                    Ok(source) => Some(source),
-                    Err(err) => None
-                }
-            }
+    /// The destination `load_files` uses: `path.ext` when encrypting and
+    /// `path` without `.ext` when decrypting.
+    pub fn destination_path(&self, path : &str) -> Option<String> {
+        match self.direction {
+            Direction::Encrypt => Some(path.to_owned() + "." + &self.filename_extension),
+            Direction::Decrypt => path.strip_suffix(&(".".to_string() + &self.filename_extension)).map(str::to_string),
         }
     }
 
-    fn create_destination_memmap(&self, path : &String, length : usize) -> Option<MmapMut> {
-        let destination_file = match OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path) {
-                Ok(file) => file,
-                Err(err) => {
-                    return None;
-                }
-            };
-        match destination_file.set_len(length as u64) {
-            Ok(ok) => {},
-            Err(err) => {
-                return None;
-            }
-        };
-        match unsafe{MmapMut::map_mut(&destination_file)} {
-            Ok(destination) => Some(destination),
-            Err(err) => None
+    /// Loads `path` together with a new destination at `destination_path`,
+    /// which must not exist yet.
+    pub fn load_files_to(&self, path : &str, destination_path : &str) -> Option<LoadedFiles> {
+        if !Path::new(path).exists() || Path::new(destination_path).exists() {
+            return None;
         }
+        let source = self.create_source_memmap(path)?;
+        let destination_length : usize = match self.direction {
+            Direction::Encrypt => self.header_size + self.operation_mode.ciphertext_length(source.len()),
+            Direction::Decrypt => self.operation_mode.plaintext_length(source.len().checked_sub(self.header_size)?)?,
+        };
+        let destination = self.create_destination_memmap(destination_path, destination_length)?;
+        Some(LoadedFiles{
+            source,
+            destination,
+            destination_path : destination_path.to_string(),
+        })
     }
 
-    
-    fn load_files_for_decryption(&self, path : &String) -> Option<LoadedFiles> {
-        if !Path::new(path).exists() {
-            None
-        } else {
-            let extension_length : usize = self.filename_extension.len() + 1;
-            let final_index : usize = path.len() - extension_length;
-            let plaintext_path : String = path[0..final_index].to_string();
-            if Path::new(&plaintext_path).exists() {
-                None
-            } else {
-                let source = match self.create_source_memmap(path) {
-                    None => {return None;}
-                    Some(source) => source
-                };
-                let destination_length : usize = source.len() - self.header_size;
-                let destination = match self.create_destination_memmap(&plaintext_path, destination_length) {
-                    None =>{return None;}
-                    Some(destination) => destination
-                };
-                Some(LoadedFiles{
-                    source : source,
-                    destination : destination,
-                    direction : self.direction,
-                })
-            }
-        }
+    fn create_source_memmap(&self, path : &str) -> Option<Mmap> {
+        let source_file = File::open(path).ok()?;
+        unsafe{MmapOptions::new().map(&source_file)}.ok()
+    }
+
+    fn create_destination_memmap(&self, path : &str, length : usize) -> Option<MmapMut> {
+        let destination_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .ok()?;
+        destination_file.set_len(length as u64).ok()?;
+        unsafe{MmapMut::map_mut(&destination_file)}.ok()
     }
 }
 
@@ -148,15 +124,17 @@ impl FileLoader {
 #[cfg(test)]
 mod file_loader_tests {
     use super::*;
+    use crate::header::TAG_SIZE;
 
     #[test]
     fn test_load_files_nonexistent() {
         let loader : FileLoader = FileLoader{
             direction : Direction::Encrypt,
             filename_extension : "enc".to_string(),
-            header_size : 28
+            header_size : 28,
+            operation_mode : OperationMode::Gcm,
         };
-        let loaded_files = loader.load_files(&"Nonexistent.txt".to_string());
+        let loaded_files = loader.load_files("Nonexistent.txt");
         assert!(loaded_files.is_none());
     }
     
@@ -165,9 +143,10 @@ mod file_loader_tests {
         let loader : FileLoader = FileLoader{
             direction : Direction::Encrypt,
             filename_extension : "enc".to_string(),
-            header_size : 28
+            header_size : 28,
+            operation_mode : OperationMode::Gcm,
         };
-        let loaded_files = loader.load_files(&"testfiles/test1.txt".to_string());
+        let loaded_files = loader.load_files("testfiles/test1.txt");
         assert!(loaded_files.is_none());
     }
     
@@ -176,9 +155,10 @@ mod file_loader_tests {
         let loader : FileLoader = FileLoader{
             direction : Direction::Decrypt,
             filename_extension : "enc".to_string(),
-            header_size : 28
+            header_size : 28,
+            operation_mode : OperationMode::Gcm,
         };
-        let loaded_files = loader.load_files(&"testfiles/test1.txt.enc".to_string());
+        let loaded_files = loader.load_files("testfiles/test1.txt.enc");
         assert!(loaded_files.is_none());
     }
     
@@ -186,27 +166,27 @@ mod file_loader_tests {
 
     #[test]
     fn test_load_files_encryption() {
-        delete_if_present(&"testfiles/test2.txt.enc");
+        delete_if_present("testfiles/test2.txt.enc");
         let loader : FileLoader = FileLoader{
             direction : Direction::Encrypt,
             filename_extension : "enc".to_string(),
-            header_size : 28
+            header_size : 28,
+            operation_mode : OperationMode::Gcm,
         };
-        let loaded_files = loader.load_files(&"testfiles/test2.txt".to_string());
+        let loaded_files = loader.load_files("testfiles/test2.txt");
         assert!(loaded_files.is_some());
         let loaded_files = loaded_files.unwrap();
         assert!(loaded_files.source.starts_with(b"test2.txt"));
         let source_len : usize = loaded_files.source.len();
-        let destination_len : usize = loaded_files.destination.len();
         assert_eq!(loaded_files.destination.len(), source_len + loader.header_size);
     }
 
     fn delete_if_present(path : &str) {
         if Path::new(path).exists() {
             match fs::remove_file(path) {
-                Ok(ok) => {},
-                Err(err) => {
-                    assert!(false);
+                Ok(_) => {},
+                Err(_) => {
+                    panic!();
                 }
             };
         }
@@ -214,19 +194,32 @@ mod file_loader_tests {
 
     #[test]
     fn test_load_files_decryption() {
-        delete_if_present(&"testfiles/test3.txt");
+        delete_if_present("testfiles/test3.txt");
         let loader : FileLoader = FileLoader{
             direction : Direction::Decrypt,
             filename_extension : "enc".to_string(),
-            header_size : 28
+            header_size : 28,
+            operation_mode : OperationMode::Gcm,
         };
-        let loaded_files = loader.load_files(&"testfiles/test3.txt.enc".to_string());
+        let loaded_files = loader.load_files("testfiles/test3.txt.enc");
         assert!(loaded_files.is_some());
         let loaded_files = loaded_files.unwrap();
         assert!(loaded_files.source.starts_with(b"test3.txt.enc"));
         let source_len : usize = loaded_files.source.len();
-        let destination_len : usize = loaded_files.destination.len();
         assert_eq!(loaded_files.destination.len(), source_len - loader.header_size);
         
     }
+
+    #[test]
+    fn test_load_files_chunked() {
+        let destination_path : &str = "testfiles/test3_chunked.txt";
+        delete_if_present(destination_path);
+        let loader : FileLoader = FileLoader::new(Direction::Decrypt, "enc", 20, OperationMode::ChunkedGcm);
+        let loaded_files = loader.load_files_to("testfiles/test3.txt.enc", destination_path).unwrap();
+        assert_eq!(loaded_files.destination.len(), loaded_files.source.len() - loader.header_size - TAG_SIZE);
+        drop(loaded_files);
+        fs::remove_file(destination_path).unwrap();
+        let loader : FileLoader = FileLoader::new(Direction::Decrypt, "enc", 28, OperationMode::ChunkedGcm);
+        assert!(loader.load_files_to("testfiles/test3.txt.enc", destination_path).is_none());
+    }
 }
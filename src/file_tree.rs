@@ -0,0 +1,251 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::container::{decrypted_path, detect_file_container};
+use crate::file_loader::Direction;
+use crate::progress;
+use glob::{Pattern, PatternError};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use walkdir::{DirEntry, WalkDir};
+
+/// A file to process. Without a destination the output is written next to
+/// the source, as for files given on the command line.
+pub struct FileJob {
+    pub source : String,
+    pub destination : Option<String>,
+}
+
+/// Selects files below a directory by matching glob patterns against their
+/// path relative to that directory.
+pub struct FileFilter {
+    include : Vec<Pattern>,
+    exclude : Vec<Pattern>,
+}
+
+impl FileFilter {
+    pub fn new(include : &[String], exclude : &[String]) -> Result<FileFilter, PatternError> {
+        Ok(FileFilter{
+            include : include.iter().map(|pattern| Pattern::new(pattern)).collect::<Result<_, _>>()?,
+            exclude : exclude.iter().map(|pattern| Pattern::new(pattern)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Without include patterns every file is included. Exclude patterns win.
+    pub fn matches(&self, relative_path : &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches_path(relative_path)))
+            && !self.exclude.iter().any(|pattern| pattern.matches_path(relative_path))
+    }
+}
+
+/// Expands every directory in `paths` into the regular files below it that
+/// pass `filter`. Encrypting skips files that are already encrypted and
+/// decrypting only picks those, whatever their extension. With an
+/// `output_directory` each file gets a destination in a tree below it that
+/// mirrors the source tree, native ciphertexts there end in `extension`.
+/// Entries that could not be read are returned as errors so the others can still be processed.
+pub fn collect_files(paths : &[String], filter : &FileFilter, direction : Direction,
+    output_directory : Option<&str>, extension : &str) -> Vec<Result<FileJob, String>> {
+    let mut jobs : Vec<Result<FileJob, String>> = Vec::new();
+    for path in paths {
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry : DirEntry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    jobs.push(Err(error.to_string()));
+                    continue;
+                }
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let source : &str = match entry.path().to_str() {
+                Some(source) => source,
+                None => {
+                    jobs.push(Err(format!("{}: path is not valid UTF-8", entry.path().display())));
+                    continue;
+                }
+            };
+            let relative_path : &Path = match entry.path().strip_prefix(path) {
+                Ok(relative_path) if entry.depth() > 0 => relative_path,
+                // The path itself is a file.
+                _ => Path::new(entry.file_name()),
+            };
+            if !filter.matches(relative_path) {
+                continue;
+            }
+            let encrypted : bool = match detect_file_container(source) {
+                Ok(container) => container.is_some(),
+                Err(error) => {
+                    jobs.push(Err(error.to_string()));
+                    continue;
+                }
+            };
+            let selected : bool = match direction {
+                Direction::Encrypt => !encrypted,
+                Direction::Decrypt => encrypted,
+            };
+            if !selected {
+                continue;
+            }
+            let destination : Option<String> = output_directory
+                .map(|output_directory| mirrored_path(output_directory, relative_path, direction, extension));
+            jobs.push(Ok(FileJob{source : source.to_string(), destination}));
+        }
+    }
+    jobs
+}
+
+/// Runs `action` on every job with `threads` workers, carrying on after
+/// failures. Once all jobs are done, the failures are printed in the order
+/// of the jobs, followed by a summary.
+pub fn process_files<F>(jobs : Vec<Result<FileJob, String>>, threads : usize, action : F) -> Result<(), Box<dyn Error>>
+    where F : Fn(&FileJob) -> Result<(), Box<dyn Error>> + Sync {
+    let total : usize = jobs.len();
+    let next_job : AtomicUsize = AtomicUsize::new(0);
+    let mut failures : Vec<(usize, String)> = thread::scope(|scope| {
+        let workers : Vec<_> = (0..threads.clamp(1, total.max(1))).map(|_| scope.spawn(|| {
+            let mut failures : Vec<(usize, String)> = Vec::new();
+            loop {
+                let index : usize = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(index) else {
+                    return failures;
+                };
+                let result : Result<(), Box<dyn Error>> = match job {
+                    Ok(job) => create_parent_directory(job).and_then(|_| action(job)),
+                    Err(error) => Err(error.as_str().into()),
+                };
+                if let Err(error) = result {
+                    failures.push((index, error.to_string()));
+                }
+            }
+        })).collect();
+        workers.into_iter().flat_map(|worker| worker.join().expect("worker panicked")).collect()
+    });
+    progress::finish(failures.is_empty());
+    failures.sort_by_key(|(index, _)| *index);
+    for (_, error) in &failures {
+        eprintln!("{}", error);
+    }
+    eprintln!("{} succeeded, {} failed", total - failures.len(), failures.len());
+    if !failures.is_empty() {
+        return Err(format!("{} of {} files failed", failures.len(), total).into());
+    }
+    Ok(())
+}
+
+fn mirrored_path(output_directory : &str, relative_path : &Path, direction : Direction, extension : &str) -> String {
+    let destination : PathBuf = Path::new(output_directory).join(relative_path);
+    match direction {
+        Direction::Encrypt => destination.to_string_lossy().into_owned() + "." + extension,
+        Direction::Decrypt => decrypted_path(&destination.to_string_lossy(), extension),
+    }
+}
+
+fn create_parent_directory(job : &FileJob) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = job.destination.as_deref().and_then(|destination| Path::new(destination).parent()) {
+        fs::create_dir_all(parent).map_err(|error| format!("{}: {}", parent.display(), error))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod file_tree_tests {
+    use super::*;
+    use crate::credentials::Credentials;
+    use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+    use crate::file_cipher::{encrypt_bytes, FILENAME_EXTENSION};
+    use crate::secret::Secret;
+    use std::sync::Mutex;
+
+    /// Plaintexts, a native ciphertext and an age file without an extension.
+    fn create_tree(root : &str) {
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.to_string() + "/sub/deeper").unwrap();
+        for file in ["a.txt", "b.log", "sub/c.txt", "sub/deeper/d.txt"] {
+            fs::write(root.to_string() + "/" + file, file).unwrap();
+        }
+        let parameters : CryptoParameters = CryptoParameters::builder()
+            .kdf(Kdf::None).key_encapsulation(KeyEncapsulation::None).build().unwrap();
+        let credentials : Credentials = Credentials::from_raw_key(Secret::new(vec![0x42; 32]));
+        fs::write(root.to_string() + "/sub/e.txt.enc", encrypt_bytes(b"e.txt", &credentials, &parameters).unwrap()).unwrap();
+        fs::write(root.to_string() + "/sub/deeper/f", b"age-encryption.org/v1\n").unwrap();
+    }
+
+    fn sources(jobs : &[Result<FileJob, String>]) -> Vec<&str> {
+        jobs.iter().map(|job| job.as_ref().unwrap().source.as_str()).collect()
+    }
+
+    #[test]
+    fn test_collect_files_with_filter() {
+        let root : &str = "testfiles/tree_filter";
+        create_tree(root);
+        let all : Vec<Result<FileJob, String>> = collect_files(&[root.to_string()],
+            &FileFilter::new(&[], &[]).unwrap(), Direction::Encrypt, None, FILENAME_EXTENSION);
+        let filtered : Vec<Result<FileJob, String>> = collect_files(&[root.to_string()],
+            &FileFilter::new(&["*.txt".to_string()], &["sub/deeper/*".to_string()]).unwrap(), Direction::Encrypt, None,
+            FILENAME_EXTENSION);
+        let encrypted : Vec<Result<FileJob, String>> = collect_files(&[root.to_string()],
+            &FileFilter::new(&[], &[]).unwrap(), Direction::Decrypt, None, FILENAME_EXTENSION);
+        fs::remove_dir_all(root).unwrap();
+        assert_eq!(sources(&all), ["testfiles/tree_filter/a.txt", "testfiles/tree_filter/b.log",
+            "testfiles/tree_filter/sub/c.txt", "testfiles/tree_filter/sub/deeper/d.txt"]);
+        assert_eq!(sources(&filtered), ["testfiles/tree_filter/a.txt", "testfiles/tree_filter/sub/c.txt"]);
+        assert_eq!(sources(&encrypted), ["testfiles/tree_filter/sub/deeper/f", "testfiles/tree_filter/sub/e.txt.enc"]);
+    }
+
+    #[test]
+    fn test_mirrored_destinations() {
+        let root : &str = "testfiles/tree_mirror";
+        create_tree(root);
+        let jobs : Vec<Result<FileJob, String>> = collect_files(&[root.to_string() + "/sub"],
+            &FileFilter::new(&[], &[]).unwrap(), Direction::Decrypt, Some("out"), FILENAME_EXTENSION);
+        fs::remove_dir_all(root).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].as_ref().unwrap().destination.as_deref(), Some("out/deeper/f.decrypted"));
+        assert_eq!(jobs[1].as_ref().unwrap().destination.as_deref(), Some("out/e.txt"));
+        assert_eq!(mirrored_path("out", Path::new("sub/c.txt"), Direction::Encrypt, FILENAME_EXTENSION), "out/sub/c.txt.enc");
+        assert_eq!(mirrored_path("out", Path::new("sub/c.txt"), Direction::Encrypt, "sfe"), "out/sub/c.txt.sfe");
+    }
+
+    #[test]
+    fn test_process_files_counts_failures() {
+        let jobs = || -> Vec<Result<FileJob, String>> {
+            (0..20).map(|index| match index {
+                3 => Err("unreadable".to_string()),
+                _ => Ok(FileJob{source : index.to_string(), destination : None}),
+            }).collect()
+        };
+        for threads in [1, 4] {
+            let processed : Mutex<Vec<String>> = Mutex::new(Vec::new());
+            let result = process_files(jobs(), threads, |job| {
+                processed.lock().unwrap().push(job.source.clone());
+                if job.source.ends_with('7') { Err("failed".into()) } else { Ok(()) }
+            });
+            assert_eq!(result.unwrap_err().to_string(), "3 of 20 files failed");
+            let mut processed : Vec<String> = processed.into_inner().unwrap();
+            processed.sort_by_key(|source| source.parse::<usize>().unwrap());
+            assert_eq!(processed, (0..20).filter(|index| *index != 3).map(|index| index.to_string()).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,389 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::kdf_cost::{KdfCost, KDF_COST_SIZE};
+use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+use crate::crypto_parameters::key_size::KeySize;
+
+/// Every native ciphertext starts with the magic and the format version.
+pub const MAGIC : [u8; MAGIC_SIZE] = *b"SFE\x00";
+pub const FORMAT_VERSION : u32 = 1;
+pub const MAGIC_SIZE : usize = 4;
+pub const VERSION_SIZE : usize = 4;
+pub const PARAMETERS_SIZE : usize = 32;
+pub const FLAGS_SIZE : usize = 4;
+pub const SALT_SIZE : usize = 16;
+pub const NONCE_SIZE : usize = 12;
+pub const STANZA_COUNT_SIZE : usize = 4;
+pub const TAG_SIZE : usize = 16;
+pub const EPHEMERAL_KEY_SIZE : usize = 32;
+/// The KDF of a keyslot followed by its costs.
+pub const SLOT_KDF_SIZE : usize = 4 + KDF_COST_SIZE;
+
+/// The fixed part of the header in front of the recipient stanzas. It is
+/// enough to compute the size of the whole header.
+pub const PREFIX_SIZE : usize = MAGIC_SIZE + VERSION_SIZE + PARAMETERS_SIZE + FLAGS_SIZE + SALT_SIZE + NONCE_SIZE
+    + STANZA_COUNT_SIZE;
+/// The size of a header without any recipient stanzas.
+pub const MINIMUM_HEADER_SIZE : usize = PREFIX_SIZE + TAG_SIZE;
+pub const MAXIMUM_STANZAS : usize = 4096;
+
+/// The key was derived from a password.
+pub const FLAG_PASSWORD : u32 = 0x00000001;
+/// The key was derived from the hash of a keyfile.
+pub const FLAG_KEYFILE : u32 = 0x00000002;
+/// A raw key was used directly, without a KDF.
+pub const FLAG_RAW_KEY : u32 = 0x00000004;
+/// The key is wrapped for X25519 recipients and needs an identity.
+pub const FLAG_IDENTITY : u32 = 0x00000008;
+
+const VERSION_OFFSET : usize = MAGIC_SIZE;
+const PARAMETERS_OFFSET : usize = VERSION_OFFSET + VERSION_SIZE;
+const FLAGS_OFFSET : usize = PARAMETERS_OFFSET + PARAMETERS_SIZE;
+const SALT_OFFSET : usize = FLAGS_OFFSET + FLAGS_SIZE;
+const NONCE_OFFSET : usize = SALT_OFFSET + SALT_SIZE;
+const STANZA_COUNT_OFFSET : usize = NONCE_OFFSET + NONCE_SIZE;
+
+/// The payload key wrapped for a single X25519 recipient.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RecipientStanza {
+    pub ephemeral_public_key : [u8; EPHEMERAL_KEY_SIZE],
+    pub wrapped_key : Vec<u8>,
+}
+
+/// The payload key wrapped under a key derived from one of several passwords.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Keyslot {
+    pub kdf_cost : KdfCost,
+    pub salt : [u8; SALT_SIZE],
+    pub wrapped_key : Vec<u8>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Stanza {
+    Recipient(RecipientStanza),
+    Keyslot(Keyslot),
+}
+
+/// Everything in front of the payload. The fixed fields from the magic up to
+/// the nonce are authenticated as associated data of the payload. Stanzas are not, since
+/// each of them authenticates its own wrapped key and keyslots have to be
+/// changeable without re-encrypting the payload.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Header {
+    pub parameters : CryptoParameters,
+    pub flags : u32,
+    pub salt : [u8; SALT_SIZE],
+    pub nonce : [u8; NONCE_SIZE],
+    pub stanzas : Vec<Stanza>,
+    pub tag : [u8; TAG_SIZE],
+}
+
+impl Header {
+    pub fn new(parameters : CryptoParameters, flags : u32) -> Header {
+        Header{
+            parameters,
+            flags,
+            salt : [0x00; SALT_SIZE],
+            nonce : [0x00; NONCE_SIZE],
+            stanzas : Vec::new(),
+            tag : [0x00; TAG_SIZE],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        MINIMUM_HEADER_SIZE + self.stanzas.len() * stanza_size(&self.parameters)
+    }
+
+    /// Computes the size of the whole header from its first `PREFIX_SIZE` bytes.
+    pub fn size_from_prefix(prefix : &[u8]) -> Option<usize> {
+        let header : Header = Self::parse_prefix(prefix)?;
+        let stanza_count : usize = Self::stanza_count(prefix);
+        Some(MINIMUM_HEADER_SIZE + stanza_count * stanza_size(&header.parameters))
+    }
+
+    /// Whether `byte_buffer` starts like a native ciphertext of any version.
+    pub fn has_magic(byte_buffer : &[u8]) -> bool {
+        byte_buffer.starts_with(&MAGIC)
+    }
+
+    pub fn authenticated_data(&self) -> Vec<u8> {
+        let mut byte_buffer : Vec<u8> = Vec::with_capacity(STANZA_COUNT_OFFSET);
+        byte_buffer.extend_from_slice(&MAGIC);
+        byte_buffer.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        byte_buffer.extend_from_slice(&self.parameters.to_byte_buffer());
+        byte_buffer.extend_from_slice(&self.flags.to_be_bytes());
+        byte_buffer.extend_from_slice(&self.salt);
+        byte_buffer.extend_from_slice(&self.nonce);
+        byte_buffer
+    }
+
+    pub fn to_byte_buffer(&self) -> Vec<u8> {
+        let mut byte_buffer : Vec<u8> = Vec::with_capacity(self.size());
+        byte_buffer.extend_from_slice(&self.authenticated_data());
+        byte_buffer.extend_from_slice(&(self.stanzas.len() as u32).to_be_bytes());
+        for stanza in &self.stanzas {
+            match stanza {
+                Stanza::Recipient(recipient) => {
+                    byte_buffer.extend_from_slice(&recipient.ephemeral_public_key);
+                    byte_buffer.extend_from_slice(&recipient.wrapped_key);
+                }
+                Stanza::Keyslot(keyslot) => {
+                    byte_buffer.extend_from_slice(&keyslot.kdf_cost.kdf().to_integer().to_be_bytes());
+                    byte_buffer.extend_from_slice(&keyslot.kdf_cost.to_byte_buffer());
+                    byte_buffer.extend_from_slice(&keyslot.salt);
+                    byte_buffer.extend_from_slice(&keyslot.wrapped_key);
+                }
+            }
+        }
+        byte_buffer.extend_from_slice(&self.tag);
+        byte_buffer
+    }
+
+    /// Parses the header at the start of `byte_buffer`, which may continue
+    /// with the payload.
+    pub fn from_byte_buffer(byte_buffer : &[u8]) -> Option<Header> {
+        let mut header : Header = Self::parse_prefix(byte_buffer)?;
+        let stanza_count : usize = Self::stanza_count(byte_buffer);
+        let stanza_size : usize = stanza_size(&header.parameters);
+        if byte_buffer.len() < MINIMUM_HEADER_SIZE + stanza_count * stanza_size {
+            return None;
+        }
+        for i in 0..stanza_count {
+            let offset : usize = PREFIX_SIZE + i * stanza_size;
+            let stanza : Stanza = Self::parse_stanza(&header.parameters, &byte_buffer[offset..offset + stanza_size])?;
+            header.stanzas.push(stanza);
+        }
+        let tag_offset : usize = header.size() - TAG_SIZE;
+        header.tag.copy_from_slice(&byte_buffer[tag_offset..tag_offset + TAG_SIZE]);
+
+        let key_encapsulation : KeyEncapsulation = header.parameters.key_encapsulation();
+        let valid : bool = match key_encapsulation {
+            KeyEncapsulation::None => header.stanzas.is_empty(),
+            KeyEncapsulation::X25519 => header.flags == FLAG_IDENTITY && !header.stanzas.is_empty(),
+            KeyEncapsulation::Keyslots => header.flags == FLAG_PASSWORD && !header.stanzas.is_empty(),
+        };
+        if !valid || (key_encapsulation != KeyEncapsulation::X25519 && header.flags & FLAG_IDENTITY != 0) {
+            return None;
+        }
+        Some(header)
+    }
+
+    fn parse_stanza(parameters : &CryptoParameters, byte_buffer : &[u8]) -> Option<Stanza> {
+        match parameters.key_encapsulation() {
+            KeyEncapsulation::None => None,
+            KeyEncapsulation::X25519 => {
+                let mut ephemeral_public_key : [u8; EPHEMERAL_KEY_SIZE] = [0x00; EPHEMERAL_KEY_SIZE];
+                ephemeral_public_key.copy_from_slice(&byte_buffer[0..EPHEMERAL_KEY_SIZE]);
+                Some(Stanza::Recipient(RecipientStanza{
+                    ephemeral_public_key,
+                    wrapped_key : byte_buffer[EPHEMERAL_KEY_SIZE..].to_vec(),
+                }))
+            }
+            KeyEncapsulation::Keyslots => {
+                let mut kdf_buffer : [u8; 4] = [0x00; 4];
+                kdf_buffer.copy_from_slice(&byte_buffer[0..4]);
+                let kdf : Kdf = Kdf::from_integer(u32::from_be_bytes(kdf_buffer))
+                    .filter(|kdf| *kdf != Kdf::None)?;
+                let mut kdf_cost_buffer : [u8; KDF_COST_SIZE] = [0x00; KDF_COST_SIZE];
+                kdf_cost_buffer.copy_from_slice(&byte_buffer[4..SLOT_KDF_SIZE]);
+                let kdf_cost : KdfCost = KdfCost::from_byte_buffer(kdf, &kdf_cost_buffer)?;
+                let mut salt : [u8; SALT_SIZE] = [0x00; SALT_SIZE];
+                salt.copy_from_slice(&byte_buffer[SLOT_KDF_SIZE..SLOT_KDF_SIZE + SALT_SIZE]);
+                Some(Stanza::Keyslot(Keyslot{
+                    kdf_cost,
+                    salt,
+                    wrapped_key : byte_buffer[SLOT_KDF_SIZE + SALT_SIZE..].to_vec(),
+                }))
+            }
+        }
+    }
+
+    fn parse_prefix(byte_buffer : &[u8]) -> Option<Header> {
+        if byte_buffer.len() < PREFIX_SIZE || !Self::has_magic(byte_buffer) {
+            return None;
+        }
+        let mut version_buffer : [u8; VERSION_SIZE] = [0x00; VERSION_SIZE];
+        version_buffer.copy_from_slice(&byte_buffer[VERSION_OFFSET..PARAMETERS_OFFSET]);
+        if u32::from_be_bytes(version_buffer) != FORMAT_VERSION {
+            return None;
+        }
+        let mut parameters_buffer : [u8; PARAMETERS_SIZE] = [0x00; PARAMETERS_SIZE];
+        parameters_buffer.copy_from_slice(&byte_buffer[PARAMETERS_OFFSET..FLAGS_OFFSET]);
+        let parameters = CryptoParameters::from_byte_buffer(&parameters_buffer)?;
+
+        let mut flags_buffer : [u8; FLAGS_SIZE] = [0x00; FLAGS_SIZE];
+        flags_buffer.copy_from_slice(&byte_buffer[FLAGS_OFFSET..SALT_OFFSET]);
+        let flags : u32 = u32::from_be_bytes(flags_buffer);
+        let without_kdf : bool = flags & (FLAG_RAW_KEY | FLAG_IDENTITY) != 0;
+        let derived_key : bool = flags & (FLAG_PASSWORD | FLAG_KEYFILE) != 0;
+        if without_kdf == derived_key || without_kdf != (parameters.kdf() == Kdf::None)
+            || flags == FLAG_RAW_KEY | FLAG_IDENTITY {
+            return None;
+        }
+        if Self::stanza_count(byte_buffer) > MAXIMUM_STANZAS {
+            return None;
+        }
+
+        let mut header : Header = Header::new(parameters, flags);
+        header.salt.copy_from_slice(&byte_buffer[SALT_OFFSET..NONCE_OFFSET]);
+        header.nonce.copy_from_slice(&byte_buffer[NONCE_OFFSET..STANZA_COUNT_OFFSET]);
+        Some(header)
+    }
+
+    fn stanza_count(byte_buffer : &[u8]) -> usize {
+        let mut count_buffer : [u8; STANZA_COUNT_SIZE] = [0x00; STANZA_COUNT_SIZE];
+        count_buffer.copy_from_slice(&byte_buffer[STANZA_COUNT_OFFSET..PREFIX_SIZE]);
+        u32::from_be_bytes(count_buffer) as usize
+    }
+}
+
+/// A stanza holds an ephemeral public key or a KDF, its costs and a salt, followed by
+/// the wrapped payload key and its tag.
+fn stanza_size(parameters : &CryptoParameters) -> usize {
+    let wrapped_key_size : usize = wrapped_key_size(parameters.key_size());
+    match parameters.key_encapsulation() {
+        KeyEncapsulation::None => 0,
+        KeyEncapsulation::X25519 => EPHEMERAL_KEY_SIZE + wrapped_key_size,
+        KeyEncapsulation::Keyslots => SLOT_KDF_SIZE + SALT_SIZE + wrapped_key_size,
+    }
+}
+
+pub fn wrapped_key_size(key_size : KeySize) -> usize {
+    key_size.to_integer() as usize / 8 + TAG_SIZE
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::*;
+    use crate::crypto_parameters::block_cipher::BlockCipher;
+    use crate::crypto_parameters::operation_mode::OperationMode;
+
+    fn test_header(parameters : CryptoParameters, flags : u32) -> Header {
+        let mut header : Header = Header::new(parameters, flags);
+        header.salt = [0x01; SALT_SIZE];
+        header.nonce = [0x02; NONCE_SIZE];
+        header.tag = [0x03; TAG_SIZE];
+        header
+    }
+
+    fn parameters(kdf : Kdf, key_encapsulation : KeyEncapsulation) -> CryptoParameters {
+        CryptoParameters::new(BlockCipher::Aes, kdf, KeySize::Size256, OperationMode::Gcm, key_encapsulation)
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header : Header = test_header(parameters(Kdf::Pbkdf2HmacSha3512, KeyEncapsulation::None),
+            FLAG_PASSWORD | FLAG_KEYFILE);
+        let byte_buffer : Vec<u8> = header.to_byte_buffer();
+        assert_eq!(byte_buffer.len(), MINIMUM_HEADER_SIZE);
+        assert_eq!(header.size(), MINIMUM_HEADER_SIZE);
+        assert_eq!(byte_buffer[0..MAGIC_SIZE], *b"SFE\x00");
+        assert_eq!(byte_buffer[VERSION_OFFSET..PARAMETERS_OFFSET], [0x00, 0x00, 0x00, 0x01]);
+        assert_eq!(byte_buffer[PARAMETERS_OFFSET..FLAGS_OFFSET], header.parameters.to_byte_buffer());
+        assert_eq!(byte_buffer[FLAGS_OFFSET..SALT_OFFSET], [0x00, 0x00, 0x00, 0x03]);
+        assert_eq!(byte_buffer[MINIMUM_HEADER_SIZE - 1], 0x03);
+        assert_eq!(Header::from_byte_buffer(&byte_buffer).unwrap(), header);
+        assert_eq!(Header::size_from_prefix(&byte_buffer[0..PREFIX_SIZE]).unwrap(), MINIMUM_HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_header_round_trip_with_stanzas() {
+        let mut header : Header = test_header(parameters(Kdf::None, KeyEncapsulation::X25519), FLAG_IDENTITY);
+        for i in 0..3 {
+            header.stanzas.push(Stanza::Recipient(RecipientStanza{
+                ephemeral_public_key : [i; EPHEMERAL_KEY_SIZE],
+                wrapped_key : vec![i; 32 + TAG_SIZE],
+            }));
+        }
+        let mut byte_buffer : Vec<u8> = header.to_byte_buffer();
+        assert_eq!(byte_buffer.len(), MINIMUM_HEADER_SIZE + 3 * (EPHEMERAL_KEY_SIZE + 32 + TAG_SIZE));
+        assert_eq!(Header::size_from_prefix(&byte_buffer).unwrap(), byte_buffer.len());
+        byte_buffer.extend_from_slice(b"payload");
+        assert_eq!(Header::from_byte_buffer(&byte_buffer).unwrap(), header);
+        assert_eq!(Header::from_byte_buffer(&byte_buffer[0..header.size() - 1]), None);
+    }
+
+    #[test]
+    fn test_header_round_trip_with_keyslots() {
+        let mut header : Header = test_header(parameters(Kdf::Pbkdf2HmacSha3512, KeyEncapsulation::Keyslots),
+            FLAG_PASSWORD);
+        for i in 0..2 {
+            header.stanzas.push(Stanza::Keyslot(Keyslot{
+                kdf_cost : KdfCost::Argon2id{memory_kib : 1024, iterations : i as u32 + 1, parallelism : 1},
+                salt : [i; SALT_SIZE],
+                wrapped_key : vec![i; 32 + TAG_SIZE],
+            }));
+        }
+        let byte_buffer : Vec<u8> = header.to_byte_buffer();
+        assert_eq!(byte_buffer.len(), MINIMUM_HEADER_SIZE + 2 * (SLOT_KDF_SIZE + SALT_SIZE + 32 + TAG_SIZE));
+        assert_eq!(Header::from_byte_buffer(&byte_buffer).unwrap(), header);
+        assert_eq!(header.authenticated_data(), byte_buffer[0..STANZA_COUNT_OFFSET]);
+        header.flags = FLAG_KEYFILE;
+        assert_eq!(Header::from_byte_buffer(&header.to_byte_buffer()), None);
+    }
+
+    #[test]
+    fn test_header_recipients_require_stanzas() {
+        let header : Header = test_header(parameters(Kdf::None, KeyEncapsulation::X25519), FLAG_IDENTITY);
+        assert_eq!(Header::from_byte_buffer(&header.to_byte_buffer()), None);
+    }
+
+    #[test]
+    fn test_header_without_credential_flags() {
+        let header : Header = test_header(parameters(Kdf::Pbkdf2HmacSha3512, KeyEncapsulation::None), 0);
+        assert_eq!(Header::from_byte_buffer(&header.to_byte_buffer()), None);
+    }
+
+    #[test]
+    fn test_header_raw_key_requires_kdf_none() {
+        let mut header : Header = test_header(parameters(Kdf::Pbkdf2HmacSha3512, KeyEncapsulation::None), FLAG_RAW_KEY);
+        assert_eq!(Header::from_byte_buffer(&header.to_byte_buffer()), None);
+        header.parameters = parameters(Kdf::None, KeyEncapsulation::None);
+        assert_eq!(Header::from_byte_buffer(&header.to_byte_buffer()).unwrap(), header);
+        header.flags = FLAG_RAW_KEY | FLAG_PASSWORD;
+        assert_eq!(Header::from_byte_buffer(&header.to_byte_buffer()), None);
+    }
+
+    #[test]
+    fn test_header_requires_magic_and_version() {
+        let header : Header = test_header(parameters(Kdf::Pbkdf2HmacSha3512, KeyEncapsulation::None), FLAG_PASSWORD);
+        let byte_buffer : Vec<u8> = header.to_byte_buffer();
+        assert!(Header::has_magic(&byte_buffer));
+        let without_magic : Vec<u8> = byte_buffer[MAGIC_SIZE + VERSION_SIZE..].to_vec();
+        assert!(!Header::has_magic(&without_magic));
+        assert_eq!(Header::from_byte_buffer(&without_magic), None);
+        let mut wrong_magic : Vec<u8> = byte_buffer.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(Header::from_byte_buffer(&wrong_magic), None);
+        let mut next_version : Vec<u8> = byte_buffer.clone();
+        next_version[PARAMETERS_OFFSET - 1] = 0x02;
+        assert!(Header::has_magic(&next_version));
+        assert_eq!(Header::from_byte_buffer(&next_version), None);
+        assert_eq!(Header::size_from_prefix(&next_version), None);
+    }
+
+    #[test]
+    fn test_header_from_short_buffer() {
+        let byte_buffer : [u8; MINIMUM_HEADER_SIZE - 1] = [0x00; MINIMUM_HEADER_SIZE - 1];
+        assert_eq!(Header::from_byte_buffer(&byte_buffer), None);
+        assert_eq!(Header::size_from_prefix(&byte_buffer[0..PREFIX_SIZE - 1]), None);
+    }
+}
@@ -0,0 +1,109 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::kdf_cost::KdfCost;
+use crate::crypto_parameters::key_size::KeySize;
+use crate::secret::Secret;
+use argon2::{Algorithm, Argon2, Version};
+use sha3::Sha3_512;
+
+/// Derives a key of `key_size` from `password` with the KDF and costs of
+/// `kdf_cost`. With `KdfCost::None` the password is a raw key and is only
+/// accepted if it has exactly the requested size.
+pub fn derive_key(password : &Secret, salt : &[u8], kdf_cost : KdfCost, key_size : KeySize) -> Option<Secret> {
+    if !kdf_cost.is_valid() {
+        return None;
+    }
+    let mut key : Vec<u8> = vec![0x00; key_size.to_integer() as usize / 8];
+    match kdf_cost {
+        KdfCost::Pbkdf2HmacSha3512{iterations} => {
+            pbkdf2::pbkdf2_hmac::<Sha3_512>(password.expose(), salt, iterations, &mut key);
+        }
+        KdfCost::Scrypt{log_n, r, p} => {
+            let parameters : scrypt::Params = scrypt::Params::new(log_n, r, p, key.len()).ok()?;
+            scrypt::scrypt(password.expose(), salt, &parameters, &mut key).ok()?;
+        }
+        KdfCost::Argon2id{memory_kib, iterations, parallelism} => {
+            let parameters : argon2::Params = argon2::Params::new(memory_kib, iterations, parallelism, Some(key.len()))
+                .ok()?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, parameters)
+                .hash_password_into(password.expose(), salt, &mut key).ok()?;
+        }
+        KdfCost::None => {
+            if password.expose().len() != key.len() {
+                return None;
+            }
+            key.copy_from_slice(password.expose());
+        }
+    }
+    Some(Secret::new(key))
+}
+
+#[cfg(test)]
+mod key_derivation_tests {
+    use super::*;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::kdf_cost::PBKDF2_ITERATIONS;
+
+    const PBKDF2 : KdfCost = KdfCost::Pbkdf2HmacSha3512{iterations : PBKDF2_ITERATIONS};
+
+    #[test]
+    fn test_derive_key_length() {
+        let password : Secret = Secret::from("password".to_string());
+        assert_eq!(derive_key(&password, b"salt", PBKDF2, KeySize::Size128).unwrap().expose().len(), 16);
+        assert_eq!(derive_key(&password, b"salt", PBKDF2, KeySize::Size192).unwrap().expose().len(), 24);
+        assert_eq!(derive_key(&password, b"salt", PBKDF2, KeySize::Size256).unwrap().expose().len(), 32);
+    }
+
+    #[test]
+    fn test_derive_key_depends_on_salt() {
+        let password : Secret = Secret::from("password".to_string());
+        let key1 : Secret = derive_key(&password, b"salt1", PBKDF2, KeySize::Size256).unwrap();
+        let key2 : Secret = derive_key(&password, b"salt1", PBKDF2, KeySize::Size256).unwrap();
+        let key3 : Secret = derive_key(&password, b"salt2", PBKDF2, KeySize::Size256).unwrap();
+        assert_eq!(key1.expose(), key2.expose());
+        assert_ne!(key1.expose(), key3.expose());
+    }
+
+    #[test]
+    fn test_derive_key_scrypt() {
+        let password : Secret = Secret::from("password".to_string());
+        let key : Secret = derive_key(&password, b"salt", KdfCost::default_for(Kdf::Scrypt), KeySize::Size192).unwrap();
+        assert_eq!(key.expose().len(), 24);
+        let pbkdf2_key : Secret = derive_key(&password, b"salt", PBKDF2, KeySize::Size192).unwrap();
+        assert_ne!(key.expose(), pbkdf2_key.expose());
+    }
+
+    #[test]
+    fn test_derive_key_argon2id() {
+        let password : Secret = Secret::from("password".to_string());
+        let kdf_cost : KdfCost = KdfCost::Argon2id{memory_kib : 1024, iterations : 2, parallelism : 1};
+        let key : Secret = derive_key(&password, b"saltsalt", kdf_cost, KeySize::Size256).unwrap();
+        assert_eq!(key.expose().len(), 32);
+        let default_key : Secret = derive_key(&password, b"saltsalt", KdfCost::default_for(Kdf::Argon2id),
+            KeySize::Size256).unwrap();
+        assert_ne!(key.expose(), default_key.expose());
+    }
+
+    #[test]
+    fn test_derive_key_none() {
+        let raw_key : Secret = Secret::new(vec![0x42; 16]);
+        assert_eq!(derive_key(&raw_key, b"salt", KdfCost::None, KeySize::Size128).unwrap().expose(), [0x42; 16]);
+        assert!(derive_key(&raw_key, b"salt", KdfCost::None, KeySize::Size256).is_none());
+    }
+}
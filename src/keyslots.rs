@@ -0,0 +1,248 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::credentials::Credentials;
+use crate::crypto_error::CryptoError;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::kdf_cost::KdfCost;
+use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+use crate::file_cipher::{open, read_header, rewrite_header, seal};
+use crate::header::{Header, Keyslot, Stanza, NONCE_SIZE, SALT_SIZE, TAG_SIZE};
+use crate::key_derivation::derive_key;
+use crate::secret::Secret;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+/// Every keyslot key is derived from its own salt and used exactly once, so
+/// a constant nonce is fine.
+const KEYSLOT_NONCE : [u8; NONCE_SIZE] = [0x00; NONCE_SIZE];
+
+/// Wraps the payload `key` under a key derived from `password` with the KDF
+/// and costs of `kdf_cost`.
+pub fn create_keyslot(key : &Secret, password : &Secret, kdf_cost : KdfCost, parameters : &CryptoParameters)
+    -> Option<Keyslot> {
+    let mut salt : [u8; SALT_SIZE] = [0x00; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    let keyslot_key : Secret = derive_key(password, &salt, kdf_cost, parameters.key_size())?;
+    let mut wrapped_key : Vec<u8> = key.expose().to_vec();
+    let tag : [u8; TAG_SIZE] = seal(parameters, &keyslot_key, &KEYSLOT_NONCE, &[], &mut wrapped_key)?;
+    wrapped_key.extend_from_slice(&tag);
+    Some(Keyslot{
+        kdf_cost,
+        salt,
+        wrapped_key,
+    })
+}
+
+/// Unwraps the payload key from `keyslot`, or returns `None` if `password` does not belong to it.
+pub fn open_keyslot(keyslot : &Keyslot, password : &Secret, parameters : &CryptoParameters) -> Option<Secret> {
+    let keyslot_key : Secret = derive_key(password, &keyslot.salt, keyslot.kdf_cost, parameters.key_size())?;
+    unwrap_keyslot(keyslot, &keyslot_key, parameters)
+}
+
+/// Tries `password` on every keyslot of `header`.
+pub fn unlock_keyslots(header : &Header, password : &Secret) -> Option<Secret> {
+    keyslots(header).find_map(|keyslot| open_keyslot(keyslot, password, &header.parameters))
+}
+
+/// Like `unlock_keyslots`, but the keyslot keys are derived through
+/// `credentials`, which remember them for other files sharing the keyslot.
+pub fn unlock_keyslots_with(header : &Header, credentials : &Credentials) -> Option<Secret> {
+    keyslots(header).find_map(|keyslot| {
+        let keyslot_key : Secret = credentials.derive_key(header.flags, &keyslot.salt, keyslot.kdf_cost,
+            header.parameters.key_size())?;
+        unwrap_keyslot(keyslot, &keyslot_key, &header.parameters)
+    })
+}
+
+fn unwrap_keyslot(keyslot : &Keyslot, keyslot_key : &Secret, parameters : &CryptoParameters) -> Option<Secret> {
+    let key_length : usize = keyslot.wrapped_key.len().checked_sub(TAG_SIZE)?;
+    let mut key : Zeroizing<Vec<u8>> = Zeroizing::new(keyslot.wrapped_key[0..key_length].to_vec());
+    if open(parameters, keyslot_key, &KEYSLOT_NONCE, &[], &mut key, &keyslot.wrapped_key[key_length..]) {
+        Some(Secret::new(key.to_vec()))
+    } else {
+        None
+    }
+}
+
+/// Adds a keyslot for `new_password` to an encrypted file, which has to be
+/// unlocked with `password` first. The keyslot uses the KDF and costs of
+/// `kdf_cost`, or those of the file if it is `None`. Only the header is
+/// rewritten.
+pub fn add_keyslot(path : &str, password : &Secret, new_password : &Secret, kdf_cost : Option<KdfCost>)
+    -> Result<(), CryptoError> {
+    let mut header : Header = read_keyslot_header(path)?;
+    let kdf_cost : KdfCost = kdf_cost.unwrap_or(header.parameters.kdf_cost());
+    if kdf_cost.kdf() == Kdf::None || !kdf_cost.is_valid() {
+        return Err(CryptoError::UnsupportedParameters(path.to_string()));
+    }
+    let key : Secret = unlock_keyslots(&header, password)
+        .ok_or_else(|| CryptoError::AuthenticationFailed(path.to_string()))?;
+    let keyslot : Keyslot = create_keyslot(&key, new_password, kdf_cost, &header.parameters)
+        .ok_or_else(|| CryptoError::Encryption(path.to_string()))?;
+    header.stanzas.push(Stanza::Keyslot(keyslot));
+    rewrite_header(path, &header)
+}
+
+/// Rewraps the payload key of the keyslot that `password` opens under
+/// `new_password`. Like every header change, the file is rewritten next to
+/// the old one and moved over it, so an interrupted write cannot leave a
+/// file that no password opens. The payload is copied unchanged.
+pub fn change_password(path : &str, password : &Secret, new_password : &Secret) -> Result<(), CryptoError> {
+    let mut header : Header = read_keyslot_header(path)?;
+    let (index, kdf_cost, key) = header.stanzas.iter().enumerate()
+        .find_map(|(index, stanza)| match stanza {
+            Stanza::Keyslot(keyslot) =>
+                Some((index, keyslot.kdf_cost, open_keyslot(keyslot, password, &header.parameters)?)),
+            Stanza::Recipient(_) => None,
+        })
+        .ok_or_else(|| CryptoError::AuthenticationFailed(path.to_string()))?;
+    let keyslot : Keyslot = create_keyslot(&key, new_password, kdf_cost, &header.parameters)
+        .ok_or_else(|| CryptoError::Encryption(path.to_string()))?;
+    header.stanzas[index] = Stanza::Keyslot(keyslot);
+    rewrite_header(path, &header)
+}
+
+/// Removes the keyslot at `index`. `password` has to unlock any keyslot, and
+/// the last keyslot cannot be removed.
+pub fn remove_keyslot(path : &str, password : &Secret, index : usize) -> Result<(), CryptoError> {
+    let mut header : Header = read_keyslot_header(path)?;
+    if index >= header.stanzas.len() {
+        return Err(CryptoError::NoSuchKeyslot(path.to_string(), index));
+    }
+    if header.stanzas.len() == 1 {
+        return Err(CryptoError::LastKeyslot(path.to_string()));
+    }
+    unlock_keyslots(&header, password).ok_or_else(|| CryptoError::AuthenticationFailed(path.to_string()))?;
+    header.stanzas.remove(index);
+    rewrite_header(path, &header)
+}
+
+/// Lists the KDF and costs of every keyslot of an encrypted file.
+pub fn list_keyslots(path : &str) -> Result<Vec<KdfCost>, CryptoError> {
+    let header : Header = read_keyslot_header(path)?;
+    Ok(keyslots(&header).map(|keyslot| keyslot.kdf_cost).collect())
+}
+
+fn read_keyslot_header(path : &str) -> Result<Header, CryptoError> {
+    let header : Header = read_header(path)?;
+    if header.parameters.key_encapsulation() != KeyEncapsulation::Keyslots {
+        return Err(CryptoError::NoKeyslots(path.to_string()));
+    }
+    Ok(header)
+}
+
+fn keyslots(header : &Header) -> impl Iterator<Item = &Keyslot> {
+    header.stanzas.iter().filter_map(|stanza| match stanza {
+        Stanza::Keyslot(keyslot) => Some(keyslot),
+        Stanza::Recipient(_) => None,
+    })
+}
+
+#[cfg(test)]
+mod keyslots_tests {
+    use super::*;
+    use crate::credentials::Credentials;
+    use crate::file_cipher::{decrypt_file, encrypt_file, FILENAME_EXTENSION};
+    use std::fs;
+
+    fn secret(password : &str) -> Secret {
+        Secret::from(password.to_string())
+    }
+
+    #[test]
+    fn test_create_and_open_keyslot() {
+        let parameters : CryptoParameters = CryptoParameters::default();
+        let key : Secret = Secret::new(vec![0x42; 32]);
+        let keyslot : Keyslot = create_keyslot(&key, &secret("password"), parameters.kdf_cost(), &parameters).unwrap();
+        assert_eq!(keyslot.wrapped_key.len(), 32 + TAG_SIZE);
+        assert_eq!(open_keyslot(&keyslot, &secret("password"), &parameters).unwrap().expose(), key.expose());
+        assert!(open_keyslot(&keyslot, &secret("wrong"), &parameters).is_none());
+    }
+
+    #[test]
+    fn test_change_password() {
+        let path : &str = "testfiles/change_password.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        let _ = fs::remove_file(&ciphertext_path);
+        fs::write(path, b"GNAMPF!").unwrap();
+        encrypt_file(path, &Credentials::from(secret("old")), CryptoParameters::default()).unwrap();
+        fs::remove_file(path).unwrap();
+        let ciphertext : Vec<u8> = fs::read(&ciphertext_path).unwrap();
+        let header_size : usize = read_header(&ciphertext_path).unwrap().size();
+        let unrelated_path : String = ciphertext_path.clone() + ".tmp";
+        fs::write(&unrelated_path, b"unrelated").unwrap();
+
+        assert!(matches!(change_password(&ciphertext_path, &secret("wrong"), &secret("new")),
+            Err(CryptoError::AuthenticationFailed(_))));
+        change_password(&ciphertext_path, &secret("old"), &secret("new")).unwrap();
+        let new_ciphertext : Vec<u8> = fs::read(&ciphertext_path).unwrap();
+        assert_eq!(new_ciphertext.len(), ciphertext.len());
+        assert_ne!(new_ciphertext[0..header_size], ciphertext[0..header_size]);
+        assert_eq!(new_ciphertext[header_size..], ciphertext[header_size..]);
+        assert_eq!(fs::read(&unrelated_path).unwrap(), b"unrelated");
+        fs::remove_file(&unrelated_path).unwrap();
+
+        assert!(decrypt_file(&ciphertext_path, &Credentials::from(secret("old"))).is_err());
+        decrypt_file(&ciphertext_path, &Credentials::from(secret("new"))).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_add_and_remove_keyslots() {
+        let path : &str = "testfiles/keyslots.txt";
+        let ciphertext_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        let _ = fs::remove_file(&ciphertext_path);
+        fs::write(path, b"GNAMPF!").unwrap();
+        encrypt_file(path, &Credentials::from(secret("first")), CryptoParameters::default()).unwrap();
+        fs::remove_file(path).unwrap();
+        let payload : Vec<u8> = fs::read(&ciphertext_path).unwrap()[read_header(&ciphertext_path).unwrap().size()..].to_vec();
+
+        let argon2id : KdfCost = KdfCost::Argon2id{memory_kib : 1024, iterations : 2, parallelism : 1};
+        assert!(matches!(add_keyslot(&ciphertext_path, &secret("wrong"), &secret("second"), None),
+            Err(CryptoError::AuthenticationFailed(_))));
+        assert!(matches!(add_keyslot(&ciphertext_path, &secret("first"), &secret("second"), Some(KdfCost::None)),
+            Err(CryptoError::UnsupportedParameters(_))));
+        add_keyslot(&ciphertext_path, &secret("first"), &secret("second"), Some(argon2id)).unwrap();
+        assert_eq!(list_keyslots(&ciphertext_path).unwrap(), vec![CryptoParameters::default().kdf_cost(), argon2id]);
+        let header : Header = read_header(&ciphertext_path).unwrap();
+        assert_eq!(fs::read(&ciphertext_path).unwrap()[header.size()..], payload);
+
+        for password in ["first", "second"] {
+            decrypt_file(&ciphertext_path, &Credentials::from(secret(password))).unwrap();
+            assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(matches!(remove_keyslot(&ciphertext_path, &secret("second"), 2),
+            Err(CryptoError::NoSuchKeyslot(_, 2))));
+        remove_keyslot(&ciphertext_path, &secret("second"), 0).unwrap();
+        assert_eq!(list_keyslots(&ciphertext_path).unwrap().len(), 1);
+        assert!(decrypt_file(&ciphertext_path, &Credentials::from(secret("first"))).is_err());
+        assert!(matches!(remove_keyslot(&ciphertext_path, &secret("second"), 0),
+            Err(CryptoError::LastKeyslot(_))));
+        decrypt_file(&ciphertext_path, &Credentials::from(secret("second"))).unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"GNAMPF!");
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+pub mod age;
+pub mod archive;
+#[cfg(feature = "tokio")]
+pub mod async_stream;
+mod armor;
+pub mod config;
+pub mod container;
+pub mod credentials;
+pub mod crypto_error;
+pub mod crypto_parameters;
+pub mod file_cipher;
+pub mod file_loader;
+pub mod file_tree;
+pub mod header;
+mod key_derivation;
+pub mod keyslots;
+mod memory_lock;
+pub mod openpgp;
+pub mod overwrite;
+pub mod passphrase_generator;
+pub mod profile;
+pub mod progress;
+pub mod raw_key;
+pub mod recipients;
+pub mod secret;
+mod segments;
+pub mod stream;
+
+pub use crate::credentials::Credentials;
+pub use crate::crypto_error::CryptoError;
+pub use crate::crypto_parameters::{BlockCipher, CryptoParameters, CryptoParametersBuilder, Kdf, KeyEncapsulation, KeySize,
+    OperationMode, ParameterError};
+pub use crate::file_cipher::{decrypt_bytes, decrypt_file, decrypt_file_to, encrypt_bytes, encrypt_file, encrypt_file_to,
+    read_header};
+pub use crate::file_loader::{Direction, FileLoader, LoadedFiles};
+pub use crate::profile::Profile;
+pub use crate::secret::Secret;
+pub use crate::stream::{Decryptor, Encryptor};
+#[cfg(feature = "tokio")]
+pub use crate::async_stream::{AsyncDecryptor, AsyncEncryptor};
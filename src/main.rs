@@ -16,20 +16,395 @@
  * You should have received a copy of the GNU General Public License
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
-mod crypto_parameters;
-mod file_loader;
+mod arguments;
 mod password_error;
 mod password_getter;
 
-use crate::password_getter::get_password;
+use crate::arguments::{Arguments, Command, DecryptArguments, DecryptionKeyArguments, EncryptArguments,
+    EncryptionKeyArguments, Format, OverwriteArguments, PackArguments, ProfileArguments, Progress, RekeyArguments,
+    SaltPolicy, SlotCommand, TreeArguments, UnpackArguments};
+use crate::password_getter::{get_existing_password, get_password, prompt_new_password, set_password_source};
+use sfe::age;
+use sfe::age::{decrypt_age_file_to, encrypt_age_file_to};
+use sfe::archive::{default_archive_path, pack_directory, unpack_archive};
+use sfe::config::{Config, PasswordSource};
+use sfe::container::{decrypted_path, detect_file_container, Container};
+use sfe::credentials::{hash_keyfile, Credentials};
+use sfe::crypto_error::CryptoError;
+use sfe::crypto_parameters::{BlockCipher, CryptoParameters, CryptoParametersBuilder, Kdf, KdfCost, KeyEncapsulation,
+    KeySize, OperationMode};
+use sfe::file_cipher::{decrypt_file_to, encrypt_armored_file_to, encrypt_file_to, read_header, rekey_file};
+use sfe::file_loader::Direction;
+use sfe::file_tree::{collect_files, process_files, FileFilter, FileJob};
+use sfe::header::{Header, FLAG_PASSWORD};
+use sfe::keyslots::{add_keyslot, change_password, list_keyslots, remove_keyslot};
+use sfe::openpgp;
+use sfe::openpgp::{export_file_to, import_file_to, MessageVersion};
+use sfe::overwrite::{write_destination, OverwritePolicy};
+use sfe::passphrase_generator::{generate_passphrase, passphrase_entropy};
+use sfe::profile::Profile;
+use sfe::progress;
+use sfe::progress::ProgressMode;
+use sfe::raw_key::load_raw_key;
+use sfe::recipients::{format_identity, format_recipient, generate_identity, load_identity, parse_recipient};
+use sfe::secret::Secret;
+use clap::Parser;
+use std::error::Error;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Write};
+use std::sync::{Mutex, RwLock};
+use x25519_dalek::PublicKey;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let password : String = match get_password() {
-        Ok(string) => string,
-        Err(error) => {
-            return Err(error.into());
+fn main() -> Result<(), Box<dyn Error>> {
+    let arguments : Arguments = Arguments::parse();
+    let mut config : Config = Config::load(arguments.config.as_deref())?;
+    if let Some(extension) = &arguments.filename_extension {
+        config.set_filename_extension(extension)?;
+    }
+    let password_source : PasswordSource = match &arguments.password_source {
+        Some(name) => PasswordSource::from_name(name).ok_or(format!("unknown password source {}", name))?,
+        None => config.password_source()?,
+    };
+    set_password_source(password_source);
+    match arguments.command {
+        Command::Encrypt(arguments) => encrypt(&arguments, &config),
+        Command::Decrypt(arguments) => decrypt(&arguments, &config),
+        Command::Genpass{words, files, overwrite} => {
+            let passphrase : Secret = generate_passphrase(words);
+            // The passphrase is the one secret that has to be shown, since
+            // nobody can use it otherwise.
+            println!("{}", String::from_utf8_lossy(passphrase.expose()));
+            eprintln!("Entropy: {:.1} bits", passphrase_entropy(words));
+            encrypt_files(&files, &TreeArguments::default(), &Credentials::from(passphrase), config.parameters(None)?,
+                false, &config, overwrite_policy(&overwrite, &config)?)
+        }
+        Command::Keygen{output} => keygen(output.as_deref()),
+        Command::Rekey(arguments) => rekey(&arguments),
+        Command::Passwd{file} => {
+            let password : Secret = get_existing_password()?;
+            eprintln!("New password:");
+            Ok(change_password(&file, &password, &prompt_new_password()?)?)
+        }
+        Command::Pack(arguments) => pack(&arguments, &config),
+        Command::Unpack(arguments) => unpack(&arguments),
+        Command::Slot(command) => slot(command),
+        Command::Profiles => {
+            Profile::ALL.iter().for_each(|profile| println!("{}", profile));
+            Ok(())
+        }
+    }
+}
+
+fn encrypt(arguments : &EncryptArguments, config : &Config) -> Result<(), Box<dyn Error>> {
+    let default : CryptoParameters = CryptoParameters::default();
+    if arguments.armor && arguments.format != Format::Native {
+        return Err("--armor only applies to the native format".into());
+    }
+    if arguments.tree.output_dir.is_some() && arguments.format != Format::Native {
+        return Err("--output-dir only applies to the native format".into());
+    }
+    if arguments.profile.profile.is_some() && arguments.format != Format::Native {
+        return Err("--profile only applies to the native format".into());
+    }
+    let files : &[String] = &arguments.files;
+    let overwrite : OverwritePolicy = overwrite_policy(&arguments.overwrite, config)?;
+    if arguments.format == Format::Openpgp || arguments.format == Format::OpenpgpV6 {
+        if !arguments.key.recipient.is_empty() {
+            return Err("OpenPGP messages can only be encrypted with a password".into());
+        }
+        let version : MessageVersion = match arguments.format {
+            Format::OpenpgpV6 => MessageVersion::V6,
+            _ => MessageVersion::V4,
+        };
+        let password : Secret = get_password()?;
+        for_each_file(files, &arguments.tree, Direction::Encrypt, config, |job| {
+            write_to(&(job.source.clone() + "." + openpgp::FILENAME_EXTENSION), overwrite,
+                |destination| export_file_to(&job.source, destination, &password, default, version))
+        })
+    } else if arguments.format == Format::Age {
+        let credentials : Credentials = if arguments.key.recipient.is_empty() {
+            Credentials::from(get_password()?)
+        } else {
+            Credentials::for_recipients(parse_recipients(&arguments.key.recipient)?)
+        };
+        for_each_file(files, &arguments.tree, Direction::Encrypt, config, |job| {
+            write_to(&(job.source.clone() + "." + age::FILENAME_EXTENSION), overwrite,
+                |destination| encrypt_age_file_to(&job.source, destination, &credentials))
+        })
+    } else {
+        let parameters : CryptoParameters = config.parameters(profile(&arguments.profile))?;
+        let (mut credentials, parameters) = native_encryption(&arguments.key, parameters)?;
+        if arguments.salt_policy == SaltPolicy::PerRun {
+            credentials.share_salt();
         }
+        encrypt_files(files, &arguments.tree, &credentials, parameters, arguments.armor, config, overwrite)
+    }
+}
+
+/// Credentials for the native format and the `parameters` adjusted to them,
+/// asking for a password unless the key comes from somewhere else.
+fn native_encryption(key : &EncryptionKeyArguments, parameters : CryptoParameters)
+    -> Result<(Credentials, CryptoParameters), Box<dyn Error>> {
+    if let Some(raw_key) = &key.raw_key {
+        let (raw_key, key_size) = load_raw_key(raw_key)?;
+        let parameters : CryptoParameters = CryptoParametersBuilder::from(parameters)
+            .kdf(Kdf::None)
+            .key_size(key_size)
+            .key_encapsulation(KeyEncapsulation::None)
+            .build()?;
+        Ok((Credentials::from_raw_key(raw_key), parameters))
+    } else if !key.recipient.is_empty() {
+        let recipients : Vec<PublicKey> = parse_recipients(&key.recipient)?;
+        let parameters : CryptoParameters = CryptoParametersBuilder::from(parameters)
+            .kdf(Kdf::None)
+            .key_encapsulation(KeyEncapsulation::X25519)
+            .build()?;
+        Ok((Credentials::for_recipients(recipients), parameters))
+    } else {
+        let keyfile_hash : Option<Secret> = key.keyfile.as_deref().map(hash_keyfile).transpose()?;
+        let password : Option<Secret> = if key.no_password { None } else { Some(get_password()?) };
+        // Keyslots only hold passwords, a keyfile goes straight into the KDF.
+        let parameters : CryptoParameters = if keyfile_hash.is_some() {
+            CryptoParametersBuilder::from(parameters).key_encapsulation(KeyEncapsulation::None).build()?
+        } else {
+            parameters
+        };
+        Ok((Credentials::new(password, keyfile_hash), parameters))
+    }
+}
+
+fn profile(arguments : &ProfileArguments) -> Option<Profile> {
+    arguments.profile.as_deref().and_then(Profile::from_name)
+}
+
+fn parse_recipients(recipients : &[String]) -> Result<Vec<PublicKey>, Box<dyn Error>> {
+    let recipients : Vec<PublicKey> = recipients.iter()
+        .map(|recipient| parse_recipient(recipient).ok_or(format!("{}: not a valid recipient", recipient)))
+        .collect::<Result<_, _>>()?;
+    Ok(recipients)
+}
+
+fn decrypt(arguments : &DecryptArguments, config : &Config) -> Result<(), Box<dyn Error>> {
+    // Workers share the credentials, only asking for a password takes the write lock.
+    let credentials : RwLock<Credentials> = RwLock::new(decryption_credentials(&arguments.key)?);
+    let overwrite : OverwritePolicy = overwrite_policy(&arguments.overwrite, config)?;
+    for_each_file(&arguments.files, &arguments.tree, Direction::Decrypt, config, |job| {
+        let file : &str = &job.source;
+        let container : Container = detect_file_container(file)?
+            .ok_or(format!("{}: not an sfe, OpenPGP or age file", file))?;
+        if container != Container::Native && job.destination.is_some() {
+            return Err(format!("{}: --output-dir only applies to the native format", file).into());
+        }
+        let destination : String = job.destination.clone()
+            .unwrap_or_else(|| decrypted_path(file, config.filename_extension()));
+        match container {
+            Container::OpenPgp => {
+                ask_for_shared_password(&credentials, |credentials| !credentials.has_password())?;
+                let credentials = credentials.read().unwrap();
+                let password : &Secret = credentials.password().ok_or("an OpenPGP message needs a password")?;
+                write_to(&destination, overwrite, |destination| import_file_to(file, destination, password))
+            }
+            Container::Age => {
+                ask_for_shared_password(&credentials,
+                    |credentials| credentials.identity().is_none() && !credentials.has_password())?;
+                let credentials = credentials.read().unwrap();
+                write_to(&destination, overwrite, |destination| decrypt_age_file_to(file, destination, &credentials))
+            }
+            Container::Native => {
+                let header : Header = read_header(file)?;
+                ask_for_shared_password(&credentials,
+                    |credentials| header.flags & FLAG_PASSWORD != 0 && !credentials.has_password())?;
+                let credentials = credentials.read().unwrap();
+                write_to(&destination, overwrite, |destination| decrypt_file_to(file, destination, &credentials))
+            }
+        }
+    })
+}
+
+/// Asks for a password if `missing` says one is needed. The write lock is
+/// held while asking, so concurrent workers only ask once.
+fn ask_for_shared_password<F>(credentials : &RwLock<Credentials>, missing : F) -> Result<(), Box<dyn Error>>
+    where F : Fn(&Credentials) -> bool {
+    if !missing(&credentials.read().unwrap()) {
+        return Ok(());
+    }
+    let mut credentials = credentials.write().unwrap();
+    if missing(&credentials) {
+        credentials.set_password(get_existing_password()?);
+    }
+    Ok(())
+}
+
+/// Credentials for existing ciphertexts. A password is only asked for once
+/// a file needs one.
+fn decryption_credentials(key : &DecryptionKeyArguments) -> Result<Credentials, Box<dyn Error>> {
+    Ok(if let Some(raw_key) = &key.raw_key {
+        Credentials::from_raw_key(load_raw_key(raw_key)?.0)
+    } else if let Some(identity) = &key.identity {
+        Credentials::from_identity(load_identity(identity)?)
+    } else {
+        Credentials::new(None, key.keyfile.as_deref().map(hash_keyfile).transpose()?)
+    })
+}
+
+fn rekey(arguments : &RekeyArguments) -> Result<(), Box<dyn Error>> {
+    let (mut credentials, raw_key_size) : (Credentials, Option<KeySize>) = if let Some(raw_key) = &arguments.raw_key {
+        let (raw_key, key_size) = load_raw_key(raw_key)?;
+        (Credentials::from_raw_key(raw_key), Some(key_size))
+    } else {
+        (Credentials::new(None, arguments.keyfile.as_deref().map(hash_keyfile).transpose()?), None)
     };
-    println!("Your password is {}", password);
+    for file in &arguments.files {
+        let header : Header = read_header(file)?;
+        ask_for_password(&header, &mut credentials)?;
+        let old : CryptoParameters = header.parameters;
+        let parameters : CryptoParameters = CryptoParametersBuilder::from(old)
+            .block_cipher(arguments.cipher.as_deref().and_then(BlockCipher::from_name).unwrap_or(old.block_cipher()))
+            .kdf_cost(arguments.kdf.as_deref().and_then(Kdf::from_name).map(KdfCost::default_for)
+                .unwrap_or(old.kdf_cost()))
+            .key_size(raw_key_size.or(arguments.key_size.and_then(KeySize::from_integer)).unwrap_or(old.key_size()))
+            .operation_mode(arguments.mode.as_deref().and_then(OperationMode::from_name).unwrap_or(old.operation_mode()))
+            .build()
+            .map_err(|error| format!("{}: {}", file, error))?;
+        rekey_file(file, &credentials, parameters)?;
+    }
+    Ok(())
+}
+
+/// Only asks for a password once one of the files actually needs it.
+fn ask_for_password(header : &Header, credentials : &mut Credentials) -> Result<(), Box<dyn Error>> {
+    if header.flags & FLAG_PASSWORD != 0 && !credentials.has_password() {
+        credentials.set_password(get_existing_password()?);
+    }
+    Ok(())
+}
+
+fn encrypt_files(files : &[String], tree : &TreeArguments, credentials : &Credentials,
+    parameters : CryptoParameters, armor : bool, config : &Config, overwrite : OverwritePolicy)
+    -> Result<(), Box<dyn Error>> {
+    for_each_file(files, tree, Direction::Encrypt, config, |job| {
+        let destination : String = job.destination.clone()
+            .unwrap_or_else(|| job.source.clone() + "." + config.filename_extension());
+        write_to(&destination, overwrite, |destination| if armor {
+            encrypt_armored_file_to(&job.source, destination, credentials, parameters)
+        } else {
+            encrypt_file_to(&job.source, destination, credentials, parameters)
+        })
+    })
+}
+
+/// The overwrite policy from the command line, or else from the config.
+fn overwrite_policy(arguments : &OverwriteArguments, config : &Config) -> Result<OverwritePolicy, Box<dyn Error>> {
+    Ok(if arguments.force {
+        OverwritePolicy::Force
+    } else if arguments.no_clobber {
+        OverwritePolicy::NoClobber
+    } else if arguments.backup {
+        OverwritePolicy::Backup
+    } else if arguments.interactive {
+        OverwritePolicy::Prompt
+    } else {
+        config.overwrite_policy()?
+    })
+}
+
+/// Lets `write` create `destination` as `overwrite` says and tells about
+/// destinations that were kept.
+fn write_to<W>(destination : &str, overwrite : OverwritePolicy, write : W) -> Result<(), Box<dyn Error>>
+    where W : FnOnce(&str) -> Result<(), CryptoError> {
+    if !write_destination(destination, overwrite, confirm_overwrite, write)? {
+        eprintln!("{}: already exists, skipped", destination);
+    }
+    Ok(())
+}
+
+/// Asks on the terminal whether to replace `destination`. Workers take
+/// turns, so their questions do not get mixed up.
+fn confirm_overwrite(destination : &str) -> bool {
+    static QUESTION : Mutex<()> = Mutex::new(());
+    let _turn = QUESTION.lock().unwrap();
+    eprint!("{} already exists, replace it? [y/N] ", destination);
+    let mut answer : String = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Runs `action` on each of `files` and stops at the first error. With
+/// `--recursive` or several jobs, every file is processed and the failures
+/// are reported at the end.
+fn for_each_file<F>(files : &[String], tree : &TreeArguments, direction : Direction, config : &Config, action : F)
+    -> Result<(), Box<dyn Error>> where F : Fn(&FileJob) -> Result<(), Box<dyn Error>> + Sync {
+    let jobs : Vec<Result<FileJob, String>> = if tree.recursive {
+        let filter : FileFilter = FileFilter::new(&tree.include, &tree.exclude)?;
+        collect_files(files, &filter, direction, tree.output_dir.as_deref(), config.filename_extension())
+    } else {
+        files.iter().map(|file| Ok(FileJob{source : file.clone(), destination : None})).collect()
+    };
+    let progress_mode : Option<ProgressMode> = match tree.progress {
+        Progress::Auto if std::io::stderr().is_terminal() => Some(ProgressMode::Bar),
+        Progress::Auto | Progress::Off => None,
+        Progress::Bar => Some(ProgressMode::Bar),
+        Progress::Json => Some(ProgressMode::Json),
+    };
+    if let Some(mode) = progress_mode {
+        let total : u64 = jobs.iter().flatten()
+            .filter_map(|job| fs::metadata(&job.source).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        progress::start(mode, total);
+    }
+    if tree.recursive || tree.jobs > 1 {
+        return process_files(jobs, tree.jobs as usize, action);
+    }
+    let result = jobs.iter().flatten().try_for_each(&action);
+    progress::finish(result.is_ok());
+    result
+}
+
+fn pack(arguments : &PackArguments, config : &Config) -> Result<(), Box<dyn Error>> {
+    let (credentials, parameters) = native_encryption(&arguments.key, config.parameters(profile(&arguments.profile))?)?;
+    let output : String = arguments.output.clone().unwrap_or_else(|| default_archive_path(&arguments.directory));
+    write_to(&output, overwrite_policy(&arguments.overwrite, config)?,
+        |output| pack_directory(&arguments.directory, output, &credentials, parameters))
+}
+
+fn unpack(arguments : &UnpackArguments) -> Result<(), Box<dyn Error>> {
+    let mut credentials : Credentials = decryption_credentials(&arguments.key)?;
+    ask_for_password(&read_header(&arguments.archive)?, &mut credentials)?;
+    Ok(unpack_archive(&arguments.archive, &arguments.output, &credentials)?)
+}
+
+fn slot(command : SlotCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        SlotCommand::List{file} => {
+            for (index, kdf) in list_keyslots(&file)?.iter().enumerate() {
+                println!("{}: {}", index, kdf);
+            }
+        }
+        SlotCommand::Add{file, kdf} => {
+            let password : Secret = get_existing_password()?;
+            eprintln!("New password:");
+            let kdf_cost : Option<KdfCost> = kdf.as_deref().and_then(Kdf::from_name).map(KdfCost::default_for);
+            add_keyslot(&file, &password, &prompt_new_password()?, kdf_cost)?;
+        }
+        SlotCommand::Remove{file, slot} => remove_keyslot(&file, &get_existing_password()?, slot)?,
+    }
+    Ok(())
+}
+
+fn keygen(output : Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (identity, public_key) = generate_identity();
+    let content : Secret = format_identity(&identity, &public_key);
+    match output {
+        Some(output) => {
+            let mut options : OpenOptions = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(output)?.write_all(content.expose())?;
+            println!("{}", format_recipient(&public_key));
+        }
+        None => std::io::stdout().write_all(content.expose())?,
+    }
     Ok(())
 }
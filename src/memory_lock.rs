@@ -0,0 +1,174 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#[cfg(target_os = "linux")]
+use std::collections::BTreeMap;
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+
+/// How many live `LockedPages` cover each locked page, keyed by page address.
+/// The kernel does not count locks, so a page is only unlocked once nothing
+/// else still needs it.
+#[cfg(target_os = "linux")]
+static LOCKED_PAGES : Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// The pages `lock` took for a buffer. It stays valid after the buffer has
+/// been cleared, so the memory can be wiped before it is unlocked.
+#[derive(Debug)]
+pub struct LockedPages {
+    address : usize,
+    length : usize,
+}
+
+/// Keeps the pages holding `bytes` out of swap and out of core dumps. If the
+/// pages cannot be locked, a warning is printed once and execution continues.
+#[cfg(target_os = "linux")]
+pub fn lock(bytes : &[u8]) -> LockedPages {
+    use std::sync::Once;
+    static WARNING : Once = Once::new();
+
+    let pages : LockedPages = page_range(bytes);
+    if pages.length == 0 {
+        return pages;
+    }
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(|error| error.into_inner());
+    for page in pages.pages() {
+        *locked_pages.entry(page).or_insert(0) += 1;
+    }
+    let (address, length) = (pages.address as *const libc::c_void, pages.length);
+    unsafe {
+        if libc::mlock(address, length) != 0 {
+            WARNING.call_once(|| {
+                eprintln!("warning: could not lock key material in memory, it may be swapped to disk \
+                    (RLIMIT_MEMLOCK is {} bytes)", memlock_limit());
+            });
+        }
+        libc::madvise(address as *mut libc::c_void, length, libc::MADV_DONTDUMP);
+    }
+    pages
+}
+
+/// Releases the lock taken by `lock`. Pages still covered by another live
+/// lock, such as a page shared with a neighbouring secret, stay locked.
+#[cfg(target_os = "linux")]
+pub fn unlock(pages : &LockedPages) {
+    if pages.length == 0 {
+        return;
+    }
+    let page_size : usize = page_size();
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(|error| error.into_inner());
+    for page in pages.pages() {
+        let count : &mut usize = locked_pages.entry(page).or_insert(1);
+        *count -= 1;
+        if *count > 0 {
+            continue;
+        }
+        locked_pages.remove(&page);
+        unsafe {
+            libc::munlock(page as *const libc::c_void, page_size);
+            libc::madvise(page as *mut libc::c_void, page_size, libc::MADV_DODUMP);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl LockedPages {
+    fn pages(&self) -> impl Iterator<Item = usize> {
+        (self.address..self.address + self.length).step_by(page_size())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(target_os = "linux")]
+fn page_range(bytes : &[u8]) -> LockedPages {
+    if bytes.is_empty() {
+        return LockedPages{address : 0, length : 0};
+    }
+    let page_size : usize = page_size();
+    let start : usize = bytes.as_ptr() as usize;
+    let aligned_start : usize = start - start % page_size;
+    LockedPages{
+        address : aligned_start,
+        length : start + bytes.len() - aligned_start,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn memlock_limit() -> u64 {
+    let mut limit = libc::rlimit{
+        rlim_cur : 0,
+        rlim_max : 0,
+    };
+    unsafe {
+        libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit);
+    }
+    limit.rlim_cur
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn lock(_bytes : &[u8]) -> LockedPages {
+    LockedPages{address : 0, length : 0}
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unlock(_pages : &LockedPages) {}
+
+#[cfg(test)]
+mod memory_lock_tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let bytes : Vec<u8> = vec![0x42; 100];
+        let pages : LockedPages = lock(&bytes);
+        unlock(&pages);
+        assert_eq!(bytes, vec![0x42; 100]);
+    }
+
+    #[test]
+    fn test_lock_empty() {
+        let bytes : Vec<u8> = Vec::new();
+        let pages : LockedPages = lock(&bytes);
+        assert_eq!(pages.length, 0);
+        unlock(&pages);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_shared_page_stays_locked() {
+        let page_size : usize = page_size();
+        let buffer : Vec<u8> = vec![0x42; 2 * page_size];
+        let offset : usize = page_size - buffer.as_ptr() as usize % page_size;
+        let page : &[u8] = &buffer[offset..offset + page_size];
+        let address : usize = page.as_ptr() as usize;
+        let count = || LOCKED_PAGES.lock().unwrap().get(&address).copied();
+
+        let first : LockedPages = lock(&page[..16]);
+        let second : LockedPages = lock(&page[16..32]);
+        assert_eq!(count(), Some(2));
+        unlock(&first);
+        assert_eq!(count(), Some(1));
+        unlock(&second);
+        assert_eq!(count(), None);
+    }
+}
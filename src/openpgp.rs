@@ -0,0 +1,730 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_error::CryptoError;
+use crate::crypto_parameters::block_cipher::BlockCipher;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+use crate::crypto_parameters::key_size::KeySize;
+use crate::crypto_parameters::operation_mode::OperationMode;
+use crate::file_cipher::{open, seal, write_new_file};
+use crate::header::TAG_SIZE;
+use crate::progress;
+use crate::secret::Secret;
+use aes::cipher::{BlockCipher as CipherAlgorithm, BlockEncrypt, KeyInit};
+use aes::{Aes128, Aes192, Aes256};
+use camellia::{Camellia128, Camellia192, Camellia256};
+use cfb_mode::cipher::{AsyncStreamCipher, KeyIvInit};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+pub const FILENAME_EXTENSION : &str = "pgp";
+/// The extension gpg gives to its output.
+pub const GPG_FILENAME_EXTENSION : &str = "gpg";
+
+const TAG_SKESK : u8 = 3;
+const TAG_COMPRESSED_DATA : u8 = 8;
+const TAG_MARKER : u8 = 10;
+const TAG_LITERAL_DATA : u8 = 11;
+const TAG_SEIPD : u8 = 18;
+const TAG_PADDING : u8 = 21;
+
+const AEAD_GCM : u8 = 3;
+const S2K_SALTED : u8 = 1;
+const S2K_ITERATED_SALTED : u8 = 3;
+const S2K_SALT_SIZE : usize = 8;
+/// Hashes 65011712 octets, the most the iterated S2K allows.
+const S2K_COUNT : u8 = 0xFF;
+const HASH_SHA1 : u8 = 2;
+const HASH_SHA256 : u8 = 8;
+const HASH_SHA512 : u8 = 10;
+
+/// Chunks of 2^(12 + 6) octets.
+const CHUNK_SIZE_OCTET : u8 = 12;
+const MAXIMUM_CHUNK_SIZE_OCTET : u8 = 16;
+const GCM_NONCE_SIZE : usize = 12;
+const SEIPD_SALT_SIZE : usize = 32;
+const BLOCK_SIZE : usize = 16;
+/// The MDC packet at the end of an SEIPD v1 packet: tag, length and a SHA-1 hash.
+const MDC_HEADER : [u8; 2] = [0xD3, 0x14];
+const MDC_SIZE : usize = 22;
+
+/// Packet bodies may well be decrypted data.
+type PacketBody = Zeroizing<Vec<u8>>;
+
+enum MessageError {
+    Malformed,
+    Unsupported,
+    AuthenticationFailed,
+}
+
+/// The packets an exported message is made of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageVersion {
+    /// An SKESK v4 packet followed by an SEIPD v1 packet in CFB mode with a
+    /// SHA-1 MDC, as `gpg -c` writes them. Every current OpenPGP
+    /// implementation, GnuPG included, can decrypt these.
+    V4,
+    /// An SKESK v6 packet followed by an SEIPD v2 packet using GCM, as
+    /// introduced by RFC 9580. GnuPG does not implement these packets and
+    /// rejects such messages, up to at least version 2.4.
+    V6,
+}
+
+/// Encrypts `path` into `path.pgp`, an OpenPGP message that can be decrypted
+/// with `password`. `version` decides which implementations can read it.
+pub fn export_file(path : &str, password : &Secret, parameters : CryptoParameters, version : MessageVersion)
+    -> Result<(), CryptoError> {
+    export_file_to(path, &(path.to_string() + "." + FILENAME_EXTENSION), password, parameters, version)
+}
+
+/// Encrypts `path` into a new OpenPGP message at `destination_path`.
+pub fn export_file_to(path : &str, destination_path : &str, password : &Secret, parameters : CryptoParameters,
+    version : MessageVersion) -> Result<(), CryptoError> {
+    let plaintext : Zeroizing<Vec<u8>> = Zeroizing::new(fs::read(path)
+        .map_err(|_| CryptoError::FileLoading(path.to_string()))?);
+    let message : Vec<u8> = encrypt_message(&plaintext, path, password, parameters, version)
+        .ok_or_else(|| CryptoError::Encryption(path.to_string()))?;
+    write_new_file(destination_path, &message, path)
+}
+
+/// Decrypts an OpenPGP message ending in `.pgp` or `.gpg` that was encrypted
+/// with `password`. Besides RFC 9580 messages this accepts the SKESK v4 and
+/// SEIPD v1 packets `gpg -c` writes.
+pub fn import_file(path : &str, password : &Secret) -> Result<(), CryptoError> {
+    if !is_openpgp_path(path) {
+        return Err(CryptoError::FileLoading(path.to_string()));
+    }
+    import_file_to(path, &path[0..path.len() - FILENAME_EXTENSION.len() - 1], password)
+}
+
+/// Decrypts the OpenPGP message at `path` into a new file at `destination_path`.
+pub fn import_file_to(path : &str, destination_path : &str, password : &Secret) -> Result<(), CryptoError> {
+    let message : Vec<u8> = fs::read(path).map_err(|_| CryptoError::FileLoading(path.to_string()))?;
+    let plaintext : Zeroizing<Vec<u8>> = decrypt_message(&message, password).map_err(|error| match error {
+        MessageError::Malformed => CryptoError::InvalidHeader(path.to_string()),
+        MessageError::Unsupported => CryptoError::UnsupportedParameters(path.to_string()),
+        MessageError::AuthenticationFailed => CryptoError::AuthenticationFailed(path.to_string()),
+    })?;
+    write_new_file(destination_path, &plaintext, path)
+}
+
+pub fn is_openpgp_path(path : &str) -> bool {
+    matches!(Path::new(path).extension().and_then(|extension| extension.to_str()),
+        Some(FILENAME_EXTENSION) | Some(GPG_FILENAME_EXTENSION))
+}
+
+/// Recognizes a binary OpenPGP message by the tag of its first packet, which
+/// is an SKESK packet or one of the packets allowed in front of it.
+pub fn is_openpgp_message(data : &[u8]) -> bool {
+    let first : u8 = match data.first() {
+        Some(first) if first & 0x80 != 0 => *first,
+        _ => return false,
+    };
+    let tag : u8 = if first & 0x40 == 0 { (first >> 2) & 0x0F } else { first & 0x3F };
+    matches!(tag, TAG_SKESK | TAG_MARKER | TAG_PADDING)
+}
+
+fn encrypt_message(plaintext : &[u8], path : &str, password : &Secret, parameters : CryptoParameters,
+    version : MessageVersion) -> Option<Vec<u8>> {
+    let cipher : u8 = cipher_to_id(parameters);
+
+    let mut literal_data : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(plaintext.len() + 512));
+    let filename : &[u8] = Path::new(path).file_name().map(|name| name.as_encoded_bytes()).unwrap_or_default();
+    let filename : &[u8] = &filename[0..filename.len().min(255)];
+    let date : u32 = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs() as u32).unwrap_or(0);
+    literal_data.push(b'b');
+    literal_data.push(filename.len() as u8);
+    literal_data.extend_from_slice(filename);
+    literal_data.extend_from_slice(&date.to_be_bytes());
+    literal_data.extend_from_slice(plaintext);
+    let mut literal_packet : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(literal_data.len() + 6));
+    write_packet(&mut literal_packet, TAG_LITERAL_DATA, &literal_data)?;
+
+    let mut message : Vec<u8> = Vec::with_capacity(literal_packet.len() + 512);
+    match version {
+        MessageVersion::V4 => {
+            // Without an encrypted session key the S2K output is the session key.
+            let specifier : Vec<u8> = s2k_specifier();
+            let (session_key, _) = s2k(password, &specifier, key_length(parameters)).ok()?;
+            let mut skesk : Vec<u8> = vec![4, cipher];
+            skesk.extend_from_slice(&specifier);
+            write_packet(&mut message, TAG_SKESK, &skesk)?;
+            write_packet(&mut message, TAG_SEIPD, &encrypt_seipd_v1(&literal_packet, &session_key, parameters)?)?;
+        }
+        MessageVersion::V6 => {
+            let session_key : Secret = random_secret(key_length(parameters));
+            write_packet(&mut message, TAG_SKESK, &create_skesk_v6(&session_key, password, parameters, cipher)?)?;
+            write_packet(&mut message, TAG_SEIPD, &encrypt_seipd_v2(&literal_packet, &session_key, parameters, cipher)?)?;
+        }
+    }
+    Some(message)
+}
+
+fn s2k_specifier() -> Vec<u8> {
+    let mut specifier : Vec<u8> = vec![S2K_ITERATED_SALTED, HASH_SHA256];
+    specifier.extend_from_slice(random_secret(S2K_SALT_SIZE).expose());
+    specifier.push(S2K_COUNT);
+    specifier
+}
+
+fn create_skesk_v6(session_key : &Secret, password : &Secret, parameters : CryptoParameters, cipher : u8) -> Option<Vec<u8>> {
+    let specifier : Vec<u8> = s2k_specifier();
+    let (s2k_key, _) = s2k(password, &specifier, key_length(parameters)).ok()?;
+
+    let info : [u8; 4] = [0xC0 | TAG_SKESK, 6, cipher, AEAD_GCM];
+    let key_encryption_key : Secret = hkdf(&s2k_key, None, &info, key_length(parameters))?;
+    let mut nonce : [u8; GCM_NONCE_SIZE] = [0x00; GCM_NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+    let mut encrypted_session_key : Vec<u8> = session_key.expose().to_vec();
+    let tag : [u8; TAG_SIZE] = seal(&parameters, &key_encryption_key, &nonce, &info, &mut encrypted_session_key)?;
+
+    let mut skesk : Vec<u8> = vec![6, (3 + specifier.len() + nonce.len()) as u8, cipher, AEAD_GCM, specifier.len() as u8];
+    skesk.extend_from_slice(&specifier);
+    skesk.extend_from_slice(&nonce);
+    skesk.extend_from_slice(&encrypted_session_key);
+    skesk.extend_from_slice(&tag);
+    Some(skesk)
+}
+
+/// Encrypts `plaintext` in OpenPGP's CFB mode behind a random prefix whose
+/// last two octets are repeated, followed by the SHA-1 MDC over all of it.
+fn encrypt_seipd_v1(plaintext : &[u8], session_key : &Secret, parameters : CryptoParameters) -> Option<Vec<u8>> {
+    let mut data : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(BLOCK_SIZE + 2 + plaintext.len() + MDC_SIZE));
+    data.extend_from_slice(random_secret(BLOCK_SIZE).expose());
+    data.extend_from_within(BLOCK_SIZE - 2..BLOCK_SIZE);
+    data.extend_from_slice(plaintext);
+    data.extend_from_slice(&MDC_HEADER);
+    let hash : [u8; 20] = Sha1::digest(&data[..]).into();
+    data.extend_from_slice(&hash);
+    cfb_encrypt(parameters, session_key, &mut data)?;
+    progress::advance(plaintext.len());
+
+    let mut body : Vec<u8> = Vec::with_capacity(1 + data.len());
+    body.push(1);
+    body.extend_from_slice(&data);
+    Some(body)
+}
+
+fn encrypt_seipd_v2(plaintext : &[u8], session_key : &Secret, parameters : CryptoParameters, cipher : u8) -> Option<Vec<u8>> {
+    let info : [u8; 5] = [0xC0 | TAG_SEIPD, 2, cipher, AEAD_GCM, CHUNK_SIZE_OCTET];
+    let salt : Secret = random_secret(SEIPD_SALT_SIZE);
+    let (key, iv) = seipd_key_and_iv(session_key, salt.expose(), &info, parameters)?;
+
+    let chunk_size : usize = 1 << (CHUNK_SIZE_OCTET + 6);
+    let chunk_count : usize = plaintext.len().div_ceil(chunk_size);
+    let mut body : Vec<u8> = Vec::with_capacity(info.len() + SEIPD_SALT_SIZE + plaintext.len() + (chunk_count + 1) * TAG_SIZE);
+    body.extend_from_slice(&info[1..]);
+    body.extend_from_slice(salt.expose());
+    for (index, chunk) in plaintext.chunks(chunk_size).enumerate() {
+        let start : usize = body.len();
+        body.extend_from_slice(chunk);
+        let tag : [u8; TAG_SIZE] = seal(&parameters, &key, &chunk_nonce(&iv, index), &info, &mut body[start..])?;
+        body.extend_from_slice(&tag);
+        progress::advance(chunk.len());
+    }
+    let final_tag : [u8; TAG_SIZE] = seal(&parameters, &key, &chunk_nonce(&iv, chunk_count),
+        &final_authenticated_data(&info, plaintext.len()), &mut [])?;
+    body.extend_from_slice(&final_tag);
+    Some(body)
+}
+
+fn decrypt_message(message : &[u8], password : &Secret) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    let mut skesks : Vec<Zeroizing<Vec<u8>>> = Vec::new();
+    let mut rest : &[u8] = message;
+    let seipd : Zeroizing<Vec<u8>> = loop {
+        let (tag, body, remainder) = next_packet(rest).ok_or(MessageError::Malformed)?;
+        rest = remainder;
+        match tag {
+            TAG_SKESK => skesks.push(body),
+            TAG_MARKER | TAG_PADDING => {}
+            TAG_SEIPD => break body,
+            _ => return Err(MessageError::Unsupported),
+        }
+    };
+    // Messages for public keys carry no SKESK packets.
+    let mut result : Result<Zeroizing<Vec<u8>>, MessageError> = Err(MessageError::Unsupported);
+    for skesk in &skesks {
+        result = session_key(skesk, password)
+            .and_then(|(parameters, session_key)| match seipd.first() {
+                Some(1) => decrypt_seipd_v1(&seipd, &session_key, parameters),
+                Some(2) => decrypt_seipd_v2(&seipd, &session_key),
+                _ => Err(MessageError::Unsupported),
+            });
+        if result.is_ok() {
+            break;
+        }
+    }
+    // The decrypted packets have been counted, which may be compressed.
+    let packets : Zeroizing<Vec<u8>> = result?;
+    progress::advance(message.len().saturating_sub(packets.len()));
+    literal_data(&packets, true)
+}
+
+/// Recovers the session key from an SKESK packet, together with the cipher it is meant for.
+fn session_key(skesk : &[u8], password : &Secret) -> Result<(CryptoParameters, Secret), MessageError> {
+    match skesk {
+        [4, cipher, specifier @ ..] => {
+            let parameters : CryptoParameters = cipher_from_id(*cipher)?;
+            let (s2k_key, specifier_length) = s2k(password, specifier, key_length(parameters))?;
+            let encrypted_session_key : &[u8] = &specifier[specifier_length..];
+            if encrypted_session_key.is_empty() {
+                return Ok((parameters, s2k_key));
+            }
+            let mut session_key : Zeroizing<Vec<u8>> = Zeroizing::new(encrypted_session_key.to_vec());
+            cfb_decrypt(parameters, &s2k_key, &mut session_key).ok_or(MessageError::Unsupported)?;
+            let parameters : CryptoParameters = cipher_from_id(session_key[0])
+                .map_err(|_| MessageError::AuthenticationFailed)?;
+            if session_key.len() != key_length(parameters) + 1 {
+                return Err(MessageError::AuthenticationFailed);
+            }
+            Ok((parameters, Secret::new(session_key[1..].to_vec())))
+        }
+        [6, _, cipher, aead, specifier_length, rest @ ..] => {
+            if *aead != AEAD_GCM {
+                return Err(MessageError::Unsupported);
+            }
+            let parameters : CryptoParameters = cipher_from_id(*cipher)?;
+            let key_length : usize = key_length(parameters);
+            let specifier_length : usize = *specifier_length as usize;
+            if rest.len() != specifier_length + GCM_NONCE_SIZE + key_length + TAG_SIZE {
+                return Err(MessageError::Malformed);
+            }
+            let (s2k_key, _) = s2k(password, &rest[0..specifier_length], key_length)?;
+            let info : [u8; 4] = [0xC0 | TAG_SKESK, 6, *cipher, *aead];
+            let key_encryption_key : Secret = hkdf(&s2k_key, None, &info, key_length).ok_or(MessageError::Malformed)?;
+            let (nonce, rest) = rest[specifier_length..].split_at(GCM_NONCE_SIZE);
+            let (encrypted_session_key, tag) = rest.split_at(key_length);
+            let mut session_key : Zeroizing<Vec<u8>> = Zeroizing::new(encrypted_session_key.to_vec());
+            if !open(&parameters, &key_encryption_key, nonce, &info, &mut session_key, tag) {
+                return Err(MessageError::AuthenticationFailed);
+            }
+            Ok((parameters, Secret::new(session_key.to_vec())))
+        }
+        _ => Err(MessageError::Unsupported),
+    }
+}
+
+/// Decrypts the CFB mode SEIPD v1 packet gpg writes and checks its SHA-1 MDC.
+fn decrypt_seipd_v1(seipd : &[u8], session_key : &Secret, parameters : CryptoParameters) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    let mut data : Zeroizing<Vec<u8>> = Zeroizing::new(seipd[1..].to_vec());
+    if data.len() < BLOCK_SIZE + 2 + MDC_SIZE {
+        return Err(MessageError::Malformed);
+    }
+    cfb_decrypt(parameters, session_key, &mut data).ok_or(MessageError::Unsupported)?;
+    // The last two octets of the random prefix are repeated, which gives away
+    // a wrong key without hashing the whole message.
+    if data[BLOCK_SIZE - 2..BLOCK_SIZE] != data[BLOCK_SIZE..BLOCK_SIZE + 2] {
+        return Err(MessageError::AuthenticationFailed);
+    }
+    let (content, hash) = data.split_at(data.len() - (MDC_SIZE - MDC_HEADER.len()));
+    if !content.ends_with(&MDC_HEADER) || Sha1::digest(content).as_slice() != hash {
+        return Err(MessageError::AuthenticationFailed);
+    }
+    let packets : &[u8] = &content[BLOCK_SIZE + 2..content.len() - MDC_HEADER.len()];
+    progress::advance(packets.len());
+    Ok(Zeroizing::new(packets.to_vec()))
+}
+
+fn decrypt_seipd_v2(seipd : &[u8], session_key : &Secret) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    if seipd.len() < 4 + SEIPD_SALT_SIZE + TAG_SIZE {
+        return Err(MessageError::Malformed);
+    }
+    let info : [u8; 5] = [0xC0 | TAG_SEIPD, seipd[0], seipd[1], seipd[2], seipd[3]];
+    let parameters : CryptoParameters = cipher_from_id(seipd[1])?;
+    if seipd[2] != AEAD_GCM || seipd[3] > MAXIMUM_CHUNK_SIZE_OCTET {
+        return Err(MessageError::Unsupported);
+    }
+    if session_key.expose().len() != key_length(parameters) {
+        return Err(MessageError::AuthenticationFailed);
+    }
+    let (key, iv) = seipd_key_and_iv(session_key, &seipd[4..4 + SEIPD_SALT_SIZE], &info, parameters)
+        .ok_or(MessageError::Malformed)?;
+
+    let chunk_size : usize = 1 << (seipd[3] + 6);
+    let (chunks, final_tag) = seipd[4 + SEIPD_SALT_SIZE..].split_at(seipd.len() - 4 - SEIPD_SALT_SIZE - TAG_SIZE);
+    let mut plaintext : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(chunks.len()));
+    let mut chunk_count : usize = 0;
+    for chunk in chunks.chunks(chunk_size + TAG_SIZE) {
+        if chunk.len() <= TAG_SIZE {
+            return Err(MessageError::Malformed);
+        }
+        let (ciphertext, tag) = chunk.split_at(chunk.len() - TAG_SIZE);
+        let start : usize = plaintext.len();
+        plaintext.extend_from_slice(ciphertext);
+        if !open(&parameters, &key, &chunk_nonce(&iv, chunk_count), &info, &mut plaintext[start..], tag) {
+            return Err(MessageError::AuthenticationFailed);
+        }
+        progress::advance(ciphertext.len());
+        chunk_count += 1;
+    }
+    if !open(&parameters, &key, &chunk_nonce(&iv, chunk_count),
+        &final_authenticated_data(&info, plaintext.len()), &mut [], final_tag) {
+        return Err(MessageError::AuthenticationFailed);
+    }
+    Ok(plaintext)
+}
+
+/// Finds the literal data in the decrypted packets. gpg compresses them by
+/// default, but compressed data inside compressed data is not accepted.
+fn literal_data(packets : &[u8], allow_compression : bool) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    let mut rest : &[u8] = packets;
+    while !rest.is_empty() {
+        let (tag, body, remainder) = next_packet(rest).ok_or(MessageError::Malformed)?;
+        rest = remainder;
+        match tag {
+            TAG_COMPRESSED_DATA if allow_compression => return literal_data(&decompress(&body)?, false),
+            TAG_LITERAL_DATA => {
+                let filename_length : usize = *body.get(1).ok_or(MessageError::Malformed)? as usize;
+                let content : &[u8] = body.get(2 + filename_length + 4..).ok_or(MessageError::Malformed)?;
+                return Ok(Zeroizing::new(content.to_vec()));
+            }
+            TAG_MARKER | TAG_PADDING => {}
+            _ => return Err(MessageError::Unsupported),
+        }
+    }
+    Err(MessageError::Malformed)
+}
+
+fn decompress(body : &[u8]) -> Result<Zeroizing<Vec<u8>>, MessageError> {
+    let (algorithm, data) = body.split_first().ok_or(MessageError::Malformed)?;
+    let mut packets : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::new());
+    let result = match algorithm {
+        0 => {
+            packets.extend_from_slice(data);
+            Ok(0)
+        }
+        1 => DeflateDecoder::new(data).read_to_end(&mut packets),
+        2 => ZlibDecoder::new(data).read_to_end(&mut packets),
+        _ => return Err(MessageError::Unsupported),
+    };
+    result.map_err(|_| MessageError::Malformed)?;
+    Ok(packets)
+}
+
+/// Splits the next packet off `data` and returns its tag, its body with all
+/// partial lengths joined and the remaining data. Both the old packet format
+/// gpg still writes and the new one are understood.
+fn next_packet(data : &[u8]) -> Option<(u8, PacketBody, &[u8])> {
+    let (first, mut rest) = data.split_first()?;
+    let mut body : PacketBody = Zeroizing::new(Vec::new());
+    if first & 0x80 == 0 {
+        return None;
+    }
+    if first & 0x40 == 0 {
+        let length : usize = match first & 0x03 {
+            0 => take(&mut rest, 1)?[0] as usize,
+            1 => u16::from_be_bytes(take(&mut rest, 2)?.try_into().ok()?) as usize,
+            2 => u32::from_be_bytes(take(&mut rest, 4)?.try_into().ok()?) as usize,
+            _ => rest.len(),
+        };
+        body.extend_from_slice(take(&mut rest, length)?);
+        return Some(((first >> 2) & 0x0F, body, rest));
+    }
+    loop {
+        let octet : u8 = take(&mut rest, 1)?[0];
+        let length : usize = match octet {
+            0..=191 => octet as usize,
+            192..=223 => ((octet as usize - 192) << 8) + take(&mut rest, 1)?[0] as usize + 192,
+            224..=254 => {
+                body.extend_from_slice(take(&mut rest, 1 << (octet & 0x1F))?);
+                continue;
+            }
+            255 => u32::from_be_bytes(take(&mut rest, 4)?.try_into().ok()?) as usize,
+        };
+        body.extend_from_slice(take(&mut rest, length)?);
+        return Some((first & 0x3F, body, rest));
+    }
+}
+
+fn take<'a>(data : &mut &'a [u8], length : usize) -> Option<&'a [u8]> {
+    if data.len() < length {
+        return None;
+    }
+    let (head, tail) = data.split_at(length);
+    *data = tail;
+    Some(head)
+}
+
+/// Appends a packet in the new format with a fixed length.
+fn write_packet(message : &mut Vec<u8>, tag : u8, body : &[u8]) -> Option<()> {
+    message.push(0xC0 | tag);
+    match body.len() {
+        0..=191 => message.push(body.len() as u8),
+        192..=8383 => {
+            let length : usize = body.len() - 192;
+            message.push((length >> 8) as u8 + 192);
+            message.push(length as u8);
+        }
+        _ => {
+            message.push(0xFF);
+            message.extend_from_slice(&u32::try_from(body.len()).ok()?.to_be_bytes());
+        }
+    }
+    message.extend_from_slice(body);
+    Some(())
+}
+
+/// Derives a key from `password` with the S2K specifier at the start of
+/// `specifier` and returns it together with the length of the specifier.
+fn s2k(password : &Secret, specifier : &[u8], key_length : usize) -> Result<(Secret, usize), MessageError> {
+    let (salt, count, specifier_length) = match specifier {
+        [S2K_SALTED, _, salt @ ..] if salt.len() >= S2K_SALT_SIZE => (&salt[0..S2K_SALT_SIZE], 0, 10),
+        [S2K_ITERATED_SALTED, _, salt @ ..] if salt.len() > S2K_SALT_SIZE => {
+            let count : u8 = salt[S2K_SALT_SIZE];
+            (&salt[0..S2K_SALT_SIZE], (16 + (count & 0x0F) as usize) << ((count >> 4) + 6), 11)
+        }
+        [S2K_SALTED | S2K_ITERATED_SALTED, ..] => return Err(MessageError::Malformed),
+        _ => return Err(MessageError::Unsupported),
+    };
+    let key : Secret = match specifier[1] {
+        HASH_SHA1 => s2k_with::<Sha1>(password, salt, count, key_length),
+        HASH_SHA256 => s2k_with::<Sha256>(password, salt, count, key_length),
+        HASH_SHA512 => s2k_with::<Sha512>(password, salt, count, key_length),
+        _ => return Err(MessageError::Unsupported),
+    };
+    Ok((key, specifier_length))
+}
+
+fn s2k_with<D : Digest>(password : &Secret, salt : &[u8], count : usize, key_length : usize) -> Secret {
+    let mut input : Zeroizing<Vec<u8>> = Zeroizing::new(salt.to_vec());
+    input.extend_from_slice(password.expose());
+    // Hashing the repeated input in larger blocks is much faster than
+    // hashing it a few octets at a time.
+    let block : Zeroizing<Vec<u8>> = Zeroizing::new(input.repeat(4096 / input.len() + 1));
+    let octets : usize = count.max(input.len());
+    let mut key : Zeroizing<Vec<u8>> = Zeroizing::new(Vec::with_capacity(key_length + <D as Digest>::output_size()));
+    // Longer keys than the hash output come from further hashes that are
+    // preloaded with one more zero octet each.
+    let mut preload : usize = 0;
+    while key.len() < key_length {
+        let mut hasher : D = D::new();
+        hasher.update(vec![0x00; preload]);
+        let mut remaining : usize = octets;
+        while remaining > 0 {
+            let length : usize = remaining.min(block.len());
+            hasher.update(&block[0..length]);
+            remaining -= length;
+        }
+        key.extend_from_slice(&hasher.finalize());
+        preload += 1;
+    }
+    Secret::new(key[0..key_length].to_vec())
+}
+
+fn hkdf(input_key : &Secret, salt : Option<&[u8]>, info : &[u8], length : usize) -> Option<Secret> {
+    let mut output : Vec<u8> = vec![0x00; length];
+    Hkdf::<Sha256>::new(salt, input_key.expose()).expand(info, &mut output).ok()?;
+    Some(Secret::new(output))
+}
+
+/// Splits the HKDF output for an SEIPD v2 packet into the message key and
+/// the leading part of every nonce.
+fn seipd_key_and_iv(session_key : &Secret, salt : &[u8], info : &[u8], parameters : CryptoParameters) -> Option<(Secret, Vec<u8>)> {
+    let key_length : usize = key_length(parameters);
+    let output : Secret = hkdf(session_key, Some(salt), info, key_length + GCM_NONCE_SIZE - 8)?;
+    let (key, iv) = output.expose().split_at(key_length);
+    Some((Secret::new(key.to_vec()), iv.to_vec()))
+}
+
+fn chunk_nonce(iv : &[u8], index : usize) -> Vec<u8> {
+    let mut nonce : Vec<u8> = iv.to_vec();
+    nonce.extend_from_slice(&(index as u64).to_be_bytes());
+    nonce
+}
+
+fn final_authenticated_data(info : &[u8], plaintext_length : usize) -> Vec<u8> {
+    let mut authenticated_data : Vec<u8> = info.to_vec();
+    authenticated_data.extend_from_slice(&(plaintext_length as u64).to_be_bytes());
+    authenticated_data
+}
+
+fn cfb_decrypt(parameters : CryptoParameters, key : &Secret, buffer : &mut [u8]) -> Option<()> {
+    match (parameters.block_cipher(), parameters.key_size()) {
+        (BlockCipher::Aes, KeySize::Size128) => cfb_decrypt_with::<Aes128>(key, buffer),
+        (BlockCipher::Aes, KeySize::Size192) => cfb_decrypt_with::<Aes192>(key, buffer),
+        (BlockCipher::Aes, KeySize::Size256) => cfb_decrypt_with::<Aes256>(key, buffer),
+        (BlockCipher::Camellia, KeySize::Size128) => cfb_decrypt_with::<Camellia128>(key, buffer),
+        (BlockCipher::Camellia, KeySize::Size192) => cfb_decrypt_with::<Camellia192>(key, buffer),
+        (BlockCipher::Camellia, KeySize::Size256) => cfb_decrypt_with::<Camellia256>(key, buffer),
+    }
+}
+
+/// OpenPGP's CFB mode always starts from a zero IV.
+fn cfb_decrypt_with<C>(key : &Secret, buffer : &mut [u8]) -> Option<()>
+where C : CipherAlgorithm + BlockEncrypt + KeyInit {
+    cfb_mode::Decryptor::<C>::new_from_slices(key.expose(), &[0x00; BLOCK_SIZE]).ok()?.decrypt(buffer);
+    Some(())
+}
+
+fn cfb_encrypt(parameters : CryptoParameters, key : &Secret, buffer : &mut [u8]) -> Option<()> {
+    match (parameters.block_cipher(), parameters.key_size()) {
+        (BlockCipher::Aes, KeySize::Size128) => cfb_encrypt_with::<Aes128>(key, buffer),
+        (BlockCipher::Aes, KeySize::Size192) => cfb_encrypt_with::<Aes192>(key, buffer),
+        (BlockCipher::Aes, KeySize::Size256) => cfb_encrypt_with::<Aes256>(key, buffer),
+        (BlockCipher::Camellia, KeySize::Size128) => cfb_encrypt_with::<Camellia128>(key, buffer),
+        (BlockCipher::Camellia, KeySize::Size192) => cfb_encrypt_with::<Camellia192>(key, buffer),
+        (BlockCipher::Camellia, KeySize::Size256) => cfb_encrypt_with::<Camellia256>(key, buffer),
+    }
+}
+
+fn cfb_encrypt_with<C>(key : &Secret, buffer : &mut [u8]) -> Option<()>
+where C : CipherAlgorithm + BlockEncrypt + KeyInit {
+    cfb_mode::Encryptor::<C>::new_from_slices(key.expose(), &[0x00; BLOCK_SIZE]).ok()?.encrypt(buffer);
+    Some(())
+}
+
+fn cipher_to_id(parameters : CryptoParameters) -> u8 {
+    match (parameters.block_cipher(), parameters.key_size()) {
+        (BlockCipher::Aes, KeySize::Size128) => 7,
+        (BlockCipher::Aes, KeySize::Size192) => 8,
+        (BlockCipher::Aes, KeySize::Size256) => 9,
+        (BlockCipher::Camellia, KeySize::Size128) => 11,
+        (BlockCipher::Camellia, KeySize::Size192) => 12,
+        (BlockCipher::Camellia, KeySize::Size256) => 13,
+    }
+}
+
+fn cipher_from_id(id : u8) -> Result<CryptoParameters, MessageError> {
+    let (block_cipher, key_size) = match id {
+        7 => (BlockCipher::Aes, KeySize::Size128),
+        8 => (BlockCipher::Aes, KeySize::Size192),
+        9 => (BlockCipher::Aes, KeySize::Size256),
+        11 => (BlockCipher::Camellia, KeySize::Size128),
+        12 => (BlockCipher::Camellia, KeySize::Size192),
+        13 => (BlockCipher::Camellia, KeySize::Size256),
+        _ => return Err(MessageError::Unsupported),
+    };
+    Ok(CryptoParameters::new(block_cipher, Kdf::None, key_size, OperationMode::Gcm, KeyEncapsulation::None))
+}
+
+fn key_length(parameters : CryptoParameters) -> usize {
+    parameters.key_size().to_integer() as usize / 8
+}
+
+fn random_secret(length : usize) -> Secret {
+    let mut bytes : Vec<u8> = vec![0x00; length];
+    OsRng.fill_bytes(&mut bytes);
+    Secret::new(bytes)
+}
+
+#[cfg(test)]
+mod openpgp_tests {
+    use super::*;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    fn password() -> Secret {
+        Secret::from("GNAMPF".to_string())
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        // Spans several chunks, the last of them shorter than the others.
+        let plaintext : Vec<u8> = (0..700_000).map(|i| i as u8).collect();
+        let parameters : CryptoParameters = CryptoParameters::new(BlockCipher::Camellia, Kdf::None,
+            KeySize::Size128, OperationMode::Gcm, KeyEncapsulation::None);
+        for (version, seipd_version) in [(MessageVersion::V4, 1), (MessageVersion::V6, 2)] {
+            let message : Vec<u8> = encrypt_message(&plaintext, "dir/file.txt", &password(), parameters, version).unwrap();
+            assert_eq!(message[0], 0xC0 | TAG_SKESK);
+            let (_, _, rest) = next_packet(&message).unwrap();
+            assert_eq!(next_packet(rest).unwrap().1[0], seipd_version);
+            assert_eq!(*decrypt_message(&message, &password()).ok().unwrap(), plaintext);
+            assert!(matches!(decrypt_message(&message, &Secret::from("wrong".to_string())),
+                Err(MessageError::AuthenticationFailed)));
+
+            let mut tampered : Vec<u8> = message.clone();
+            let last : usize = tampered.len() - 1;
+            tampered[last] ^= 0x01;
+            assert!(matches!(decrypt_message(&tampered, &password()), Err(MessageError::AuthenticationFailed)));
+        }
+    }
+
+    #[test]
+    fn test_gpg_decrypts_export() {
+        // Runs only where gpg is installed, in a home directory of its own.
+        if Command::new("gpg").arg("--version").output().is_err() {
+            eprintln!("gpg not found, skipping");
+            return;
+        }
+        let home : TempDir = tempfile::Builder::new().prefix("sfe-gnupg-").tempdir().unwrap();
+        let path : &str = "testfiles/gpg_export.txt";
+        let message_path : String = path.to_string() + "." + FILENAME_EXTENSION;
+        let decrypted_path : String = path.to_string() + ".gpg-out";
+        let _ = fs::remove_file(&message_path);
+        let _ = fs::remove_file(&decrypted_path);
+        fs::write(path, b"GNAMPF!\n").unwrap();
+        export_file(path, &password(), CryptoParameters::default(), MessageVersion::V4).unwrap();
+
+        let output = Command::new("gpg").arg("--homedir").arg(home.path())
+            .args(["--batch", "--pinentry-mode", "loopback", "--passphrase", "GNAMPF", "--output", &decrypted_path,
+                "--decrypt", &message_path])
+            .output().unwrap();
+        let _ = Command::new("gpgconf").arg("--homedir").arg(home.path()).args(["--kill", "gpg-agent"]).output();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(fs::read(&decrypted_path).unwrap(), b"GNAMPF!\n");
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&message_path).unwrap();
+        fs::remove_file(&decrypted_path).unwrap();
+    }
+
+    #[test]
+    fn test_import_gpg_message() {
+        // Written by `gpg -c` 2.2: SKESK v4, SEIPD v1 and compressed data in the old packet format.
+        let path : &str = "testfiles/gpg_symmetric.txt.gpg";
+        let _ = fs::remove_file("testfiles/gpg_symmetric.txt");
+        assert!(matches!(import_file(path, &Secret::from("wrong".to_string())),
+            Err(CryptoError::AuthenticationFailed(_))));
+        import_file(path, &password()).unwrap();
+        assert_eq!(fs::read("testfiles/gpg_symmetric.txt").unwrap(), b"BLARZBLURZBLIRZ\nGNAMPF!\n");
+        fs::remove_file("testfiles/gpg_symmetric.txt").unwrap();
+    }
+
+    #[test]
+    fn test_partial_body_lengths() {
+        let mut data : Vec<u8> = vec![0xC0 | TAG_LITERAL_DATA, 0xE1];
+        data.extend_from_slice(&[0x01, 0x02]);
+        data.push(0x03);
+        data.extend_from_slice(&[0x03, 0x04, 0x05, 0xFF]);
+        let (tag, body, rest) = next_packet(&data).unwrap();
+        assert_eq!(tag, TAG_LITERAL_DATA);
+        assert_eq!(*body, [0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!(rest, [0xFF]);
+        assert!(next_packet(&data[0..4]).is_none());
+    }
+
+    #[test]
+    fn test_write_packet_lengths() {
+        for length in [0, 191, 192, 8383, 8384, 100_000] {
+            let mut message : Vec<u8> = Vec::new();
+            write_packet(&mut message, TAG_SEIPD, &vec![0x42; length]).unwrap();
+            let (tag, body, rest) = next_packet(&message).unwrap();
+            assert_eq!((tag, body.len(), rest.len()), (TAG_SEIPD, length, 0));
+        }
+    }
+}
@@ -0,0 +1,208 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_error::CryptoError;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+pub const BACKUP_EXTENSION : &str = "bak";
+
+/// What happens when the destination of a file already exists.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum OverwritePolicy {
+    /// Fails, naming the other policies.
+    #[default]
+    Refuse,
+    /// Leaves the destination alone and skips the file.
+    NoClobber,
+    /// Replaces the destination once the new file is complete.
+    Force,
+    /// Keeps the destination as `<destination>.bak`, or as `<destination>.bak.<n>`
+    /// with the first free number, before replacing it.
+    Backup,
+    /// Asks whether to replace each destination.
+    Prompt,
+}
+
+impl OverwritePolicy {
+    pub const ALL : [OverwritePolicy; 5] = [OverwritePolicy::Refuse, OverwritePolicy::NoClobber, OverwritePolicy::Force,
+        OverwritePolicy::Backup, OverwritePolicy::Prompt];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OverwritePolicy::Refuse => "refuse",
+            OverwritePolicy::NoClobber => "no-clobber",
+            OverwritePolicy::Force => "force",
+            OverwritePolicy::Backup => "backup",
+            OverwritePolicy::Prompt => "prompt",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<OverwritePolicy> {
+        OverwritePolicy::ALL.into_iter().find(|policy| policy.name() == name)
+    }
+}
+
+impl fmt::Display for OverwritePolicy {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Lets `write` create the file at `destination`, or at a temporary path if
+/// `policy` allows replacing an existing one. The temporary path lies in a
+/// private directory next to the destination that is unique to this call and
+/// removed afterwards. The existing file is only replaced once `write`
+/// succeeded, so a wrong password does not cost it.
+/// `confirm` is asked under `OverwritePolicy::Prompt`. Returns whether the
+/// file was written, or skipped because the destination was kept.
+pub fn write_destination<C, W>(destination : &str, policy : OverwritePolicy, confirm : C, write : W)
+    -> Result<bool, CryptoError> where C : FnOnce(&str) -> bool, W : FnOnce(&str) -> Result<(), CryptoError> {
+    if fs::symlink_metadata(destination).is_err() {
+        write(destination)?;
+        return Ok(true);
+    }
+    let replace : bool = match policy {
+        OverwritePolicy::Refuse => return Err(CryptoError::DestinationExists(destination.to_string(), policy)),
+        OverwritePolicy::NoClobber => false,
+        OverwritePolicy::Force | OverwritePolicy::Backup => true,
+        OverwritePolicy::Prompt => confirm(destination),
+    };
+    if !replace {
+        return Ok(false);
+    }
+    if !Path::new(destination).is_file() {
+        return Err(CryptoError::OverwriteFailed(destination.to_string(), policy));
+    }
+    let failed = || CryptoError::OverwriteFailed(destination.to_string(), policy);
+    let (directory, temporary_path) : (TempDir, String) = temporary_path(destination).map_err(|_| failed())?;
+    write(&temporary_path)?;
+    if policy == OverwritePolicy::Backup {
+        back_up(destination).map_err(|_| failed())?;
+    }
+    fs::rename(&temporary_path, destination).map_err(|_| failed())?;
+    drop(directory);
+    Ok(true)
+}
+
+/// A path named like `destination` in a new private directory next to it,
+/// so renaming it over `destination` stays on the same file system. The
+/// directory and anything left in it are removed when it is dropped.
+pub(crate) fn temporary_path(destination : &str) -> io::Result<(TempDir, String)> {
+    let destination : &Path = Path::new(destination);
+    let parent : &Path = destination.parent().filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let directory : TempDir = tempfile::Builder::new().prefix(".sfe-").tempdir_in(parent)?;
+    let path : PathBuf = directory.path().join(destination.file_name().ok_or(io::ErrorKind::InvalidInput)?);
+    let path : String = path.to_str().ok_or(io::ErrorKind::InvalidInput)?.to_string();
+    Ok((directory, path))
+}
+
+/// Keeps `destination` as `<destination>.bak`, or as `<destination>.bak.<n>`
+/// with the first free number if there are backups already. The backup is a
+/// hard link, which never replaces an existing file, so no earlier backup is
+/// lost even to a concurrent run.
+fn back_up(destination : &str) -> io::Result<String> {
+    let first_backup : String = destination.to_string() + "." + BACKUP_EXTENSION;
+    let mut number : u32 = 0;
+    loop {
+        let backup_path : String = match number {
+            0 => first_backup.clone(),
+            number => format!("{}.{}", first_backup, number),
+        };
+        match fs::hard_link(destination, &backup_path) {
+            Ok(()) => return Ok(backup_path),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists && number < u32::MAX => number += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod overwrite_tests {
+    use super::*;
+
+    fn write(content : &'static str) -> impl FnOnce(&str) -> Result<(), CryptoError> {
+        move |path| fs::write(path, content).map_err(|_| CryptoError::Io(path.to_string()))
+    }
+
+    #[test]
+    fn test_overwrite_policies() {
+        let directory : &str = "testfiles/overwrite";
+        let path : &str = "testfiles/overwrite/overwrite.txt";
+        let backup_path : String = path.to_string() + "." + BACKUP_EXTENSION;
+        let second_backup_path : String = backup_path.clone() + ".1";
+        let _ = fs::remove_dir_all(directory);
+        fs::create_dir(directory).unwrap();
+        fs::write(path, "old").unwrap();
+
+        assert!(matches!(write_destination(path, OverwritePolicy::Refuse, |_| true, write("new")),
+            Err(CryptoError::DestinationExists(_, OverwritePolicy::Refuse))));
+        assert!(!write_destination(path, OverwritePolicy::NoClobber, |_| true, write("new")).unwrap());
+        assert!(!write_destination(path, OverwritePolicy::Prompt, |_| false, write("new")).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "old");
+
+        let failed = write_destination(path, OverwritePolicy::Force, |_| true,
+            |path| Err(CryptoError::AuthenticationFailed(path.to_string())));
+        assert!(matches!(failed, Err(CryptoError::AuthenticationFailed(_))));
+        assert_eq!(fs::read_to_string(path).unwrap(), "old");
+
+        assert!(write_destination(path, OverwritePolicy::Backup, |_| false, write("new")).unwrap());
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "old");
+        assert!(write_destination(path, OverwritePolicy::Backup, |_| false, write("newer")).unwrap());
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&second_backup_path).unwrap(), "new");
+        assert!(write_destination(path, OverwritePolicy::Prompt, |_| true, write("newest")).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "newest");
+        // Only the file and its backups are left, no temporary directories.
+        assert_eq!(fs::read_dir(directory).unwrap().count(), 3);
+
+        fs::remove_file(path).unwrap();
+        assert!(write_destination(path, OverwritePolicy::Refuse, |_| false, write("fresh")).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), "fresh");
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_temporary_path() {
+        let (directory, path) : (TempDir, String) = temporary_path("testfiles/temporary.txt").unwrap();
+        assert_eq!(Path::new(&path).parent().unwrap(), directory.path());
+        assert!(directory.path().parent().unwrap().ends_with("testfiles"));
+        assert!(directory.path().file_name().unwrap().to_string_lossy().starts_with(".sfe-"));
+        assert!(path.ends_with("/temporary.txt"));
+        let (other_directory, other_path) : (TempDir, String) = temporary_path("testfiles/temporary.txt").unwrap();
+        assert_ne!(path, other_path);
+        fs::write(&path, "left behind").unwrap();
+        drop(directory);
+        drop(other_directory);
+        assert!(!Path::new(&path).exists());
+        let (directory, _) : (TempDir, String) = temporary_path("temporary.txt").unwrap();
+        assert_eq!(fs::canonicalize(directory.path().parent().unwrap()).unwrap(), fs::canonicalize(".").unwrap());
+    }
+
+    #[test]
+    fn test_overwrite_policy_names() {
+        for policy in OverwritePolicy::ALL {
+            assert_eq!(OverwritePolicy::from_name(policy.name()), Some(policy));
+        }
+        assert_eq!(OverwritePolicy::from_name("clobber"), None);
+    }
+}
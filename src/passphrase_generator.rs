@@ -0,0 +1,92 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::secret::Secret;
+use rand::rngs::OsRng;
+use rand::Rng;
+
+/// The BIP-39 English wordlist: 2048 short, unambiguous words.
+const WORDLIST : &str = include_str!("wordlist.txt");
+const SEPARATOR : u8 = b'-';
+
+fn words() -> Vec<&'static str> {
+    parse_wordlist(WORDLIST)
+}
+
+/// Takes one word per line. Lines may start with the dice rolls that select
+/// the word, as in the EFF diceware lists, which are then skipped.
+fn parse_wordlist(wordlist : &str) -> Vec<&str> {
+    wordlist.lines()
+        .map(|line| line.rsplit('\t').next().unwrap_or(line).trim())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Generates a diceware-style passphrase of `word_count` uniformly chosen words.
+pub fn generate_passphrase(word_count : u32) -> Secret {
+    let words : Vec<&str> = words();
+    let longest_word : usize = words.iter().map(|word| word.len()).max().unwrap_or(0);
+    // Reserving everything up front keeps the buffer from being reallocated,
+    // which would leave partial copies of the passphrase behind.
+    let mut passphrase : Vec<u8> = Vec::with_capacity(word_count as usize * (longest_word + 1));
+    for i in 0..word_count {
+        if i > 0 {
+            passphrase.push(SEPARATOR);
+        }
+        passphrase.extend_from_slice(words[OsRng.gen_range(0..words.len())].as_bytes());
+    }
+    Secret::new(passphrase)
+}
+
+/// Entropy in bits of a passphrase made of `word_count` words.
+pub fn passphrase_entropy(word_count : u32) -> f64 {
+    word_count as f64 * (words().len() as f64).log2()
+}
+
+#[cfg(test)]
+mod passphrase_generator_tests {
+    use super::*;
+
+    #[test]
+    fn test_wordlist() {
+        let words : Vec<&str> = words();
+        assert_eq!(words.len(), 2048);
+        assert!(words.iter().all(|word| !word.is_empty() && word.bytes().all(|byte| byte.is_ascii_lowercase())));
+    }
+
+    #[test]
+    fn test_parse_wordlist() {
+        assert_eq!(parse_wordlist("abandon\nability\n"), vec!["abandon", "ability"]);
+        assert_eq!(parse_wordlist("11111\tabacus\n11112\tabdomen\n\n"), vec!["abacus", "abdomen"]);
+    }
+
+    #[test]
+    fn test_generate_passphrase() {
+        let passphrase : Secret = generate_passphrase(6);
+        let passphrase : &str = std::str::from_utf8(passphrase.expose()).unwrap();
+        let words : Vec<&str> = words();
+        assert_eq!(passphrase.split('-').count(), 6);
+        assert!(passphrase.split('-').all(|word| words.contains(&word)));
+    }
+
+    #[test]
+    fn test_passphrase_entropy() {
+        assert_eq!(passphrase_entropy(1), 11.0);
+        assert_eq!(passphrase_entropy(6), 66.0);
+    }
+}
@@ -20,11 +20,18 @@ use std::error::Error;
 use std::fmt;
 
 #[derive(Debug, Clone)]
-pub struct PasswordError;
+pub enum PasswordError {
+    Invalid,
+    /// The configured password source could not be read.
+    Unavailable(String),
+}
 
 impl fmt::Display for PasswordError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "invalid password")
+        match self {
+            PasswordError::Invalid => write!(f, "invalid password"),
+            PasswordError::Unavailable(message) => write!(f, "password source {}", message),
+        }
     }
 }
 
@@ -17,15 +17,63 @@
  * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
  */
 use crate::password_error::PasswordError;
+use sfe::config::PasswordSource;
+use sfe::secret::Secret;
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+use zeroize::Zeroizing;
 
-pub fn get_password() -> Result<String, PasswordError>{
-    let password1 = rpassword::prompt_password("Insert password: ").unwrap();
-    let password2 = rpassword::prompt_password("Insert password again: ").unwrap();
-    if password1 != password2 {
-        return Err(PasswordError);
-    } else if password1.trim().is_empty() {
-        return Err(PasswordError);
+static PASSWORD_SOURCE : OnceLock<PasswordSource> = OnceLock::new();
+
+/// Makes later requests for a password read it from `source`. Only the first
+/// call has an effect.
+pub fn set_password_source(source : PasswordSource) {
+    let _ = PASSWORD_SOURCE.set(source);
+}
+
+/// Reads the password from the configured source, None means prompting.
+fn password_from_source() -> Option<Result<Secret, PasswordError>> {
+    let password : Result<Zeroizing<String>, PasswordError> = match PASSWORD_SOURCE.get()? {
+        PasswordSource::Prompt => return None,
+        PasswordSource::Environment(variable) => env::var(variable).map(Zeroizing::new)
+            .map_err(|error| PasswordError::Unavailable(format!("env:{}: {}", variable, error))),
+        PasswordSource::File(path) => fs::read_to_string(path).map(Zeroizing::new)
+            .map_err(|error| PasswordError::Unavailable(format!("file:{}: {}", path, error))),
+    };
+    Some(password.and_then(|password| {
+        let password : &str = password.lines().next().unwrap_or("");
+        if password.is_empty() {
+            return Err(PasswordError::Invalid);
+        }
+        Ok(Secret::new(password.as_bytes().to_vec()))
+    }))
+}
+
+/// Asks for a new password, e.g. for encryption.
+pub fn get_password() -> Result<Secret, PasswordError>{
+    password_from_source().unwrap_or_else(prompt_new_password)
+}
+
+/// Prompts for a new password twice, even if passwords come from elsewhere.
+pub fn prompt_new_password() -> Result<Secret, PasswordError>{
+    let password1 = Zeroizing::new(rpassword::prompt_password("Insert password: ").unwrap());
+    let password2 = Zeroizing::new(rpassword::prompt_password("Insert password again: ").unwrap());
+    if password1 != password2 || password1.trim().is_empty() {
+        Err(PasswordError::Invalid)
     } else {
-        return Ok(password1);
+        Ok(Secret::new(password1.as_bytes().to_vec()))
+    }
+}
+
+/// Asks for an existing password once, e.g. for decryption.
+pub fn get_existing_password() -> Result<Secret, PasswordError>{
+    if let Some(password) = password_from_source() {
+        return password;
+    }
+    let password = Zeroizing::new(rpassword::prompt_password("Insert password: ").unwrap());
+    if password.is_empty() {
+        return Err(PasswordError::Invalid);
     }
+    Ok(Secret::new(password.as_bytes().to_vec()))
 }
@@ -0,0 +1,137 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::block_cipher::BlockCipher;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::kdf::Kdf;
+use crate::crypto_parameters::kdf_cost::{KdfCost, PBKDF2_ITERATIONS};
+use crate::crypto_parameters::key_size::KeySize;
+use crate::crypto_parameters::operation_mode::OperationMode;
+use std::fmt;
+
+/// A named set of parameters, so nobody has to pick every one of them.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Profile {
+    Fast,
+    Default,
+    Paranoid,
+    Fips,
+}
+
+impl Profile {
+    pub const ALL : [Profile; 4] = [Profile::Fast, Profile::Default, Profile::Paranoid, Profile::Fips];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Fast => "fast",
+            Profile::Default => "default",
+            Profile::Paranoid => "paranoid",
+            Profile::Fips => "fips",
+        }
+    }
+
+    pub fn from_name(name : &str) -> Option<Profile> {
+        Profile::ALL.into_iter().find(|profile| profile.name() == name)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Profile::Fast => "a shorter key and PBKDF2 instead of Argon2id, quicker to open and to attack",
+            Profile::Default => "what sfe uses unless told otherwise",
+            Profile::Paranoid => "Argon2id with 1 GiB of memory per password guess instead of 64 MiB",
+            Profile::Fips => "only FIPS approved algorithms, a random nonce covers the whole file",
+        }
+    }
+
+    /// The KDF and the costs it runs with for a password.
+    pub fn kdf_cost(self) -> KdfCost {
+        match self {
+            Profile::Fast => KdfCost::Pbkdf2HmacSha3512{
+                iterations : PBKDF2_ITERATIONS / 2,
+            },
+            Profile::Default => KdfCost::default_for(Kdf::Argon2id),
+            Profile::Fips => KdfCost::Pbkdf2HmacSha3512{
+                iterations : PBKDF2_ITERATIONS,
+            },
+            Profile::Paranoid => KdfCost::Argon2id{
+                memory_kib : 1 << 20,
+                iterations : 4,
+                parallelism : 4,
+            },
+        }
+    }
+
+    /// The parameters for a password, other credentials still change the
+    /// KDF and the key encapsulation.
+    pub fn parameters(self) -> CryptoParameters {
+        let builder = CryptoParameters::builder().kdf_cost(self.kdf_cost());
+        match self {
+            Profile::Fast => builder.key_size(KeySize::Size128),
+            Profile::Default | Profile::Paranoid => builder,
+            Profile::Fips => builder.block_cipher(BlockCipher::Aes).operation_mode(OperationMode::Gcm),
+        }.build().expect("profiles use valid parameters")
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        let parameters : CryptoParameters = self.parameters();
+        write!(f, "{:<10}{}-{} {}, {}: {}", self.name(), parameters.block_cipher(), parameters.key_size().to_integer(),
+            parameters.operation_mode(), parameters.kdf_cost(), self.description())
+    }
+}
+
+#[cfg(test)]
+mod profile_tests {
+    use super::*;
+    use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+
+    #[test]
+    fn test_profile_from_name() {
+        for profile in Profile::ALL {
+            assert_eq!(Profile::from_name(profile.name()).unwrap(), profile);
+        }
+        assert_eq!(Profile::from_name("weak"), None);
+    }
+
+    #[test]
+    fn test_profile_parameters() {
+        assert_eq!(Profile::Default.parameters(), CryptoParameters::default());
+        assert_eq!(Profile::Fast.parameters().key_size(), KeySize::Size128);
+        assert_eq!(Profile::Paranoid.parameters().kdf(), Kdf::Argon2id);
+        let fips : CryptoParameters = Profile::Fips.parameters();
+        assert_eq!((fips.block_cipher(), fips.kdf(), fips.operation_mode()),
+            (BlockCipher::Aes, Kdf::Pbkdf2HmacSha3512, OperationMode::Gcm));
+        for profile in Profile::ALL {
+            assert_eq!(profile.parameters().key_encapsulation(), KeyEncapsulation::Keyslots);
+            assert_eq!(profile.parameters().kdf_cost(), profile.kdf_cost());
+        }
+    }
+
+    #[test]
+    fn test_profile_costs() {
+        let iterations = |profile : Profile| match profile.kdf_cost() {
+            KdfCost::Pbkdf2HmacSha3512{iterations} => iterations,
+            _ => panic!("{} does not use PBKDF2", profile.name()),
+        };
+        assert!(iterations(Profile::Fast) < iterations(Profile::Fips));
+        assert_eq!(iterations(Profile::Fips), PBKDF2_ITERATIONS);
+        assert_eq!(Profile::Default.kdf_cost(), KdfCost::default_for(Kdf::Argon2id));
+        assert!(matches!(Profile::Paranoid.kdf_cost(), KdfCost::Argon2id{memory_kib, ..} if memory_kib == 1 << 20));
+    }
+}
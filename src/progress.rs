@@ -0,0 +1,181 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL : Duration = Duration::from_millis(200);
+const BAR_WIDTH : usize = 30;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ProgressMode {
+    /// A single line on stderr that is redrawn in place.
+    Bar,
+    /// One JSON object per line on stderr.
+    Json,
+}
+
+/// What a report line describes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Event {
+    Progress,
+    Done,
+    Failed,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::Progress => "progress",
+            Event::Done => "done",
+            Event::Failed => "failed",
+        }
+    }
+}
+
+struct Progress {
+    mode : ProgressMode,
+    total : u64,
+    processed : AtomicU64,
+    started : Instant,
+    last_report : Mutex<Instant>,
+}
+
+// The cipher loops deep down report to whichever progress the command
+// started, so none of the functions in between need to know about it.
+static PROGRESS : Mutex<Option<Arc<Progress>>> = Mutex::new(None);
+
+/// Starts reporting progress towards `total` bytes.
+pub fn start(mode : ProgressMode, total : u64) {
+    *PROGRESS.lock().unwrap() = Some(Arc::new(Progress{
+        mode,
+        total,
+        processed : AtomicU64::new(0),
+        started : Instant::now(),
+        last_report : Mutex::new(Instant::now()),
+    }));
+}
+
+/// Counts `bytes` as processed. Called from the cipher loops, possibly on
+/// several threads at once. Does nothing unless progress was started.
+///
+/// The total is the size of the source files, so the loops count the
+/// plaintext they sealed or opened and decryption counts the rest of each
+/// file, its header, tags and armor, once the file has been decrypted.
+/// The chunked formats call this after every segment. A GCM payload is a
+/// single AEAD call that cannot report from inside, so it only advances once
+/// the whole file is done.
+pub fn advance(bytes : usize) {
+    let Some(progress) = PROGRESS.lock().unwrap().clone() else {
+        return;
+    };
+    let processed : u64 = progress.processed.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+    // Whoever holds the lock reports, the others skip this round.
+    let due : bool = match progress.last_report.try_lock() {
+        Ok(mut last_report) if last_report.elapsed() >= REPORT_INTERVAL => {
+            *last_report = Instant::now();
+            true
+        }
+        _ => false,
+    };
+    if due {
+        report(&progress, processed.min(progress.total), Event::Progress);
+    }
+}
+
+/// Reports the final state and stops reporting. A run that did not
+/// succeed is reported as failed with the bytes it actually processed.
+pub fn finish(succeeded : bool) {
+    if let Some(progress) = PROGRESS.lock().unwrap().take() {
+        if succeeded {
+            report(&progress, progress.total, Event::Done);
+        } else {
+            report(&progress, progress.processed.load(Ordering::Relaxed).min(progress.total), Event::Failed);
+        }
+    }
+}
+
+fn report(progress : &Progress, processed : u64, event : Event) {
+    let elapsed : f64 = progress.started.elapsed().as_secs_f64();
+    let bytes_per_second : f64 = if elapsed > 0.0 { processed as f64 / elapsed } else { 0.0 };
+    let remaining : u64 = progress.total - processed;
+    let eta : Option<u64> = (bytes_per_second > 0.0).then(|| (remaining as f64 / bytes_per_second).ceil() as u64);
+    let line : String = match progress.mode {
+        ProgressMode::Bar => format!("\r{}{}", format_bar(processed, progress.total, bytes_per_second, eta),
+            match event {
+                Event::Progress => "",
+                Event::Done => "\n",
+                Event::Failed => " failed\n",
+            }),
+        ProgressMode::Json => format_json(processed, progress.total, bytes_per_second, eta, event) + "\n",
+    };
+    let mut stderr = std::io::stderr().lock();
+    let _ = stderr.write_all(line.as_bytes());
+    let _ = stderr.flush();
+}
+
+fn format_bar(processed : u64, total : u64, bytes_per_second : f64, eta : Option<u64>) -> String {
+    let fraction : f64 = if total == 0 { 1.0 } else { processed as f64 / total as f64 };
+    let filled : usize = (fraction * BAR_WIDTH as f64) as usize;
+    format!("[{}{}] {:3.0}% {} / {} {}/s ETA {}",
+        "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), fraction * 100.0,
+        format_bytes(processed as f64), format_bytes(total as f64), format_bytes(bytes_per_second),
+        eta.map_or("--:--".to_string(), |eta| format!("{}:{:02}", eta / 60, eta % 60)))
+}
+
+fn format_json(processed : u64, total : u64, bytes_per_second : f64, eta : Option<u64>, event : Event) -> String {
+    format!("{{\"event\":\"{}\",\"processed\":{},\"total\":{},\"bytes_per_second\":{:.0},\"eta_seconds\":{}}}",
+        event.name(), processed, total, bytes_per_second,
+        eta.map_or("null".to_string(), |eta| eta.to_string()))
+}
+
+fn format_bytes(bytes : f64) -> String {
+    const UNITS : [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value : f64 = bytes;
+    let mut unit : usize = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bar() {
+        assert_eq!(format_bar(1_500_000, 3_000_000, 250_000.0, Some(6)),
+            "[###############---------------]  50% 1.5 MB / 3.0 MB 250.0 KB/s ETA 0:06");
+        assert_eq!(format_bar(0, 0, 0.0, None),
+            "[##############################] 100% 0.0 B / 0.0 B 0.0 B/s ETA --:--");
+    }
+
+    #[test]
+    fn test_format_json() {
+        assert_eq!(format_json(10, 100, 5.4, Some(17), Event::Progress),
+            "{\"event\":\"progress\",\"processed\":10,\"total\":100,\"bytes_per_second\":5,\"eta_seconds\":17}");
+        assert_eq!(format_json(100, 100, 50.0, None, Event::Done),
+            "{\"event\":\"done\",\"processed\":100,\"total\":100,\"bytes_per_second\":50,\"eta_seconds\":null}");
+        assert_eq!(format_json(40, 100, 20.0, Some(3), Event::Failed),
+            "{\"event\":\"failed\",\"processed\":40,\"total\":100,\"bytes_per_second\":20,\"eta_seconds\":3}");
+    }
+}
@@ -0,0 +1,92 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::key_size::KeySize;
+use crate::secret::Secret;
+use std::fs;
+use std::io;
+use zeroize::Zeroizing;
+
+/// Loads a raw key from a file holding either the key itself or its
+/// hexadecimal representation. The key has to match one of the `KeySize`s.
+pub fn load_raw_key(path : &str) -> io::Result<(Secret, KeySize)> {
+    let content : Zeroizing<Vec<u8>> = Zeroizing::new(fs::read(path)?);
+    let key : Secret = decode_hex(content.trim_ascii())
+        .filter(|key| KeySize::from_integer(key.expose().len() as u32 * 8).is_some())
+        .unwrap_or_else(|| Secret::new(content.to_vec()));
+    match KeySize::from_integer(key.expose().len() as u32 * 8) {
+        Some(key_size) => Ok((key, key_size)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{}: raw key must be 128, 192 or 256 bits, as binary or hex", path))),
+    }
+}
+
+/// Decodes a hexadecimal string into a secret.
+pub fn decode_hex(hex : &[u8]) -> Option<Secret> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut key : Vec<u8> = Vec::with_capacity(hex.len() / 2);
+    for pair in hex.chunks(2) {
+        let high = (pair[0] as char).to_digit(16);
+        let low = (pair[1] as char).to_digit(16);
+        match (high, low) {
+            (Some(high), Some(low)) => key.push((high * 16 + low) as u8),
+            _ => {
+                // Wrap what was decoded so far so it gets zeroed.
+                drop(Secret::new(key));
+                return None;
+            }
+        }
+    }
+    Some(Secret::new(key))
+}
+
+#[cfg(test)]
+mod raw_key_tests {
+    use super::*;
+
+    #[test]
+    fn test_load_raw_key_hex() {
+        let path : &str = "testfiles/raw_key_hex.key";
+        fs::write(path, "00112233445566778899aabbccddeeff\n").unwrap();
+        let (key, key_size) = load_raw_key(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(key_size, KeySize::Size128);
+        assert_eq!(key.expose()[0..3], [0x00, 0x11, 0x22]);
+        assert_eq!(key.expose()[15], 0xFF);
+    }
+
+    #[test]
+    fn test_load_raw_key_binary() {
+        let path : &str = "testfiles/raw_key_binary.key";
+        fs::write(path, [0xFE; 32]).unwrap();
+        let (key, key_size) = load_raw_key(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(key_size, KeySize::Size256);
+        assert_eq!(key.expose(), [0xFE; 32]);
+    }
+
+    #[test]
+    fn test_load_raw_key_wrong_size() {
+        let path : &str = "testfiles/raw_key_wrong_size.key";
+        fs::write(path, "0011223344").unwrap();
+        assert!(load_raw_key(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::header::{RecipientStanza, EPHEMERAL_KEY_SIZE, TAG_SIZE};
+use crate::raw_key::decode_hex;
+use crate::secret::Secret;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+use zeroize::Zeroizing;
+
+pub const PUBLIC_KEY_PREFIX : &str = "sfepub";
+pub const SECRET_KEY_PREFIX : &str = "SFE-SECRET-KEY-";
+pub const AGE_PUBLIC_KEY_PREFIX : &str = "age1";
+pub const AGE_SECRET_KEY_PREFIX : &str = "AGE-SECRET-KEY-1";
+const WRAP_KEY_INFO : &[u8] = b"sfe x25519 key wrap";
+
+/// Generates an identity and returns it together with its public key.
+pub fn generate_identity() -> (Secret, PublicKey) {
+    let identity : StaticSecret = StaticSecret::random_from_rng(OsRng);
+    let public_key : PublicKey = PublicKey::from(&identity);
+    (Secret::new(identity.to_bytes().to_vec()), public_key)
+}
+
+pub fn format_recipient(public_key : &PublicKey) -> String {
+    let hex : String = public_key.as_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    PUBLIC_KEY_PREFIX.to_string() + &hex
+}
+
+/// Parses a recipient printed by `sfe keygen` or an age X25519 recipient.
+pub fn parse_recipient(recipient : &str) -> Option<PublicKey> {
+    if recipient.starts_with(AGE_PUBLIC_KEY_PREFIX) {
+        let bytes : [u8; 32] = decode_bech32(recipient, AGE_PUBLIC_KEY_PREFIX)?.expose().try_into().ok()?;
+        return Some(PublicKey::from(bytes));
+    }
+    let hex : &str = recipient.strip_prefix(PUBLIC_KEY_PREFIX)?;
+    let bytes : Secret = decode_hex(hex.as_bytes())?;
+    let bytes : [u8; 32] = bytes.expose().try_into().ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+/// The content of an identity file: the public key as a comment, followed
+/// by the secret key.
+pub fn format_identity(identity : &Secret, public_key : &PublicKey) -> Secret {
+    let mut content : Vec<u8> = Vec::with_capacity(128 + identity.expose().len() * 2);
+    content.extend_from_slice(b"# public key: ");
+    content.extend_from_slice(format_recipient(public_key).as_bytes());
+    content.push(b'\n');
+    content.extend_from_slice(SECRET_KEY_PREFIX.as_bytes());
+    for byte in identity.expose() {
+        content.extend_from_slice(&hex_digits(*byte));
+    }
+    content.push(b'\n');
+    Secret::new(content)
+}
+
+/// Decodes a bech32 string as age uses them for its keys, checking that it
+/// starts with `prefix` in either case.
+fn decode_bech32(text : &str, prefix : &str) -> Option<Secret> {
+    if !text.to_ascii_lowercase().starts_with(&prefix.to_ascii_lowercase()) {
+        return None;
+    }
+    let (hrp, data) = bech32::decode(text).ok()?;
+    let data : Secret = Secret::new(data);
+    if hrp.as_str().to_ascii_lowercase() + "1" != prefix.to_ascii_lowercase() {
+        return None;
+    }
+    Some(data)
+}
+
+fn hex_digits(byte : u8) -> [u8; 2] {
+    const DIGITS : &[u8; 16] = b"0123456789ABCDEF";
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0x0F) as usize]]
+}
+
+/// Loads the secret key from an identity file written by `sfe keygen` or `age-keygen`.
+pub fn load_identity(path : &str) -> io::Result<Secret> {
+    let content : Zeroizing<Vec<u8>> = Zeroizing::new(fs::read(path)?);
+    content.split(|byte| *byte == b'\n')
+        .map(|line| line.trim_ascii())
+        .filter_map(|line| match line.strip_prefix(SECRET_KEY_PREFIX.as_bytes()) {
+            Some(hex) => decode_hex(hex),
+            None => decode_bech32(std::str::from_utf8(line).ok()?, AGE_SECRET_KEY_PREFIX),
+        })
+        .find(|identity| identity.expose().len() == 32)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no identity found", path)))
+}
+
+/// Wraps the payload key for a single recipient using an ephemeral key
+/// agreement, so only the holder of the matching identity can unwrap it.
+pub fn wrap_key(key : &Secret, recipient : &PublicKey) -> Option<RecipientStanza> {
+    let (ephemeral_public_key, wrapping_key) = ephemeral_wrapping_key(recipient, WRAP_KEY_INFO)?;
+    let cipher : Aes256Gcm = Aes256Gcm::new_from_slice(wrapping_key.expose()).ok()?;
+    // Every wrapping key is used exactly once, so a constant nonce is fine.
+    let wrapped_key : Vec<u8> = cipher.encrypt(Nonce::from_slice(&[0x00; 12]), key.expose()).ok()?;
+    debug_assert_eq!(wrapped_key.len(), key.expose().len() + TAG_SIZE);
+    Some(RecipientStanza{
+        ephemeral_public_key : ephemeral_public_key.to_bytes(),
+        wrapped_key,
+    })
+}
+
+/// Unwraps the payload key from a stanza, or returns `None` if the stanza
+/// was not made for this identity.
+pub fn unwrap_key(stanza : &RecipientStanza, identity : &Secret) -> Option<Secret> {
+    let ephemeral_public_key : PublicKey = PublicKey::from(stanza.ephemeral_public_key);
+    let wrapping_key : Secret = static_wrapping_key(identity, &ephemeral_public_key, WRAP_KEY_INFO)?;
+    let cipher : Aes256Gcm = Aes256Gcm::new_from_slice(wrapping_key.expose()).ok()?;
+    cipher.decrypt(Nonce::from_slice(&[0x00; 12]), stanza.wrapped_key.as_slice()).ok().map(Secret::new)
+}
+
+/// Agrees on a key with `recipient` using a fresh ephemeral key and derives a
+/// wrapping key from it with `info`. Returns the ephemeral public key, which
+/// the recipient needs, together with the wrapping key.
+pub fn ephemeral_wrapping_key(recipient : &PublicKey, info : &[u8]) -> Option<(PublicKey, Secret)> {
+    let ephemeral_secret : EphemeralSecret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public_key : PublicKey = PublicKey::from(&ephemeral_secret);
+    let shared_secret : SharedSecret = ephemeral_secret.diffie_hellman(recipient);
+    if !shared_secret.was_contributory() {
+        return None;
+    }
+    let wrapping_key : Secret = wrapping_key(&shared_secret, &ephemeral_public_key, recipient, info);
+    Some((ephemeral_public_key, wrapping_key))
+}
+
+/// The recipient's side of `ephemeral_wrapping_key`.
+pub fn static_wrapping_key(identity : &Secret, ephemeral_public_key : &PublicKey, info : &[u8]) -> Option<Secret> {
+    let identity_bytes : Zeroizing<[u8; 32]> = Zeroizing::new(identity.expose().try_into().ok()?);
+    let identity : StaticSecret = StaticSecret::from(*identity_bytes);
+    let public_key : PublicKey = PublicKey::from(&identity);
+    let shared_secret : SharedSecret = identity.diffie_hellman(ephemeral_public_key);
+    if !shared_secret.was_contributory() {
+        return None;
+    }
+    Some(wrapping_key(&shared_secret, ephemeral_public_key, &public_key, info))
+}
+
+fn wrapping_key(shared_secret : &SharedSecret, ephemeral_public_key : &PublicKey, recipient : &PublicKey,
+    info : &[u8]) -> Secret {
+    let mut salt : [u8; 2 * EPHEMERAL_KEY_SIZE] = [0x00; 2 * EPHEMERAL_KEY_SIZE];
+    salt[0..EPHEMERAL_KEY_SIZE].copy_from_slice(ephemeral_public_key.as_bytes());
+    salt[EPHEMERAL_KEY_SIZE..].copy_from_slice(recipient.as_bytes());
+    let mut key : Vec<u8> = vec![0x00; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes())
+        .expand(info, &mut key)
+        .expect("32 bytes are a valid HKDF-SHA256 output length");
+    Secret::new(key)
+}
+
+#[cfg(test)]
+mod recipients_tests {
+    use super::*;
+
+    #[test]
+    fn test_recipient_round_trip() {
+        let (_, public_key) = generate_identity();
+        let recipient : String = format_recipient(&public_key);
+        assert!(recipient.starts_with(PUBLIC_KEY_PREFIX));
+        assert_eq!(parse_recipient(&recipient).unwrap(), public_key);
+        assert_eq!(parse_recipient(&recipient[1..]), None);
+        assert_eq!(parse_recipient(&recipient[0..recipient.len() - 2]), None);
+    }
+
+    #[test]
+    fn test_parse_age_keys() {
+        // Generated by age-keygen.
+        let path : &str = "testfiles/age_identity.key";
+        fs::write(path, "# public key: age12uve52z6jhg0edywksdtlmdayv66l9jqzm9mql4asff52mxkm46s3qaux5\n\
+            AGE-SECRET-KEY-1H6W65DLM3N5K2LS3X9GSZ02T6FD42GXZM50NVSFWXSZJVEQXHHKQYFEL0Y\n").unwrap();
+        let identity : Secret = load_identity(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let identity : StaticSecret = StaticSecret::from(<[u8; 32]>::try_from(identity.expose()).unwrap());
+        let public_key : PublicKey = parse_recipient("age12uve52z6jhg0edywksdtlmdayv66l9jqzm9mql4asff52mxkm46s3qaux5").unwrap();
+        assert_eq!(PublicKey::from(&identity), public_key);
+        assert_eq!(parse_recipient("age12uve52z6jhg0edywksdtlmdayv66l9jqzm9mql4asff52mxkm46s3qaux4"), None);
+    }
+
+    #[test]
+    fn test_identity_file_round_trip() {
+        let path : &str = "testfiles/identity_round_trip.key";
+        let (identity, public_key) = generate_identity();
+        fs::write(path, format_identity(&identity, &public_key).expose()).unwrap();
+        let loaded : Secret = load_identity(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.expose(), identity.expose());
+    }
+
+    #[test]
+    fn test_wrap_and_unwrap_key() {
+        let (identity, public_key) = generate_identity();
+        let (other_identity, _) = generate_identity();
+        let key : Secret = Secret::new(vec![0x42; 24]);
+        let stanza : RecipientStanza = wrap_key(&key, &public_key).unwrap();
+        assert_eq!(stanza.wrapped_key.len(), 24 + TAG_SIZE);
+        assert_eq!(unwrap_key(&stanza, &identity).unwrap().expose(), key.expose());
+        assert!(unwrap_key(&stanza, &other_identity).is_none());
+    }
+}
@@ -0,0 +1,96 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::memory_lock;
+use crate::memory_lock::LockedPages;
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Holds sensitive bytes such as passwords and derived keys. The buffer is
+/// locked into memory while alive, zeroed when the secret is dropped and never
+/// shows up in formatted output.
+pub struct Secret {
+    bytes : Vec<u8>,
+    locked_pages : LockedPages,
+}
+
+impl Secret {
+    pub fn new(bytes : Vec<u8>) -> Secret {
+        let locked_pages : LockedPages = memory_lock::lock(&bytes);
+        Secret{
+            bytes,
+            locked_pages,
+        }
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// The copy is locked and zeroed on its own.
+impl Clone for Secret {
+    fn clone(&self) -> Secret {
+        Secret::new(self.bytes.clone())
+    }
+}
+
+impl From<String> for Secret {
+    fn from(string : String) -> Secret {
+        Secret::new(string.into_bytes())
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Wipes the spare capacity too, which may hold secret data from
+        // before the vector was truncated.
+        self.bytes.zeroize();
+        memory_lock::unlock(&self.locked_pages);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret([REDACTED])")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[REDACTED]")
+    }
+}
+
+#[cfg(test)]
+mod secret_tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_expose() {
+        let secret : Secret = Secret::from("hunter2".to_string());
+        assert_eq!(secret.expose(), b"hunter2");
+    }
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let secret : Secret = Secret::from("hunter2".to_string());
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+    }
+}
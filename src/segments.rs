@@ -0,0 +1,217 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::file_cipher::{open, seal};
+use crate::header::{NONCE_SIZE, TAG_SIZE};
+use crate::progress;
+use crate::secret::Secret;
+use std::thread;
+
+// The chunked format splits the plaintext into segments of SEGMENT_SIZE
+// bytes, only the last one may be shorter or empty. Each segment is sealed
+// on its own and followed by its tag, so segments can be processed in
+// parallel. The nonce of a segment is the header nonce with its index and
+// a flag for the last segment mixed into the final bytes, which rules out
+// reordering and truncation. The header tag seals no data under its own
+// flag, so a wrong key is detected before any segment is touched.
+
+/// Plaintext bytes per segment.
+pub const SEGMENT_SIZE : usize = 1 << 20;
+const SEGMENT_FLAG : u8 = 0x00;
+const LAST_SEGMENT_FLAG : u8 = 0x01;
+const HEADER_FLAG : u8 = 0x02;
+
+pub fn ciphertext_length(plaintext_length : usize) -> usize {
+    plaintext_length + segment_count(plaintext_length) * TAG_SIZE
+}
+
+pub fn plaintext_length(ciphertext_length : usize) -> Option<usize> {
+    let count : usize = ciphertext_length.div_ceil(SEGMENT_SIZE + TAG_SIZE).max(1);
+    let plaintext_length : usize = ciphertext_length.checked_sub(count * TAG_SIZE)?;
+    (segment_count(plaintext_length) == count).then_some(plaintext_length)
+}
+
+/// Seals `source` into `destination`, which has to be `ciphertext_length`
+/// bytes long, on all available cores. Returns the header tag.
+pub fn seal_segments(parameters : &CryptoParameters, key : &Secret, nonce : &[u8], authenticated_data : &[u8],
+    source : &[u8], destination : &mut [u8]) -> Option<[u8; TAG_SIZE]> {
+    let count : usize = segment_count(source.len());
+    if destination.len() != ciphertext_length(source.len()) || u32::try_from(count).is_err() {
+        return None;
+    }
+    let sealed : bool = for_each_segment(count, destination, SEGMENT_SIZE + TAG_SIZE, |index, segment| {
+        let plaintext : &[u8] = &source[index * SEGMENT_SIZE..source.len().min((index + 1) * SEGMENT_SIZE)];
+        let (buffer, tag) = segment.split_at_mut(plaintext.len());
+        buffer.copy_from_slice(plaintext);
+        match seal(parameters, key, &segment_nonce(nonce, index, segment_flag(index + 1 == count)), authenticated_data, buffer) {
+            Some(segment_tag) => {
+                tag.copy_from_slice(&segment_tag);
+                progress::advance(plaintext.len());
+                true
+            }
+            None => false,
+        }
+    });
+    if !sealed {
+        return None;
+    }
+    seal_header(parameters, key, nonce, authenticated_data)
+}
+
+/// Opens `source` into `destination`, which has to be `plaintext_length`
+/// bytes long, on all available cores. Fails if the header tag or any
+/// segment does not verify.
+pub fn open_segments(parameters : &CryptoParameters, key : &Secret, nonce : &[u8], authenticated_data : &[u8],
+    source : &[u8], destination : &mut [u8], tag : &[u8]) -> bool {
+    if plaintext_length(source.len()) != Some(destination.len())
+        || !open_header(parameters, key, nonce, authenticated_data, tag) {
+        return false;
+    }
+    let count : usize = segment_count(destination.len());
+    for_each_segment(count, destination, SEGMENT_SIZE, |index, plaintext| {
+        let start : usize = index * (SEGMENT_SIZE + TAG_SIZE);
+        let (ciphertext, tag) = source[start..start + plaintext.len() + TAG_SIZE].split_at(plaintext.len());
+        plaintext.copy_from_slice(ciphertext);
+        let opened : bool = open(parameters, key, &segment_nonce(nonce, index, segment_flag(index + 1 == count)),
+            authenticated_data, plaintext, tag);
+        progress::advance(plaintext.len());
+        opened
+    })
+}
+
+/// The header tag, which seals no data.
+pub fn seal_header(parameters : &CryptoParameters, key : &Secret, nonce : &[u8],
+    authenticated_data : &[u8]) -> Option<[u8; TAG_SIZE]> {
+    seal(parameters, key, &segment_nonce(nonce, 0, HEADER_FLAG), authenticated_data, &mut [])
+}
+
+pub fn open_header(parameters : &CryptoParameters, key : &Secret, nonce : &[u8], authenticated_data : &[u8],
+    tag : &[u8]) -> bool {
+    open(parameters, key, &segment_nonce(nonce, 0, HEADER_FLAG), authenticated_data, &mut [], tag)
+}
+
+/// The nonce of the segment at `index`, for streams that seal one segment
+/// at a time because they do not know their length up front.
+pub fn nonce_for_segment(nonce : &[u8], index : usize, last : bool) -> Option<[u8; NONCE_SIZE]> {
+    u32::try_from(index).ok()?;
+    Some(segment_nonce(nonce, index, segment_flag(last)))
+}
+
+fn segment_count(plaintext_length : usize) -> usize {
+    plaintext_length.div_ceil(SEGMENT_SIZE).max(1)
+}
+
+fn segment_flag(last : bool) -> u8 {
+    if last { LAST_SEGMENT_FLAG } else { SEGMENT_FLAG }
+}
+
+fn segment_nonce(nonce : &[u8], index : usize, flag : u8) -> [u8; NONCE_SIZE] {
+    let mut segment_nonce : [u8; NONCE_SIZE] = [0x00; NONCE_SIZE];
+    segment_nonce.copy_from_slice(nonce);
+    let index : [u8; 4] = (index as u32).to_be_bytes();
+    for (byte, mixed) in segment_nonce[NONCE_SIZE - 5..].iter_mut().zip(index.iter().chain([flag].iter())) {
+        *byte ^= mixed;
+    }
+    segment_nonce
+}
+
+/// Calls `process` for each of `count` segments with its index and its part
+/// of `output`. The segments are split into contiguous runs, one per core.
+fn for_each_segment<F>(count : usize, output : &mut [u8], segment_size : usize, process : F) -> bool
+    where F : Fn(usize, &mut [u8]) -> bool + Sync {
+    let threads : usize = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let segments_per_run : usize = count.div_ceil(threads);
+    let run = |first : usize, output : &mut [u8]| (first..count.min(first + segments_per_run)).all(|index| {
+        let start : usize = (index - first) * segment_size;
+        let end : usize = output.len().min(start + segment_size);
+        process(index, &mut output[start..end])
+    });
+    if segments_per_run == count {
+        return run(0, output);
+    }
+    thread::scope(|scope| {
+        let mut runs = Vec::new();
+        let mut rest : &mut [u8] = output;
+        for first in (0..count).step_by(segments_per_run) {
+            let (output, tail) = rest.split_at_mut(rest.len().min(segments_per_run * segment_size));
+            rest = tail;
+            runs.push(scope.spawn(move || run(first, output)));
+        }
+        runs.into_iter().all(|run| run.join().expect("segment worker panicked"))
+    })
+}
+
+#[cfg(test)]
+mod segments_tests {
+    use super::*;
+
+    #[test]
+    fn test_lengths() {
+        for length in [0, 1, SEGMENT_SIZE - 1, SEGMENT_SIZE, SEGMENT_SIZE + 1, 5 * SEGMENT_SIZE] {
+            assert_eq!(plaintext_length(ciphertext_length(length)), Some(length));
+        }
+        assert_eq!(ciphertext_length(0), TAG_SIZE);
+        assert_eq!(ciphertext_length(SEGMENT_SIZE + 1), SEGMENT_SIZE + 1 + 2 * TAG_SIZE);
+        assert_eq!(plaintext_length(TAG_SIZE - 1), None);
+        assert_eq!(plaintext_length(SEGMENT_SIZE + 2 * TAG_SIZE), None);
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let parameters : CryptoParameters = CryptoParameters::default();
+        let key : Secret = Secret::new(vec![0x42; 32]);
+        let nonce : [u8; NONCE_SIZE] = [0x17; NONCE_SIZE];
+        for length in [0, 1000, 3 * SEGMENT_SIZE + 1000] {
+            let plaintext : Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let mut ciphertext : Vec<u8> = vec![0x00; ciphertext_length(length)];
+            let tag : [u8; TAG_SIZE] = seal_segments(&parameters, &key, &nonce, b"header", &plaintext, &mut ciphertext).unwrap();
+            let mut decrypted : Vec<u8> = vec![0x00; length];
+            assert!(open_segments(&parameters, &key, &nonce, b"header", &ciphertext, &mut decrypted, &tag));
+            assert_eq!(decrypted, plaintext);
+            assert!(!open_segments(&parameters, &key, &nonce, b"other", &ciphertext, &mut decrypted, &tag));
+        }
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let parameters : CryptoParameters = CryptoParameters::default();
+        let key : Secret = Secret::new(vec![0x42; 32]);
+        let nonce : [u8; NONCE_SIZE] = [0x17; NONCE_SIZE];
+        let plaintext : Vec<u8> = vec![0x23; 2 * SEGMENT_SIZE + 10];
+        let mut ciphertext : Vec<u8> = vec![0x00; ciphertext_length(plaintext.len())];
+        let tag : [u8; TAG_SIZE] = seal_segments(&parameters, &key, &nonce, &[], &plaintext, &mut ciphertext).unwrap();
+        let mut decrypted : Vec<u8> = vec![0x00; plaintext.len()];
+
+        let mut flipped : Vec<u8> = ciphertext.clone();
+        flipped[SEGMENT_SIZE + TAG_SIZE + 5] ^= 0x01;
+        assert!(!open_segments(&parameters, &key, &nonce, &[], &flipped, &mut decrypted, &tag));
+
+        // Dropping the last segment leaves a full segment that is not marked as the last one.
+        let truncated : &[u8] = &ciphertext[0..2 * (SEGMENT_SIZE + TAG_SIZE)];
+        let mut decrypted : Vec<u8> = vec![0x00; 2 * SEGMENT_SIZE];
+        assert!(!open_segments(&parameters, &key, &nonce, &[], truncated, &mut decrypted, &tag));
+
+        let mut swapped : Vec<u8> = ciphertext.clone();
+        let segment : usize = SEGMENT_SIZE + TAG_SIZE;
+        swapped.copy_within(0..segment, segment);
+        swapped[0..segment].copy_from_slice(&ciphertext[segment..2 * segment]);
+        let mut decrypted : Vec<u8> = vec![0x00; plaintext.len()];
+        assert!(!open_segments(&parameters, &key, &nonce, &[], &swapped, &mut decrypted, &tag));
+    }
+}
@@ -0,0 +1,351 @@
+/* 
+ * Copyright 2022, Lukas Jäger
+ *
+ * This file is part of SFE.
+ *
+ * SFE is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * SFE is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with SFE.  If not, see <http://www.gnu.org/licenses/>.
+ */
+use crate::credentials::Credentials;
+use crate::crypto_error::CryptoError;
+use crate::crypto_parameters::crypto_parameters::CryptoParameters;
+use crate::crypto_parameters::operation_mode::OperationMode;
+use crate::file_cipher::{decryption_key, new_header, open, seal, IN_MEMORY};
+use crate::header::{Header, PREFIX_SIZE, TAG_SIZE};
+use crate::secret::Secret;
+use crate::segments::{nonce_for_segment, open_header, seal_header, SEGMENT_SIZE};
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use zeroize::Zeroizing;
+
+/// Encrypts everything written to it into `writer`, producing the same
+/// container as `encrypt_file`. In the chunked mode only one segment is held
+/// in memory, in the GCM mode the whole plaintext is. `finish` has to be
+/// called at the end, otherwise the ciphertext is incomplete.
+pub struct Encryptor<W : Write> {
+    writer : W,
+    header : Header,
+    key : Secret,
+    buffer : Zeroizing<Vec<u8>>,
+    segment : usize,
+}
+
+impl<W : Write> Encryptor<W> {
+    /// Creates the header and, in the chunked mode, writes it right away.
+    pub fn new(mut writer : W, credentials : &Credentials, parameters : CryptoParameters) -> Result<Encryptor<W>, CryptoError> {
+        let (header, key) = match parameters.operation_mode() {
+            OperationMode::Gcm => new_header(credentials, parameters, IN_MEMORY)?,
+            OperationMode::ChunkedGcm => {
+                let (header, key) = new_chunked_header(credentials, parameters)?;
+                writer.write_all(&header.to_byte_buffer()).map_err(|_| CryptoError::Io(IN_MEMORY.to_string()))?;
+                (header, key)
+            }
+        };
+        Ok(Encryptor{
+            writer,
+            header,
+            key,
+            buffer : Zeroizing::new(Vec::new()),
+            segment : 0,
+        })
+    }
+
+    /// Seals what is left and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.header.parameters.operation_mode() {
+            OperationMode::Gcm => {
+                self.header.tag = seal(&self.header.parameters, &self.key, &self.header.nonce,
+                    &self.header.authenticated_data(), &mut self.buffer).ok_or_else(encryption_error)?;
+                self.writer.write_all(&self.header.to_byte_buffer())?;
+                self.writer.write_all(&self.buffer)?;
+            }
+            OperationMode::ChunkedGcm => self.write_segment(true)?,
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_segment(&mut self, last : bool) -> io::Result<()> {
+        seal_segment(&self.header, &self.key, self.segment, last, &mut self.buffer)?;
+        self.writer.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.segment += 1;
+        Ok(())
+    }
+}
+
+impl<W : Write> Write for Encryptor<W> {
+    fn write(&mut self, data : &[u8]) -> io::Result<usize> {
+        if self.header.parameters.operation_mode() == OperationMode::Gcm {
+            self.buffer.extend_from_slice(data);
+            return Ok(data.len());
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        // A full segment is only sealed once more data shows it is not the last one.
+        if self.buffer.len() == SEGMENT_SIZE {
+            self.write_segment(false)?;
+        }
+        let length : usize = data.len().min(SEGMENT_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[0..length]);
+        Ok(length)
+    }
+
+    /// Flushes the writer, which does not include a partial segment.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts a container written by `Encryptor` or `encrypt_file` from
+/// `reader`. In the chunked mode a segment is only returned once it has been
+/// authenticated, a truncated or tampered stream fails with
+/// `io::ErrorKind::InvalidData` at the segment concerned. In the GCM mode the
+/// whole ciphertext is read and authenticated up front.
+pub struct Decryptor<R : Read> {
+    reader : R,
+    header : Header,
+    key : Secret,
+    plaintext : Zeroizing<Vec<u8>>,
+    position : usize,
+    next_segment : Vec<u8>,
+    segment : usize,
+    finished : bool,
+}
+
+impl<R : Read> Decryptor<R> {
+    /// Reads the header and checks the credentials against it.
+    pub fn new(mut reader : R, credentials : &Credentials) -> Result<Decryptor<R>, CryptoError> {
+        let mut byte_buffer : Vec<u8> = vec![0x00; PREFIX_SIZE];
+        reader.read_exact(&mut byte_buffer).map_err(|_| CryptoError::InvalidHeader(IN_MEMORY.to_string()))?;
+        byte_buffer.resize(header_size(&byte_buffer)?, 0x00);
+        reader.read_exact(&mut byte_buffer[PREFIX_SIZE..]).map_err(|_| CryptoError::InvalidHeader(IN_MEMORY.to_string()))?;
+        let (header, key) = unlock_header(&byte_buffer, credentials)?;
+        let mut decryptor : Decryptor<R> = Decryptor{
+            reader,
+            header,
+            key,
+            plaintext : Zeroizing::new(Vec::new()),
+            position : 0,
+            next_segment : Vec::new(),
+            segment : 0,
+            finished : false,
+        };
+        let header : &Header = &decryptor.header;
+        match header.parameters.operation_mode() {
+            OperationMode::Gcm => {
+                decryptor.reader.read_to_end(&mut decryptor.plaintext).map_err(|_| CryptoError::FileLoading(IN_MEMORY.to_string()))?;
+                if !open(&header.parameters, &decryptor.key, &header.nonce, &header.authenticated_data(),
+                    &mut decryptor.plaintext, &header.tag) {
+                    return Err(CryptoError::AuthenticationFailed(IN_MEMORY.to_string()));
+                }
+                decryptor.finished = true;
+            }
+            OperationMode::ChunkedGcm => {
+                decryptor.next_segment = read_segment(&mut decryptor.reader)
+                    .map_err(|_| CryptoError::FileLoading(IN_MEMORY.to_string()))?;
+            }
+        }
+        Ok(decryptor)
+    }
+
+    /// Opens the next segment into `plaintext`. Only a segment followed by
+    /// the end of the stream may be the last one.
+    fn read_plaintext(&mut self) -> io::Result<()> {
+        let mut segment : Zeroizing<Vec<u8>> = Zeroizing::new(mem::take(&mut self.next_segment));
+        let last : bool = if segment.len() == SEGMENT_SIZE + TAG_SIZE {
+            self.next_segment = read_segment(&mut self.reader)?;
+            self.next_segment.is_empty()
+        } else {
+            true
+        };
+        open_segment(&self.header, &self.key, self.segment, last, &mut segment)?;
+        self.plaintext = segment;
+        self.position = 0;
+        self.segment += 1;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+impl<R : Read> Read for Decryptor<R> {
+    fn read(&mut self, buffer : &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.read_plaintext()?;
+        }
+        let length : usize = buffer.len().min(self.plaintext.len() - self.position);
+        buffer[0..length].copy_from_slice(&self.plaintext[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Reads up to one segment with its tag, less only at the end of the stream.
+fn read_segment<R : Read>(reader : &mut R) -> io::Result<Vec<u8>> {
+    let mut segment : Vec<u8> = Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE);
+    reader.take((SEGMENT_SIZE + TAG_SIZE) as u64).read_to_end(&mut segment)?;
+    Ok(segment)
+}
+
+/// Creates the header of a chunked stream. Its tag does not depend on the
+/// payload, so it can be written before the first segment.
+pub(crate) fn new_chunked_header(credentials : &Credentials, parameters : CryptoParameters) -> Result<(Header, Secret), CryptoError> {
+    let (mut header, key) = new_header(credentials, parameters, IN_MEMORY)?;
+    header.tag = seal_header(&header.parameters, &key, &header.nonce, &header.authenticated_data())
+        .ok_or_else(|| CryptoError::Encryption(IN_MEMORY.to_string()))?;
+    Ok((header, key))
+}
+
+/// The size of the whole header from its first `PREFIX_SIZE` bytes.
+pub(crate) fn header_size(prefix : &[u8]) -> Result<usize, CryptoError> {
+    Header::size_from_prefix(prefix).ok_or_else(|| CryptoError::InvalidHeader(IN_MEMORY.to_string()))
+}
+
+/// Parses a header and returns it with the payload key. The header tag of a
+/// chunked stream is checked right away, so a wrong key shows before any
+/// segment is read.
+pub(crate) fn unlock_header(byte_buffer : &[u8], credentials : &Credentials) -> Result<(Header, Secret), CryptoError> {
+    let header : Header = Header::from_byte_buffer(byte_buffer)
+        .ok_or_else(|| CryptoError::InvalidHeader(IN_MEMORY.to_string()))?;
+    let key : Secret = decryption_key(&header, credentials, IN_MEMORY)?;
+    if header.parameters.operation_mode() == OperationMode::ChunkedGcm
+        && !open_header(&header.parameters, &key, &header.nonce, &header.authenticated_data(), &header.tag) {
+        return Err(CryptoError::AuthenticationFailed(IN_MEMORY.to_string()));
+    }
+    Ok((header, key))
+}
+
+/// Seals the plaintext in `segment` in place and appends its tag.
+pub(crate) fn seal_segment(header : &Header, key : &Secret, index : usize, last : bool,
+    segment : &mut Vec<u8>) -> io::Result<()> {
+    let nonce = nonce_for_segment(&header.nonce, index, last).ok_or_else(encryption_error)?;
+    let tag : [u8; TAG_SIZE] = seal(&header.parameters, key, &nonce, &header.authenticated_data(), segment)
+        .ok_or_else(encryption_error)?;
+    segment.extend_from_slice(&tag);
+    Ok(())
+}
+
+/// Opens a segment with its tag in place, leaving only the plaintext.
+pub(crate) fn open_segment(header : &Header, key : &Secret, index : usize, last : bool,
+    segment : &mut Vec<u8>) -> io::Result<()> {
+    let plaintext_length : usize = segment.len().checked_sub(TAG_SIZE).ok_or_else(authentication_error)?;
+    let nonce = nonce_for_segment(&header.nonce, index, last).ok_or_else(authentication_error)?;
+    let (plaintext, tag) = segment.split_at_mut(plaintext_length);
+    if !open(&header.parameters, key, &nonce, &header.authenticated_data(), plaintext, tag) {
+        return Err(authentication_error());
+    }
+    segment.truncate(plaintext_length);
+    Ok(())
+}
+
+fn encryption_error() -> io::Error {
+    io::Error::other(CryptoError::Encryption(IN_MEMORY.to_string()))
+}
+
+fn authentication_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, CryptoError::AuthenticationFailed(IN_MEMORY.to_string()))
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::crypto_parameters::block_cipher::BlockCipher;
+    use crate::crypto_parameters::kdf::Kdf;
+    use crate::crypto_parameters::key_encapsulation::KeyEncapsulation;
+    use crate::crypto_parameters::key_size::KeySize;
+    use crate::file_cipher::{decrypt_bytes, decrypt_file, encrypt_bytes, encrypt_file};
+    use std::fs;
+
+    fn raw_key() -> Credentials {
+        Credentials::from_raw_key(Secret::new(vec![0x42; 32]))
+    }
+
+    fn parameters(operation_mode : OperationMode) -> CryptoParameters {
+        CryptoParameters::new(BlockCipher::Aes, Kdf::None, KeySize::Size256, operation_mode, KeyEncapsulation::None)
+    }
+
+    fn plaintext(length : usize) -> Vec<u8> {
+        (0..length).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn encrypt_in_pieces(plaintext : &[u8], parameters : CryptoParameters) -> Vec<u8> {
+        let mut encryptor : Encryptor<Vec<u8>> = Encryptor::new(Vec::new(), &raw_key(), parameters).unwrap();
+        for piece in plaintext.chunks(100_000) {
+            encryptor.write_all(piece).unwrap();
+        }
+        encryptor.finish().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for operation_mode in [OperationMode::Gcm, OperationMode::ChunkedGcm] {
+            for length in [0, 1000, SEGMENT_SIZE, 2 * SEGMENT_SIZE + 1000] {
+                let plaintext : Vec<u8> = plaintext(length);
+                let ciphertext : Vec<u8> = encrypt_in_pieces(&plaintext, parameters(operation_mode));
+                assert_eq!(*decrypt_bytes(&ciphertext, &raw_key()).unwrap(), plaintext);
+
+                let ciphertext : Vec<u8> = encrypt_bytes(&plaintext, &raw_key(), &parameters(operation_mode)).unwrap();
+                let mut decrypted : Vec<u8> = Vec::new();
+                Decryptor::new(ciphertext.as_slice(), &raw_key()).unwrap().read_to_end(&mut decrypted).unwrap();
+                assert_eq!(decrypted, plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_container_as_files() {
+        let path : &str = "testfiles/stream.txt";
+        let ciphertext_path : String = path.to_string() + ".enc";
+        let plaintext : Vec<u8> = plaintext(SEGMENT_SIZE + 1000);
+
+        fs::write(&ciphertext_path, encrypt_in_pieces(&plaintext, parameters(OperationMode::ChunkedGcm))).unwrap();
+        decrypt_file(&ciphertext_path, &raw_key()).unwrap();
+        assert_eq!(fs::read(path).unwrap(), plaintext);
+        fs::remove_file(&ciphertext_path).unwrap();
+
+        encrypt_file(path, &raw_key(), parameters(OperationMode::ChunkedGcm)).unwrap();
+        let mut decrypted : Vec<u8> = Vec::new();
+        Decryptor::new(fs::File::open(&ciphertext_path).unwrap(), &raw_key()).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(&ciphertext_path).unwrap();
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let plaintext : Vec<u8> = plaintext(2 * SEGMENT_SIZE + 1000);
+        let ciphertext : Vec<u8> = encrypt_in_pieces(&plaintext, parameters(OperationMode::ChunkedGcm));
+        let mut decrypted : Vec<u8> = Vec::new();
+
+        let truncated : &[u8] = &ciphertext[0..ciphertext.len() - 1000 - TAG_SIZE];
+        let error : io::Error = Decryptor::new(truncated, &raw_key()).unwrap().read_to_end(&mut decrypted).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut flipped : Vec<u8> = ciphertext.clone();
+        let last : usize = flipped.len() - 1;
+        flipped[last] ^= 0x01;
+        let mut decryptor : Decryptor<&[u8]> = Decryptor::new(flipped.as_slice(), &raw_key()).unwrap();
+        let mut first_segment : Vec<u8> = vec![0x00; SEGMENT_SIZE];
+        decryptor.read_exact(&mut first_segment).unwrap();
+        assert_eq!(first_segment, plaintext[0..SEGMENT_SIZE]);
+        assert!(decryptor.read_to_end(&mut decrypted).is_err());
+
+        let wrong_key : Credentials = Credentials::from_raw_key(Secret::new(vec![0x23; 32]));
+        assert!(matches!(Decryptor::new(ciphertext.as_slice(), &wrong_key), Err(CryptoError::AuthenticationFailed(_))));
+    }
+}